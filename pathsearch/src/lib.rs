@@ -52,10 +52,11 @@ pub mod windows;
 /// generated from searching the supplied path string following the
 /// standard rules: explode path by the system path separator character
 /// and then for each entry, concatenate the candidate command and test
-/// whether that is a file.
+/// whether that is a file (or a directory, if `directories` was used).
 pub struct SimplePathSearcher<'a> {
     path_iter: std::env::SplitPaths<'a>,
     command: &'a OsStr,
+    directories: bool,
 }
 
 impl<'a> SimplePathSearcher<'a> {
@@ -65,7 +66,18 @@ impl<'a> SimplePathSearcher<'a> {
         let path = path.unwrap_or_else(|| OsStr::new(""));
         let path_iter = std::env::split_paths(path);
         let command = command.as_ref();
-        Self { path_iter, command }
+        Self {
+            path_iter,
+            command,
+            directories: false,
+        }
+    }
+
+    /// Yield matching directories rather than files, as is
+    /// needed to search CDPATH
+    pub fn directories(mut self) -> Self {
+        self.directories = true;
+        self
     }
 }
 
@@ -78,7 +90,12 @@ impl<'a> Iterator for SimplePathSearcher<'a> {
             let entry = self.path_iter.next()?;
             let candidate = entry.join(self.command);
 
-            if candidate.is_file() {
+            let matched = if self.directories {
+                candidate.is_dir()
+            } else {
+                candidate.is_file()
+            };
+            if matched {
                 return Some(candidate);
             }
         }
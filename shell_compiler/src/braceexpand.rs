@@ -0,0 +1,281 @@
+//! Brace expansion, as described in
+//! https://www.gnu.org/software/bash/manual/html_node/Brace-Expansion.html
+//! Brace expansion is a purely textual transformation that produces
+//! multiple words from a single input word.  It happens before any
+//! of the other expansions, so we perform it at compile time by
+//! re-arranging the word components produced by the lexer.
+use shell_lexer::{WordComponent, WordComponentKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    /// A single character from the literal component at index
+    /// `component` of the input word.  `quoted` is true if the
+    /// character was escaped by a backslash, or was generated by
+    /// a sequence expression, and thus cannot start, end or
+    /// separate a brace expression.
+    Char {
+        c: char,
+        component: usize,
+        quoted: bool,
+    },
+    /// A component that is not subject to brace expansion, such
+    /// as a quoted string or a parameter expansion.
+    Opaque(usize),
+}
+
+impl Piece {
+    fn is_unquoted(&self, wanted: char) -> bool {
+        match self {
+            Piece::Char {
+                c, quoted: false, ..
+            } => *c == wanted,
+            _ => false,
+        }
+    }
+}
+
+/// Apply brace expansion to word, returning the list of words that
+/// it expands to.  If word contains no brace expressions then the
+/// result is a list holding just a copy of word.
+/// Quoted braces are not subject to expansion, nor are braces that
+/// have no matching partner, or that contain neither a comma nor a
+/// valid sequence expression.
+pub fn brace_expand(word: &[WordComponent]) -> Vec<Vec<WordComponent>> {
+    let pieces = split_into_pieces(word);
+    if !pieces.iter().any(|p| p.is_unquoted('{')) {
+        return vec![word.to_vec()];
+    }
+    expand(&pieces)
+        .iter()
+        .map(|pieces| join_pieces(word, pieces))
+        .collect()
+}
+
+fn split_into_pieces(word: &[WordComponent]) -> Vec<Piece> {
+    let mut pieces = vec![];
+    for (component, comp) in word.iter().enumerate() {
+        match &comp.kind {
+            WordComponentKind::Literal(s) if comp.splittable => {
+                let mut quoted = false;
+                for c in s.chars() {
+                    pieces.push(Piece::Char {
+                        c,
+                        component,
+                        quoted,
+                    });
+                    quoted = !quoted && comp.remove_backslash && c == '\\';
+                }
+            }
+            _ => pieces.push(Piece::Opaque(component)),
+        }
+    }
+    pieces
+}
+
+/// Re-assemble pieces into a word, coalescing adjacent characters
+/// that originated from the same component back into a single literal.
+fn join_pieces(word: &[WordComponent], pieces: &[Piece]) -> Vec<WordComponent> {
+    let mut result: Vec<WordComponent> = vec![];
+    let mut current = None;
+    for piece in pieces {
+        match *piece {
+            Piece::Char { c, component, .. } => {
+                if current == Some(component) {
+                    if let Some(WordComponent {
+                        kind: WordComponentKind::Literal(s),
+                        ..
+                    }) = result.last_mut()
+                    {
+                        s.push(c);
+                        continue;
+                    }
+                }
+                let template = &word[component];
+                result.push(WordComponent {
+                    kind: WordComponentKind::Literal(c.to_string()),
+                    span: template.span,
+                    splittable: template.splittable,
+                    remove_backslash: template.remove_backslash,
+                });
+                current = Some(component);
+            }
+            Piece::Opaque(component) => {
+                result.push(word[component].clone());
+                current = None;
+            }
+        }
+    }
+    result
+}
+
+fn expand(pieces: &[Piece]) -> Vec<Vec<Piece>> {
+    let mut start = 0;
+    while let Some(open) = find_unquoted(pieces, start, '{') {
+        if let Some(close) = matching_brace(pieces, open) {
+            let inner = &pieces[open + 1..close];
+            let component = match pieces[open] {
+                Piece::Char { component, .. } => component,
+                Piece::Opaque(_) => unreachable!(),
+            };
+
+            if let Some(alternatives) =
+                sequence(inner, component).or_else(|| split_alternatives(inner))
+            {
+                let prefix = &pieces[..open];
+                let suffixes = expand(&pieces[close + 1..]);
+                let mut result = vec![];
+                for alternative in alternatives {
+                    for alternative in expand(&alternative) {
+                        for suffix in &suffixes {
+                            let mut expanded = prefix.to_vec();
+                            expanded.extend_from_slice(&alternative);
+                            expanded.extend_from_slice(suffix);
+                            result.push(expanded);
+                        }
+                    }
+                }
+                return result;
+            }
+        }
+        // Not a valid brace expression; treat the opening brace
+        // as a literal and look for a nested expression instead
+        start = open + 1;
+    }
+    vec![pieces.to_vec()]
+}
+
+fn find_unquoted(pieces: &[Piece], start: usize, wanted: char) -> Option<usize> {
+    pieces[start..]
+        .iter()
+        .position(|p| p.is_unquoted(wanted))
+        .map(|idx| idx + start)
+}
+
+fn matching_brace(pieces: &[Piece], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, piece) in pieces.iter().enumerate().skip(open) {
+        if piece.is_unquoted('{') {
+            depth += 1;
+        } else if piece.is_unquoted('}') {
+            depth -= 1;
+            if depth == 0 {
+                return Some(idx);
+            }
+        }
+    }
+    None
+}
+
+/// Split the content of a brace expression at its top level commas.
+/// Returns None if there are no such commas.
+fn split_alternatives(inner: &[Piece]) -> Option<Vec<Vec<Piece>>> {
+    let mut alternatives = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, piece) in inner.iter().enumerate() {
+        if piece.is_unquoted('{') {
+            depth += 1;
+        } else if piece.is_unquoted('}') {
+            depth -= 1;
+        } else if depth == 0 && piece.is_unquoted(',') {
+            alternatives.push(inner[start..idx].to_vec());
+            start = idx + 1;
+        }
+    }
+    if alternatives.is_empty() {
+        return None;
+    }
+    alternatives.push(inner[start..].to_vec());
+    Some(alternatives)
+}
+
+/// Parse a sequence expression of the form `x..y` or `x..y..incr`
+/// where x and y are either both integers or both single letters.
+/// Returns the generated sequence if inner is a valid expression.
+fn sequence(inner: &[Piece], component: usize) -> Option<Vec<Vec<Piece>>> {
+    let mut text = String::new();
+    for piece in inner {
+        match piece {
+            Piece::Char {
+                c, quoted: false, ..
+            } => text.push(*c),
+            _ => return None,
+        }
+    }
+
+    let parts: Vec<&str> = text.split("..").collect();
+    let (first, last, incr) = match parts.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, incr] => (
+            *first,
+            *last,
+            incr.parse::<i64>().ok()?.checked_abs()?.max(1),
+        ),
+        _ => return None,
+    };
+
+    let values: Vec<String> = if let (Ok(a), Ok(b)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let width = if is_zero_padded(first) || is_zero_padded(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        range(a, b, incr)?
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect()
+    } else if let (Some(a), Some(b)) = (single_letter(first), single_letter(last)) {
+        range(a as i64, b as i64, incr)?
+            .filter_map(|n| std::char::from_u32(n as u32))
+            .map(|c| c.to_string())
+            .collect()
+    } else {
+        return None;
+    };
+
+    Some(
+        values
+            .into_iter()
+            .map(|s| {
+                s.chars()
+                    .map(|c| Piece::Char {
+                        c,
+                        component,
+                        quoted: true,
+                    })
+                    .collect()
+            })
+            .collect(),
+    )
+}
+
+/// The most words that a single sequence expression may generate.
+/// Expansion happens at compile time, so a larger sequence is left
+/// unexpanded rather than exhausting memory.
+const MAX_SEQUENCE_LENGTH: u64 = 65536;
+
+/// Returns the values from first towards last in steps of incr, which
+/// must be positive, or None if the distance between them overflows
+/// or there would be more than MAX_SEQUENCE_LENGTH of them.
+fn range(first: i64, last: i64, incr: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = first.checked_sub(last)?.unsigned_abs() / incr.unsigned_abs();
+    if count >= MAX_SEQUENCE_LENGTH {
+        return None;
+    }
+    let incr = if first <= last { incr } else { -incr };
+    // Every value lies between first and last, but the offset from
+    // first to the final value may not fit in an i64 by itself
+    Some((0..=count).map(move |i| (i128::from(first) + i128::from(i) * i128::from(incr)) as i64))
+}
+
+fn is_zero_padded(s: &str) -> bool {
+    let digits = s.trim_start_matches('-');
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+fn single_letter(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
@@ -1,13 +1,18 @@
 #![allow(dead_code, unused_imports)]
 use anyhow::{anyhow, bail};
-use shell_lexer::{Assignment, ParamExpr, ParamOper, WordComponent, WordComponentKind};
-use shell_parser::{Command, CommandType, CompoundList, Redirection};
+use shell_lexer::{
+    Assignment, ParamExpr, ParamOper, ParamSubscript, Span, Token, WordComponent, WordComponentKind,
+};
+use shell_parser::{Command, CommandType, CompoundList, Parser, Redirection, SimpleCommand};
 pub use shell_vm::*;
 use std::cell::Cell;
 use std::collections::VecDeque;
+use std::sync::Arc;
 use std::thread::JoinHandle;
 
+mod braceexpand;
 mod registeralloc;
+use braceexpand::brace_expand;
 use registeralloc::RegisterAllocator;
 
 #[derive(Default, Debug)]
@@ -32,7 +37,22 @@ impl std::ops::DerefMut for FrameCompiler {
 #[derive(Default, Debug)]
 pub struct Compiler {
     program: Vec<Operation>,
+    /// The source span of each of the opcodes in program
+    spans: Vec<Option<Span>>,
+    /// The span of the source that is currently being compiled
+    span: Option<Span>,
+    /// The addresses at which simple commands and function
+    /// definitions begin
+    commands: Vec<usize>,
     frames: VecDeque<FrameCompiler>,
+    source_name: Option<String>,
+    /// Non-zero while compiling commands whose status is tested,
+    /// such as an `if` condition, and that therefore must not
+    /// trigger the errexit option
+    condition_depth: usize,
+    /// Set while compiling a pipeline stage that is to be spawned
+    /// concurrently; its status is collected by WaitConcurrent
+    concurrent_stage: bool,
 }
 
 impl Compiler {
@@ -40,11 +60,26 @@ impl Compiler {
         Default::default()
     }
 
-    pub fn finish(mut self) -> anyhow::Result<Vec<Operation>> {
+    /// Set the name of the file being compiled; it is recorded
+    /// alongside the functions that it defines
+    pub fn set_source_name(&mut self, name: &str) {
+        self.source_name = Some(name.to_owned());
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<Arc<Program>> {
         self.push(op::Exit {
             value: Operand::LastWaitStatus,
         });
-        Ok(self.program)
+        Program::with_spans(self.program, self.spans, self.commands, self.source_name)
+    }
+
+    /// Like finish(), but for the body of a function, which returns
+    /// to its caller rather than terminating the program
+    pub fn finish_function(mut self) -> anyhow::Result<Arc<Program>> {
+        self.push(op::Return {
+            value: Operand::LastWaitStatus,
+        });
+        Program::with_spans(self.program, self.spans, self.commands, self.source_name)
     }
 
     /// Emit a half-baked PushFrame instruction and set up a new
@@ -99,6 +134,19 @@ impl Compiler {
 
     fn push<OP: Into<Operation>>(&mut self, op: OP) {
         self.program.push(op.into());
+        self.spans.push(self.span);
+    }
+
+    /// Attribute the opcodes that func emits to the source in span
+    fn spanned<F: FnOnce(&mut Self) -> anyhow::Result<()>>(
+        &mut self,
+        span: Span,
+        func: F,
+    ) -> anyhow::Result<()> {
+        let outer = self.span.replace(span);
+        let result = func(self);
+        self.span = outer;
+        result
     }
 
     /// Allocate a new empty string and return the frame relative
@@ -166,17 +214,84 @@ impl Compiler {
         Ok(())
     }
 
+    /// Expand word into a single string, as is done for the value
+    /// of an assignment or the subscript of an array
+    fn expand_string(
+        &mut self,
+        target_string: usize,
+        word: &[WordComponent],
+    ) -> anyhow::Result<()> {
+        let list = self.allocate_list()?;
+        self.expand_single_word(list, word)?;
+        self.push(op::JoinList {
+            list: Operand::FrameRelative(list),
+            destination: Operand::FrameRelative(target_string),
+        });
+        self.frame()?.free(list);
+        Ok(())
+    }
+
+    /// Load the value of the parameter named by expr into slot.
+    /// `${NAME[@]}` and `${NAME[*]}` are joined into a single string.
+    fn load_parameter(&mut self, slot: usize, expr: &ParamExpr) -> anyhow::Result<()> {
+        let name = Operand::Immediate(expr.name.as_str().into());
+        match &expr.subscript {
+            None => self.push(op::GetEnv {
+                name,
+                target: Operand::FrameRelative(slot),
+            }),
+            Some(ParamSubscript::Word(word)) => {
+                let subscript = self.allocate_string()?;
+                self.expand_string(subscript, word)?;
+                self.push(op::GetArrayElement {
+                    name,
+                    subscript: Operand::FrameRelative(subscript),
+                    target: Operand::FrameRelative(slot),
+                });
+                self.frame()?.free(subscript);
+            }
+            Some(ParamSubscript::At) | Some(ParamSubscript::Star) => {
+                self.push(op::GetArrayValues {
+                    name,
+                    target: Operand::FrameRelative(slot),
+                });
+                self.push(op::JoinList {
+                    list: Operand::FrameRelative(slot),
+                    destination: Operand::FrameRelative(slot),
+                });
+            }
+        }
+        Ok(())
+    }
+
     fn parameter_expand(&mut self, target_string: usize, expr: &ParamExpr) -> anyhow::Result<()> {
         let slot = self.frame()?.allocate();
-        self.push(op::GetEnv {
-            name: Operand::Immediate(expr.name.as_str().into()),
-            target: Operand::FrameRelative(slot),
-        });
+        let all_elements = matches!(
+            expr.subscript,
+            Some(ParamSubscript::At) | Some(ParamSubscript::Star)
+        );
+        match expr.kind {
+            // The number of elements rather than the length of the
+            // joined string
+            ParamOper::StringLength if all_elements => {
+                self.push(op::GetArrayValues {
+                    name: Operand::Immediate(expr.name.as_str().into()),
+                    target: Operand::FrameRelative(slot),
+                });
+            }
+            ParamOper::Keys => {
+                self.push(op::GetArrayKeys {
+                    name: Operand::Immediate(expr.name.as_str().into()),
+                    target: Operand::FrameRelative(slot),
+                });
+                self.push(op::JoinList {
+                    list: Operand::FrameRelative(slot),
+                    destination: Operand::FrameRelative(slot),
+                });
+            }
+            _ => self.load_parameter(slot, expr)?,
+        }
         match expr.kind {
-            ParamOper::Get => self.push(op::Copy {
-                source: Operand::FrameRelative(slot),
-                destination: Operand::FrameRelative(target_string),
-            }),
             ParamOper::GetDefault { allow_null } => {
                 let test = self.frame()?.allocate();
                 if allow_null {
@@ -240,10 +355,7 @@ impl Compiler {
                             destination: Operand::FrameRelative(target_string),
                         });
                         me.frame()?.free(argv);
-                        me.push(op::SetEnv {
-                            name: Operand::Immediate(expr.name.as_str().into()),
-                            value: Operand::FrameRelative(target_string),
-                        });
+                        me.assign(&expr.name, expr.subscript.as_ref(), target_string)?;
 
                         Ok(())
                     },
@@ -257,6 +369,10 @@ impl Compiler {
                 )?;
                 self.frame()?.free(test);
             }
+            ParamOper::Get | ParamOper::Keys => self.push(op::Copy {
+                source: Operand::FrameRelative(slot),
+                destination: Operand::FrameRelative(target_string),
+            }),
             ParamOper::StringLength => self.push(op::StringLength {
                 string: Operand::FrameRelative(slot),
                 length: Operand::FrameRelative(target_string),
@@ -355,19 +471,97 @@ impl Compiler {
         Ok(())
     }
 
+    /// Compile `<(command)` (when readable is true) or `>(command)`.
+    /// The command is spawned asynchronously with one end of a pipe
+    /// as its stdout or stdin respectively.  The other end of the
+    /// pipe is assigned to a spare descriptor in the current IO
+    /// environment and the `/dev/fd/N` path that refers to it is
+    /// stored into the path slot.
+    fn process_substitution(
+        &mut self,
+        tokens: &[Token],
+        readable: bool,
+        path: usize,
+    ) -> anyhow::Result<()> {
+        let mut command = Parser::from_tokens(tokens.to_vec()).parse()?;
+        mark_asynchronous(&mut command);
+
+        self.push(op::PushIo {});
+        if readable {
+            self.push(op::PushPipe {});
+        } else {
+            self.push(op::PushStdinPipe {});
+        }
+        self.compile_command(&command)?;
+        self.push(op::PopIo {});
+        self.push(op::PopPipeFd {
+            path: Operand::FrameRelative(path),
+        });
+        Ok(())
+    }
+
     /// Perform word expansion on word.
+    /// Brace expansion is applied first and may produce several
+    /// words from the input; each of those is then expanded by
+    /// expand_single_word and appended to argv.
+    fn word_expand(&mut self, argv: usize, word: &[WordComponent]) -> anyhow::Result<()> {
+        let span = match (word.first(), word.last()) {
+            (Some(first), Some(last)) => Span::new(first.span.start, last.span.end),
+            _ => self.span.unwrap_or_default(),
+        };
+        self.spanned(span, |me| {
+            for word in brace_expand(word) {
+                me.expand_single_word(argv, &word)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Perform word expansion on word, without brace expansion.
     /// Word is a list of components that are logically all part of the
     /// same field and thus are emitted into a string value together.
     /// However, some elements may be splittable which means that they
     /// are subject to field splitting based on the runtime value of
     /// the IFS variable.
-    fn word_expand(&mut self, argv: usize, word: &Vec<WordComponent>) -> anyhow::Result<()> {
+    fn expand_single_word(&mut self, argv: usize, word: &[WordComponent]) -> anyhow::Result<()> {
         // Hideous "special parameters" special casing
         if word.len() == 1 {
+            if let WordComponentKind::ParamExpand(ParamExpr {
+                name,
+                word,
+                kind,
+                subscript: Some(ParamSubscript::At),
+            }) = &word[0].kind
+            {
+                // Each element, or each key, of the array becomes
+                // a separate field
+                let keys = match kind {
+                    ParamOper::Get if word.is_empty() => Some(false),
+                    ParamOper::Keys => Some(true),
+                    _ => None,
+                };
+                if let Some(keys) = keys {
+                    let list = self.allocate_string()?;
+                    let name = Operand::Immediate(name.as_str().into());
+                    let target = Operand::FrameRelative(list);
+                    if keys {
+                        self.push(op::GetArrayKeys { name, target });
+                    } else {
+                        self.push(op::GetArrayValues { name, target });
+                    }
+                    self.push(op::ListAppendList {
+                        src_list: Operand::FrameRelative(list),
+                        dest_list: Operand::FrameRelative(argv),
+                    });
+                    self.frame()?.free(list);
+                    return Ok(());
+                }
+            }
             if let WordComponentKind::ParamExpand(ParamExpr {
                 name,
                 word,
                 kind: ParamOper::Get,
+                subscript: None,
             }) = &word[0].kind
             {
                 if word.is_empty() && (name == "@" || name == "*") {
@@ -441,6 +635,20 @@ impl Compiler {
                     //   function.
                     bail!("command subst not implemented");
                 }
+                WordComponentKind::ReadProcessSubstitution(tokens)
+                | WordComponentKind::WriteProcessSubstitution(tokens) => {
+                    let readable = matches!(
+                        component.kind,
+                        WordComponentKind::ReadProcessSubstitution(_)
+                    );
+                    let path = self.allocate_string()?;
+                    self.process_substitution(tokens, readable, path)?;
+                    self.push(op::StringAppend {
+                        source: Operand::FrameRelative(path),
+                        destination: Operand::FrameRelative(expanded_word),
+                    });
+                    self.frame()?.free(path);
+                }
             }
         }
 
@@ -458,37 +666,121 @@ impl Compiler {
         Ok(())
     }
 
-    fn apply_redirection(&mut self, redir: &Vec<Redirection>) -> anyhow::Result<bool> {
+    fn apply_redirection(&mut self, redir: &[Redirection]) -> anyhow::Result<bool> {
         if redir.is_empty() {
             return Ok(false);
         }
         self.push(op::PushIo {});
+        self.redirect(redir)?;
+        Ok(true)
+    }
 
+    /// Apply redirections to the current IO environment
+    fn redirect(&mut self, redir: &[Redirection]) -> anyhow::Result<()> {
         for r in redir {
-            match r {
-                Redirection::File(f) => {
-                    let filename = self.allocate_list()?;
-                    self.word_expand(filename, &f.file_name)?;
-                    self.push(op::OpenFile {
-                        name: Operand::FrameRelative(filename),
-                        fd_number: f.fd_number,
-                        input: f.input,
-                        output: f.output,
-                        clobber: f.clobber,
-                        append: f.append,
+            self.spanned(r.span(), |me| me.redirect_one(r))?;
+        }
+        Ok(())
+    }
+
+    fn redirect_one(&mut self, r: &Redirection) -> anyhow::Result<()> {
+        match r {
+            Redirection::File(f) => {
+                let filename = self.allocate_list()?;
+                self.word_expand(filename, &f.file_name)?;
+                self.push(op::OpenFile {
+                    name: Operand::FrameRelative(filename),
+                    fd_number: f.fd_number,
+                    input: f.input,
+                    output: f.output,
+                    clobber: f.clobber,
+                    append: f.append,
+                });
+                self.frame()?.free(filename);
+            }
+            Redirection::Fd(f) => {
+                self.push(op::DupFd {
+                    src_fd: f.src_fd_number,
+                    dest_fd: f.dest_fd_number,
+                });
+            }
+            Redirection::Move(f) => {
+                self.push(op::MoveFd {
+                    src_fd: f.src_fd_number,
+                    dest_fd: f.dest_fd_number,
+                });
+            }
+            Redirection::Close(f) => {
+                self.push(op::CloseFd {
+                    fd_number: f.fd_number,
+                });
+            }
+            Redirection::FdWord(f) => {
+                let src_fd = self.allocate_list()?;
+                self.expand_single_word(src_fd, &f.src_word)?;
+                self.push(op::JoinList {
+                    list: Operand::FrameRelative(src_fd),
+                    destination: Operand::FrameRelative(src_fd),
+                });
+                self.push(op::DupFdFrom {
+                    src_fd: Operand::FrameRelative(src_fd),
+                    dest_fd: f.dest_fd_number,
+                });
+                self.frame()?.free(src_fd);
+            }
+            Redirection::NamedFd(n) => {
+                let fd_number = self.allocate_string()?;
+                if let Redirection::Close(_) = *n.redirection {
+                    self.push(op::GetEnv {
+                        name: Operand::Immediate(n.name.as_str().into()),
+                        target: Operand::FrameRelative(fd_number),
                     });
-                    self.frame()?.free(filename);
-                }
-                Redirection::Fd(f) => {
-                    self.push(op::DupFd {
-                        src_fd: f.src_fd_number,
-                        dest_fd: f.dest_fd_number,
+                    self.push(op::CloseFdFrom {
+                        fd_number: Operand::FrameRelative(fd_number),
+                    });
+                } else {
+                    // Set up the descriptor in the staging slot,
+                    // then move it to its final number
+                    let mut staged = (*n.redirection).clone();
+                    set_redirection_fd_number(&mut staged, STAGING_FD);
+                    self.redirect(&[staged])?;
+                    self.push(op::AllocateFd {
+                        src_fd: STAGING_FD,
+                        fd_number: Operand::FrameRelative(fd_number),
+                    });
+                    self.push(op::SetEnv {
+                        name: Operand::Immediate(n.name.as_str().into()),
+                        value: Operand::FrameRelative(fd_number),
                     });
                 }
+                self.frame()?.free(fd_number);
+            }
+            Redirection::HereString(h) => {
+                // Like an assignment, the word is expanded to a
+                // single string; a newline is appended to it
+                let value = self.allocate_list()?;
+                self.expand_single_word(value, &h.word)?;
+                self.push(op::JoinList {
+                    list: Operand::FrameRelative(value),
+                    destination: Operand::FrameRelative(value),
+                });
+                self.push(op::StringAppend {
+                    source: Operand::Immediate("\n".into()),
+                    destination: Operand::FrameRelative(value),
+                });
+                self.push(op::PushIo {});
+                self.push(op::PushPipe {});
+                self.push(op::WriteOutput {
+                    value: Operand::FrameRelative(value),
+                });
+                self.push(op::PopIo {});
+                self.push(op::PopPipe {
+                    fd_number: h.fd_number,
+                });
+                self.frame()?.free(value);
             }
         }
-
-        Ok(true)
+        Ok(())
     }
 
     fn pop_redirection(&mut self, do_pop: bool) {
@@ -497,33 +789,238 @@ impl Compiler {
         }
     }
 
+    /// Assign the value in slot to the parameter name, or to
+    /// an element of it when a subscript is present
+    fn assign(
+        &mut self,
+        name: &str,
+        subscript: Option<&ParamSubscript>,
+        value: usize,
+    ) -> anyhow::Result<()> {
+        match subscript {
+            None => self.push(op::SetEnv {
+                name: Operand::Immediate(name.into()),
+                value: Operand::FrameRelative(value),
+            }),
+            Some(ParamSubscript::Word(word)) => self.assign_element(name, word, value)?,
+            Some(_) => bail!("{}: cannot assign to all elements of an array", name),
+        }
+        Ok(())
+    }
+
+    fn assign_element(
+        &mut self,
+        name: &str,
+        subscript: &[WordComponent],
+        value: usize,
+    ) -> anyhow::Result<()> {
+        let expanded = self.allocate_string()?;
+        self.expand_string(expanded, subscript)?;
+        self.push(op::SetArrayElement {
+            name: Operand::Immediate(name.into()),
+            subscript: Operand::FrameRelative(expanded),
+            value: Operand::FrameRelative(value),
+        });
+        self.frame()?.free(expanded);
+        Ok(())
+    }
+
+    /// Compile `name=(word [subscript]=word ...)`.
+    fn assign_array(&mut self, name: &str, elements: &[Vec<WordComponent>]) -> anyhow::Result<()> {
+        let list = self.array_elements(elements)?;
+        self.push(op::SetArray {
+            name: Operand::Immediate(name.into()),
+            elements: Operand::FrameRelative(list),
+        });
+        self.frame()?.free(list);
+        Ok(())
+    }
+
+    /// Build the list of elements for `name=(word [subscript]=word ...)`
+    /// in the format expected by SetArray, returning its frame slot.
+    /// Words without a subscript are subject to the full word
+    /// expansion, and so may produce several elements.
+    fn array_elements(&mut self, elements: &[Vec<WordComponent>]) -> anyhow::Result<usize> {
+        let list = self.allocate_list()?;
+        for element in elements {
+            match split_subscripted_element(element) {
+                Some((subscript, value)) => {
+                    let pair = self.allocate_list()?;
+                    for word in &[subscript, value] {
+                        let expanded = self.allocate_string()?;
+                        self.expand_string(expanded, word)?;
+                        self.push(op::ListAppend {
+                            value: Operand::FrameRelative(expanded),
+                            list: Operand::FrameRelative(pair),
+                            split: false,
+                            glob: false,
+                            remove_backslash: false,
+                        });
+                        self.frame()?.free(expanded);
+                    }
+                    self.push(op::ListAppend {
+                        value: Operand::FrameRelative(pair),
+                        list: Operand::FrameRelative(list),
+                        split: false,
+                        glob: false,
+                        remove_backslash: false,
+                    });
+                    self.frame()?.free(pair);
+                }
+                None => self.word_expand(list, element)?,
+            }
+        }
+        Ok(list)
+    }
+
+    /// Expand the arguments of a simple command into argv.  The
+    /// compound array assignments among the arguments of `declare`
+    /// and similar builtins are passed to them as a list holding the
+    /// name and the elements, so that they can set attributes such
+    /// as -A before assigning the array.
+    fn command_arguments(&mut self, argv: usize, simple: &SimpleCommand) -> anyhow::Result<()> {
+        let declaration = is_declaration_command(simple);
+        for (idx, word) in simple.words.iter().enumerate() {
+            let array = simple
+                .array_arguments
+                .iter()
+                .find(|(arg, _)| *arg == idx)
+                .map(|(_, assignment)| assignment);
+            match array {
+                Some(assignment) if declaration => {
+                    if assignment.subscript.is_some() {
+                        bail!(
+                            "{}: cannot assign a list to an array element",
+                            assignment.name
+                        );
+                    }
+                    let pair = self.allocate_list()?;
+                    self.push(op::ListAppend {
+                        value: Operand::Immediate(assignment.name.as_str().into()),
+                        list: Operand::FrameRelative(pair),
+                        split: false,
+                        glob: false,
+                        remove_backslash: false,
+                    });
+                    let elements =
+                        self.array_elements(assignment.elements.as_deref().unwrap_or(&[]))?;
+                    self.push(op::ListAppend {
+                        value: Operand::FrameRelative(elements),
+                        list: Operand::FrameRelative(pair),
+                        split: false,
+                        glob: false,
+                        remove_backslash: false,
+                    });
+                    self.frame()?.free(elements);
+                    self.push(op::ListAppend {
+                        value: Operand::FrameRelative(pair),
+                        list: Operand::FrameRelative(argv),
+                        split: false,
+                        glob: false,
+                        remove_backslash: false,
+                    });
+                    self.frame()?.free(pair);
+                }
+                _ => self.word_expand(argv, word)?,
+            }
+        }
+        Ok(())
+    }
+
     fn process_assignments(&mut self, assignments: &Vec<Assignment>) -> anyhow::Result<()> {
         for a in assignments {
-            let value = self.allocate_list()?;
-            self.word_expand(value, &a.value)?;
-            self.push(op::JoinList {
-                list: Operand::FrameRelative(value),
-                destination: Operand::FrameRelative(value),
-            });
-            self.push(op::SetEnv {
-                name: Operand::Immediate(a.name.as_str().into()),
-                value: Operand::FrameRelative(value),
-            });
+            if let Some(elements) = &a.elements {
+                if a.subscript.is_some() {
+                    bail!("{}: cannot assign a list to an array element", a.name);
+                }
+                self.assign_array(&a.name, elements)?;
+                continue;
+            }
+
+            let value = self.allocate_string()?;
+            // Assignment values are not subject to brace expansion
+            self.expand_string(value, &a.value)?;
+            match &a.subscript {
+                Some(subscript) => self.assign_element(&a.name, subscript, value)?,
+                None => self.assign(&a.name, None, value)?,
+            }
 
             self.frame()?.free(value);
         }
         Ok(())
     }
 
+    /// Compile commands that don't check errexit themselves, such as
+    /// the stages of a pipeline, whose status is checked as a whole
+    fn condition<F: FnOnce(&mut Self) -> anyhow::Result<()>>(
+        &mut self,
+        func: F,
+    ) -> anyhow::Result<()> {
+        self.condition_depth += 1;
+        let result = func(self);
+        self.condition_depth -= 1;
+        result
+    }
+
+    /// Compile commands whose status is tested, and which therefore
+    /// are exempt from the errexit option.  The exemption is tracked
+    /// at runtime so that it extends to the functions that they call.
+    fn tested_condition<F: FnOnce(&mut Self) -> anyhow::Result<()>>(
+        &mut self,
+        func: F,
+    ) -> anyhow::Result<()> {
+        self.push(op::EnterCondition {});
+        self.condition(func)?;
+        self.push(op::LeaveCondition {});
+        Ok(())
+    }
+
     pub fn compile_command(&mut self, command: &Command) -> anyhow::Result<()> {
+        self.spanned(command.span, |me| me.compile_command_inner(command))
+    }
+
+    fn compile_command_inner(&mut self, command: &Command) -> anyhow::Result<()> {
+        if let CommandType::SimpleCommand(_) | CommandType::FunctionDefinition { .. } =
+            &command.command
+        {
+            self.commands.push(self.program.len());
+        }
         self.reserve_frame();
+        let concurrent = std::mem::replace(&mut self.concurrent_stage, false);
         let pop_outer_redir = self.apply_redirection(&command.redirects)?;
+        let mut check_errexit = false;
 
         match &command.command {
+            CommandType::SimpleCommand(simple) if is_bare_exec(simple) => {
+                // `exec` without a command applies its redirections to
+                // the current IO environment, where they persist.
+                self.redirect(&simple.redirects)?;
+                // Spawning an empty argv succeeds, as it does for a
+                // command that consists only of assignments
+                let argv = self.allocate_list()?;
+                let status = self.frame()?.allocate();
+                self.push(op::SpawnCommand {
+                    argv: Operand::FrameRelative(argv),
+                    status: Operand::FrameRelative(status),
+                    concurrent: false,
+                });
+                self.push(op::Wait {
+                    status: Operand::FrameRelative(status),
+                });
+                self.frame()?.free(status);
+                self.frame()?.free(argv);
+            }
             CommandType::SimpleCommand(simple) => {
                 // Goal: build up an argument list and then invoke it
                 let argv = self.allocate_list()?;
-                let pop_redir = self.apply_redirection(&simple.redirects)?;
+                let mut pop_redir = self.apply_redirection(&simple.redirects)?;
+                if !pop_redir && simple.words.iter().any(|w| has_process_substitution(w)) {
+                    // Process substitution assigns descriptors in the
+                    // current IO environment; those must not outlive
+                    // this command, so give it an environment of its own.
+                    self.push(op::PushIo {});
+                    pop_redir = true;
+                }
                 let pop_env = if !simple.words.is_empty() && !simple.assignments.is_empty() {
                     // Assignments are applicable only to the command we're
                     // setting up here, so push a new context.
@@ -536,20 +1033,29 @@ impl Compiler {
                 };
 
                 self.process_assignments(&simple.assignments)?;
-
-                for word in &simple.words {
-                    self.word_expand(argv, word)?;
+                if pop_env {
+                    // Assignments that prefix a command are placed
+                    // into the environment of that command
+                    for a in &simple.assignments {
+                        self.push(op::ExportEnv {
+                            name: Operand::Immediate(a.name.as_str().into()),
+                        });
+                    }
                 }
 
+                self.command_arguments(argv, simple)?;
+
                 let status = self.frame()?.allocate();
                 self.push(op::SpawnCommand {
                     argv: Operand::FrameRelative(argv),
                     status: Operand::FrameRelative(status),
+                    concurrent,
                 });
-                if !command.asynchronous {
+                if !command.asynchronous && !concurrent {
                     self.push(op::Wait {
                         status: Operand::FrameRelative(status),
                     });
+                    check_errexit = true;
                 }
                 self.frame()?.free(status);
 
@@ -560,7 +1066,7 @@ impl Compiler {
             }
             CommandType::If(cmd) => {
                 // First evaluate the condition
-                self.compound_list(&cmd.condition)?;
+                self.tested_condition(|me| me.compound_list(&cmd.condition))?;
                 self.if_then_else(
                     Operand::LastWaitStatus,
                     |me| {
@@ -582,41 +1088,113 @@ impl Compiler {
             }
             CommandType::Pipeline(pipeline) => {
                 let num_commands = pipeline.commands.len();
-                if num_commands <= 1 {
-                    // Nothing to pipe together, so just emit the command
-                    for cmd in &pipeline.commands {
-                        self.compile_command(&cmd)?;
-                    }
-                } else {
-                    for (i, cmd) in pipeline.commands.iter().enumerate() {
-                        self.push(op::PushIo {});
-                        let first = i == 0;
-                        if !first {
-                            // Connect the read pipe from the prior iteration
-                            self.push(op::PopPipe {});
+                if pipeline.timed {
+                    self.push(op::PushTimer {});
+                }
+                // Only the status of the pipeline as a whole is
+                // subject to errexit, and not when it is inverted
+                let stages = |me: &mut Self| {
+                    if num_commands <= 1 {
+                        // Nothing to pipe together, so just emit the command
+                        for cmd in &pipeline.commands {
+                            me.compile_command(&cmd)?;
+                        }
+                    } else {
+                        // Simple commands are spawned concurrently and
+                        // waited for together once all of the stages
+                        // are running.  Compound commands are compiled
+                        // into programs of their own and run concurrently
+                        // too, except in the final stage, which runs
+                        // inline.  Nothing waits for a pipeline that runs
+                        // in the background.
+                        let waited = !command.asynchronous;
+                        let mut num_concurrent = 0;
+                        for (i, cmd) in pipeline.commands.iter().enumerate() {
+                            me.push(op::PushIo {});
+                            let first = i == 0;
+                            if !first {
+                                // Connect the read pipe from the prior iteration
+                                me.push(op::PopPipe { fd_number: 0 });
+                            }
+                            let last = i == num_commands - 1;
+                            if !last {
+                                // Set up the write pipe for the next iteration
+                                me.push(op::PushPipe {});
+                            }
+                            if waited && !last && is_compound_stage(cmd) {
+                                let mut compiler = Self::new();
+                                compiler.source_name = me.source_name.clone();
+                                compiler.compile_command(cmd)?;
+                                me.push(op::SpawnConcurrentProgram {
+                                    program: compiler.finish()?,
+                                });
+                                num_concurrent += 1;
+                            } else {
+                                if waited && is_concurrent_stage(cmd) {
+                                    me.concurrent_stage = true;
+                                    num_concurrent += 1;
+                                }
+                                me.compile_command(cmd)?;
+                            }
+                            me.push(op::PopIo {});
                         }
-                        let last = i == num_commands - 1;
-                        if !last {
-                            // Set up the write pipe for the next iteration
-                            self.push(op::PushPipe {});
+
+                        if num_concurrent > 0 {
+                            let last = pipeline.commands.last().unwrap();
+                            if is_concurrent_stage(last) {
+                                me.push(op::WaitConcurrent {
+                                    count: num_concurrent,
+                                });
+                            } else {
+                                // The status of the pipeline is that of
+                                // the inline final stage
+                                let status = me.frame()?.allocate();
+                                me.push(op::Copy {
+                                    source: Operand::LastWaitStatus,
+                                    destination: Operand::FrameRelative(status),
+                                });
+                                me.push(op::WaitConcurrent {
+                                    count: num_concurrent,
+                                });
+                                me.push(op::Wait {
+                                    status: Operand::FrameRelative(status),
+                                });
+                                me.frame()?.free(status);
+                            }
                         }
-                        self.compile_command(cmd)?;
-                        self.push(op::PopIo {});
                     }
+                    Ok(())
+                };
+                if pipeline.inverted {
+                    self.tested_condition(stages)?;
+                } else {
+                    self.condition(stages)?;
                 }
 
+                if pipeline.timed {
+                    self.push(op::PopTimer {
+                        posix_format: pipeline.posix_time_format,
+                    });
+                }
                 if pipeline.inverted {
                     self.push(op::InvertLastWait {});
+                } else {
+                    check_errexit = !command.asynchronous;
                 }
             }
 
-            CommandType::FunctionDefinition { name, body } => {
+            CommandType::FunctionDefinition { name, body, span } => {
                 let mut compiler = Self::new();
+                compiler.source_name = self.source_name.clone();
                 compiler.compile_command(&*body)?;
-                let program = Program::new(compiler.finish()?);
+                let program = compiler.finish_function()?;
                 self.push(op::DefineFunction {
                     name: name.to_string(),
                     program,
+                    location: SourceLocation {
+                        file: self.source_name.clone(),
+                        line: span.start.line,
+                    },
                 });
             }
 
@@ -624,6 +1202,9 @@ impl Compiler {
         };
 
         self.pop_redirection(pop_outer_redir);
+        if check_errexit && self.condition_depth == 0 {
+            self.push(op::ErrExit {});
+        }
         self.commit_frame()?;
         Ok(())
     }
@@ -636,6 +1217,122 @@ impl Compiler {
     }
 }
 
+/// Change the descriptor number that redir applies to
+fn set_redirection_fd_number(redir: &mut Redirection, fd_number: usize) {
+    match redir {
+        Redirection::File(f) => f.fd_number = fd_number,
+        Redirection::Fd(f) | Redirection::Move(f) => f.dest_fd_number = fd_number,
+        Redirection::Close(f) => f.fd_number = fd_number,
+        Redirection::FdWord(f) => f.dest_fd_number = fd_number,
+        Redirection::HereString(h) => h.fd_number = fd_number,
+        Redirection::NamedFd(_) => {}
+    }
+}
+
+/// Returns true if command is a pipeline stage that is spawned
+/// concurrently with the other stages
+fn is_concurrent_stage(command: &Command) -> bool {
+    match &command.command {
+        CommandType::SimpleCommand(simple) => !is_bare_exec(simple),
+        _ => false,
+    }
+}
+
+/// Returns true if command is a pipeline stage that is a compound
+/// command, such as a brace group, rather than a simple command
+fn is_compound_stage(command: &Command) -> bool {
+    !matches!(command.command, CommandType::SimpleCommand(_))
+}
+
+/// Returns true if simple is `exec` followed only by redirections
+fn is_bare_exec(simple: &SimpleCommand) -> bool {
+    simple.assignments.is_empty()
+        && simple.words.len() == 1
+        && match simple.words[0].as_slice() {
+            [WordComponent {
+                kind: WordComponentKind::Literal(word),
+                ..
+            }] => word == "exec",
+            _ => false,
+        }
+}
+
+/// Returns true if simple runs a builtin that accepts compound
+/// array assignments as arguments, such as `declare -a name=(...)`
+fn is_declaration_command(simple: &SimpleCommand) -> bool {
+    match simple.words.first().map(Vec::as_slice) {
+        Some(
+            [WordComponent {
+                kind: WordComponentKind::Literal(word),
+                ..
+            }],
+        ) => matches!(word.as_str(), "declare" | "typeset" | "readonly"),
+        _ => false,
+    }
+}
+
+fn has_process_substitution(word: &[WordComponent]) -> bool {
+    word.iter().any(|component| {
+        matches!(
+            component.kind,
+            WordComponentKind::ReadProcessSubstitution(_)
+                | WordComponentKind::WriteProcessSubstitution(_)
+        )
+    })
+}
+
+/// Arrange for command to be spawned without waiting for it to
+/// complete.  For a pipeline, that applies to each of its stages.
+fn mark_asynchronous(command: &mut Command) {
+    command.asynchronous = true;
+    if let CommandType::Pipeline(pipeline) = &mut command.command {
+        for cmd in &mut pipeline.commands {
+            cmd.asynchronous = true;
+        }
+    }
+}
+
+/// If element is of the form `[subscript]=value`, returns the
+/// subscript and value parts.  The closing `]=` must appear in
+/// a literal component.
+fn split_subscripted_element(
+    element: &[WordComponent],
+) -> Option<(Vec<WordComponent>, Vec<WordComponent>)> {
+    match element.first() {
+        Some(WordComponent {
+            kind: WordComponentKind::Literal(s),
+            ..
+        }) if s.starts_with('[') => {}
+        _ => return None,
+    }
+
+    for (i, component) in element.iter().enumerate() {
+        if let WordComponentKind::Literal(s) = &component.kind {
+            let start = if i == 0 { 1 } else { 0 };
+            if let Some(close) = s[start..].find("]=").map(|pos| pos + start) {
+                let piece = |text: &str| WordComponent {
+                    kind: WordComponentKind::Literal(text.to_owned()),
+                    ..component.clone()
+                };
+                let mut subscript: Vec<WordComponent> = element[..i].to_vec();
+                let mut value = vec![];
+                subscript.push(piece(&s[..close]));
+                if close + 2 < s.len() {
+                    value.push(piece(&s[close + 2..]));
+                }
+                value.extend(element[i + 1..].iter().cloned());
+
+                // Remove the opening bracket
+                if let WordComponentKind::Literal(first) = &mut subscript[0].kind {
+                    first.remove(0);
+                }
+                return Some((subscript, value));
+            }
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -646,7 +1343,7 @@ mod test {
     use std::collections::HashMap;
     use std::ffi::{OsStr, OsString};
     use std::io::{Read, Write};
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::sync::{Arc, Mutex};
 
     #[derive(Debug, Clone, PartialEq)]
@@ -671,6 +1368,12 @@ mod test {
             self.environment.set(key, value);
             self
         }
+
+        fn set_exported_env(mut self, key: &str, value: &str) -> Self {
+            self.environment.set(key, value);
+            self.environment.export(key);
+            self
+        }
     }
 
     #[derive(Default, Debug)]
@@ -679,12 +1382,6 @@ mod test {
         funcs: Arc<Mutex<HashMap<String, Arc<Program>>>>,
     }
 
-    impl TestHost {
-        fn lookup_function(&self, name: &str) -> Option<Arc<Program>> {
-            self.funcs.lock().unwrap().get(name).map(Arc::clone)
-        }
-    }
-
     #[derive(Debug)]
     enum ThreadState {
         Running(JoinHandle<isize>),
@@ -692,7 +1389,7 @@ mod test {
     }
 
     struct ThreadStatus {
-        state: Cell<ThreadState>,
+        state: Mutex<Option<ThreadState>>,
     }
 
     impl std::fmt::Debug for ThreadStatus {
@@ -704,19 +1401,20 @@ mod test {
     impl ThreadStatus {
         pub fn new(handle: JoinHandle<isize>) -> ThreadStatus {
             Self {
-                state: Cell::new(ThreadState::Running(handle)),
+                state: Mutex::new(Some(ThreadState::Running(handle))),
             }
         }
     }
 
     impl WaitForStatus for ThreadStatus {
         fn wait(&self) -> Option<Status> {
-            let state = self.state.replace(ThreadState::Done(10));
-            let state = match state {
-                ThreadState::Running(thread) => thread.join().unwrap(),
-                ThreadState::Done(state) => state,
+            let mut guard = self.state.lock().unwrap();
+            let state = match guard.take() {
+                Some(ThreadState::Running(thread)) => thread.join().unwrap(),
+                Some(ThreadState::Done(state)) => state,
+                None => 10,
             };
-            self.state.set(ThreadState::Done(state));
+            guard.replace(ThreadState::Done(state));
             Some(Status::Complete(state.into()))
         }
 
@@ -773,6 +1471,9 @@ mod test {
             current_directory: &mut PathBuf,
             io_env: &IoEnvironment,
         ) -> anyhow::Result<WaitableStatus> {
+            if argv.is_empty() {
+                return Ok(Status::Complete(0.into()).into());
+            }
             let command = argv
                 .get(0)
                 .ok_or_else(|| anyhow!("argv0 is missing"))?
@@ -788,13 +1489,16 @@ mod test {
                     funcs: Arc::clone(&self.funcs),
                     spawn_log: Arc::clone(&self.spawn_log),
                 }));
+                machine.set_io_env(io_env.clone());
 
                 print_prog(prog.opcodes());
                 machine.set_positional(argv.clone());
 
                 let status = machine.run();
 
-                let (new_cwd, new_env) = machine.top_environment();
+                let (new_cwd, mut new_env) = machine.top_environment();
+                // The positional parameters are local to the function call
+                new_env.set_positional(environment.positional().to_vec());
                 *current_directory = new_cwd;
                 *environment = new_env;
 
@@ -832,15 +1536,58 @@ mod test {
                 Status::Complete(2.into()).into()
             };
 
+            // The positional parameters and the call stack are not
+            // passed to spawned commands
+            let mut environment = environment.clone();
+            environment.set_positional(vec![]);
+            environment.truncate_call_stack(0);
             log.push(SpawnEntry {
                 argv: argv.clone(),
-                environment: environment.clone(),
+                environment,
                 current_directory: current_directory.clone(),
             });
             Ok(status)
         }
 
-        fn define_function(&self, name: &str, program: &Arc<Program>) -> anyhow::Result<()> {
+        fn spawn_concurrent_program(
+            &self,
+            program: &Arc<Program>,
+            environment: &Environment,
+            current_directory: &Path,
+            io_env: &IoEnvironment,
+        ) -> anyhow::Result<WaitableStatus> {
+            let program = Arc::clone(program);
+            let environment = environment.clone();
+            let current_directory = current_directory.to_path_buf();
+            let io_env = io_env.clone();
+            let host = TestHost {
+                funcs: Arc::clone(&self.funcs),
+                spawn_log: Arc::clone(&self.spawn_log),
+            };
+            let run = move || -> anyhow::Result<Status> {
+                let mut machine = Machine::new(&program, Some(environment), &current_directory)?;
+                machine.set_host(Arc::new(host));
+                machine.set_io_env(io_env);
+                machine.run()
+            };
+            Ok(ThreadStatus::new(std::thread::spawn(move || match run() {
+                Ok(Status::Complete(Value::Integer(n))) => n,
+                Ok(Status::Complete(value)) if value.truthy() => 0,
+                _ => 1,
+            }))
+            .into())
+        }
+
+        fn lookup_function(&self, name: &str) -> Option<Arc<Program>> {
+            self.funcs.lock().unwrap().get(name).map(Arc::clone)
+        }
+
+        fn define_function(
+            &self,
+            name: &str,
+            program: &Arc<Program>,
+            _location: &SourceLocation,
+        ) -> anyhow::Result<()> {
             let mut funcs = self.funcs.lock().unwrap();
             funcs.insert(name.to_owned(), Arc::clone(program));
             Ok(())
@@ -852,7 +1599,7 @@ mod test {
         let command = parser.parse()?;
         let mut compiler = Compiler::new();
         compiler.compile_command(&command)?;
-        compiler.finish()
+        Ok(compiler.finish()?.opcodes().to_vec())
     }
 
     fn run(prog: Vec<Operation>) -> anyhow::Result<Status> {
@@ -973,12 +1720,14 @@ mod test {
                 op::SpawnCommand {
                     argv: Operand::FrameRelative(1),
                     status: Operand::FrameRelative(2),
+                    concurrent: false,
                 }
                 .into(),
                 op::Wait {
                     status: Operand::FrameRelative(2),
                 }
                 .into(),
+                op::ErrExit {}.into(),
                 op::PopFrame {}.into(),
                 op::Exit {
                     value: Operand::LastWaitStatus
@@ -1052,7 +1801,7 @@ mod test {
             run_with_log(compile("foo=1 echo $foo")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_env("foo", "1"),]
+                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_exported_env("foo", "1"),]
             )
         );
 
@@ -1093,7 +1842,7 @@ mod test {
             run_with_log(compile("foo=1 echo ${foo:-bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_env("foo", "1"),]
+                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_exported_env("foo", "1"),]
             )
         );
 
@@ -1101,7 +1850,9 @@ mod test {
             run_with_log(compile("foo='' echo ${foo:-bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_env("foo", ""),]
+                vec![
+                    SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_exported_env("foo", ""),
+                ]
             )
         );
 
@@ -1109,7 +1860,7 @@ mod test {
             run_with_log(compile("foo= echo ${foo-bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into()]).set_env("foo", ""),]
+                vec![SpawnEntry::new(vec!["echo".into()]).set_exported_env("foo", ""),]
             )
         );
 
@@ -1129,7 +1880,9 @@ mod test {
             run_with_log(compile("foo=foo echo ${#foo}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "3".into()]).set_env("foo", "foo"),]
+                vec![
+                    SpawnEntry::new(vec!["echo".into(), "3".into()]).set_exported_env("foo", "foo"),
+                ]
             )
         );
         Ok(())
@@ -1169,7 +1922,7 @@ mod test {
             run_with_log(compile("foo=1 echo ${foo:=bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_env("foo", "1"),]
+                vec![SpawnEntry::new(vec!["echo".into(), "1".into()]).set_exported_env("foo", "1"),]
             )
         );
 
@@ -1177,7 +1930,8 @@ mod test {
             run_with_log(compile("foo='' echo ${foo:=bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_env("foo", "bar"),]
+                vec![SpawnEntry::new(vec!["echo".into(), "bar".into()])
+                    .set_exported_env("foo", "bar"),]
             )
         );
 
@@ -1185,7 +1939,7 @@ mod test {
             run_with_log(compile("foo= echo ${foo=bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into()]).set_env("foo", ""),]
+                vec![SpawnEntry::new(vec!["echo".into()]).set_exported_env("foo", ""),]
             )
         );
 
@@ -1194,27 +1948,91 @@ mod test {
 
     #[test]
     fn test_param_check_set() -> anyhow::Result<()> {
+        assert_eq!(runtime_error("echo ${foo:?bar}")?.error.to_string(), "bar");
         assert_eq!(
-            run_with_log_and_output(compile("echo ${foo:?bar}")?)?,
-            (
-                Status::Complete(1.into()),
-                vec![],
-                String::new(),
-                String::from("bar")
-            )
+            runtime_error("echo ${foo:?}")?.error.to_string(),
+            "parameter foo is not set"
         );
+        Ok(())
+    }
+
+    /// Compile and run prog, which is expected to fail
+    fn runtime_error(prog: &str) -> anyhow::Result<RuntimeError> {
+        let mut parser = Parser::new(prog.as_bytes());
+        let mut compiler = Compiler::new();
+        compiler.compile_command(&parser.parse()?)?;
+        let mut machine = Machine::new(
+            &compiler.finish()?,
+            Some(Environment::new_empty()),
+            &std::env::current_dir()?,
+        )?;
+        machine.set_host(Arc::new(TestHost::default()));
+        match machine.run() {
+            Ok(status) => bail!("expected an error, but completed with {:?}", status),
+            Err(err) => err
+                .downcast::<RuntimeError>()
+                .map_err(|err| anyhow!("not a RuntimeError: {:#}", err)),
+        }
+    }
+
+    #[test]
+    fn runtime_error_span() -> anyhow::Result<()> {
+        let err = runtime_error("true\necho ${foo:?bar}")?;
+        assert_eq!(err.span, Some(Span::new_to(1, 5, 15)));
+
+        let err = runtime_error("f() {\n  true\n  exec 3<&$fd\n}\nf")?;
+        assert_eq!(err.span, Some(Span::new_to(2, 7, 12)));
+        Ok(())
+    }
+
+    #[test]
+    fn command_starts() -> anyhow::Result<()> {
+        let mut parser = Parser::new("f() { true; }\nif true; then echo hi; fi".as_bytes());
+        let mut compiler = Compiler::new();
+        compiler.compile_command(&parser.parse()?)?;
+        let prog = compiler.finish()?;
+        let starts: Vec<_> = (0..prog.opcodes().len())
+            .filter(|&addr| prog.is_command_start(addr))
+            .map(|addr| prog.span(addr))
+            .collect();
         assert_eq!(
-            run_with_log_and_output(compile("echo ${foo:?}")?)?,
-            (
-                Status::Complete(1.into()),
-                vec![],
-                String::new(),
-                String::from("parameter foo is not set")
-            )
+            starts,
+            vec![
+                Some(Span::new_to(0, 0, 12)),
+                Some(Span::new_to(1, 3, 6)),
+                Some(Span::new_to(1, 14, 20)),
+            ]
         );
         Ok(())
     }
 
+    #[test]
+    fn runtime_error_backtrace() -> anyhow::Result<()> {
+        let err = runtime_error(
+            "inner() {
+  echo ${foo:?bar}
+}
+outer() { inner a; }
+true
+outer",
+        )?;
+        let calls: Vec<_> = err
+            .backtrace
+            .iter()
+            .map(|call| (call.function.as_str(), call.span))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                ("outer", Some(Span::new_to(5, 0, 4))),
+                ("inner", Some(Span::new_to(3, 10, 16))),
+            ]
+        );
+
+        assert!(runtime_error("echo ${foo:?bar}")?.backtrace.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_param_alternative_value() -> anyhow::Result<()> {
         assert_eq!(
@@ -1229,7 +2047,9 @@ mod test {
             run_with_log(compile("foo=1 echo ${foo:+bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_env("foo", "1"),]
+                vec![
+                    SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_exported_env("foo", "1"),
+                ]
             )
         );
 
@@ -1237,7 +2057,7 @@ mod test {
             run_with_log(compile("foo= echo ${foo:+bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into()]).set_env("foo", ""),]
+                vec![SpawnEntry::new(vec!["echo".into()]).set_exported_env("foo", ""),]
             )
         );
 
@@ -1245,7 +2065,9 @@ mod test {
             run_with_log(compile("foo= echo ${foo+bar}")?)?,
             (
                 Status::Complete(0.into()),
-                vec![SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_env("foo", ""),]
+                vec![
+                    SpawnEntry::new(vec!["echo".into(), "bar".into()]).set_exported_env("foo", ""),
+                ]
             )
         );
 
@@ -1311,6 +2133,159 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn pipeline_stages_concurrent() -> anyhow::Result<()> {
+        let ops = compile("echo a | uppercase | uppercase")?;
+        let concurrent: Vec<bool> = ops
+            .iter()
+            .filter_map(|op| match op {
+                Operation::SpawnCommand(spawn) => Some(spawn.concurrent),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(concurrent, vec![true, true, true]);
+        assert!(ops.contains(&op::WaitConcurrent { count: 3 }.into()));
+        assert!(!ops.iter().any(|op| matches!(op, Operation::Wait(_))));
+
+        let (status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("f() { echo hello; }\nf | uppercase")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stdout, "HELLO\n");
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_compound_stages_concurrent() -> anyhow::Result<()> {
+        let ops = compile("{ echo a; } | { uppercase; }")?;
+        assert_eq!(
+            ops.iter()
+                .filter(|op| matches!(op, Operation::SpawnConcurrentProgram(_)))
+                .count(),
+            1
+        );
+        assert!(ops.contains(&op::WaitConcurrent { count: 1 }.into()));
+
+        let (status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("{ echo a; echo b; } | uppercase")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stdout, "A\nB\n");
+
+        // The first stage writes more than a pipe can buffer, and so
+        // only completes because the stage that follows it is running
+        let (status, _log, _stdout, _stderr) =
+            run_with_log_and_output(compile("if true; then echo {1..20000}; fi | true")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_here_string() -> anyhow::Result<()> {
+        let (status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("uppercase <<< \"hi ${foo:-there}\"")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stdout, "HI THERE\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirect_stdout_and_stderr() -> anyhow::Result<()> {
+        let (_status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("echo hello &>/dev/null")?)?;
+        assert_eq!(stdout, "");
+
+        let (_status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("echo hello |& uppercase")?)?;
+        assert_eq!(stdout, "HELLO\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrays() -> anyhow::Result<()> {
+        // Array values are OsStrings while keys are Strings, so
+        // compare them as strs
+        let echo = |prog| -> anyhow::Result<Vec<String>> {
+            Ok(echo_argv(prog)?
+                .iter()
+                .map(|v| v.as_str().unwrap().to_owned())
+                .collect())
+        };
+        assert_eq!(
+            echo("arr=(one \"two three\" [5]=six seven)\necho \"${arr[@]}\"")?,
+            vec!["one", "two three", "six", "seven"]
+        );
+        assert_eq!(
+            echo("arr=(one two)\narr[4]=five\necho ${!arr[@]} ${#arr[@]} \"${arr[*]}\"")?,
+            vec!["0", "1", "4", "3", "one two five"]
+        );
+        assert_eq!(
+            echo("arr=(one two)\nidx=1\necho $arr ${arr[$idx]} ${arr[7]:-unset}")?,
+            vec!["one", "two", "unset"]
+        );
+        assert_eq!(
+            echo("word=hello\nword[1]=there\necho \"${word[@]}\"")?,
+            vec!["hello", "there"]
+        );
+
+        let (_status, log) = run_with_log(compile("arr=(one two)\nplain=yes echo")?)?;
+        let exported: Vec<&OsString> = log[0].environment.iter_exported().map(|(k, _)| k).collect();
+        assert_eq!(exported, vec![&OsString::from("plain")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_time() -> anyhow::Result<()> {
+        let (status, _log, stdout, stderr) = run_with_log_and_output(compile(
+            "TIMEFORMAT=\"%% %0U %0S\"\ntime echo hello | uppercase",
+        )?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stdout, "HELLO\n");
+        assert_eq!(stderr, "% 0 0\n");
+
+        let (status, _log, _stdout, stderr) =
+            run_with_log_and_output(compile("TIMEFORMAT=\ntime ! false")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stderr, "");
+
+        let (_status, _log, _stdout, stderr) = run_with_log_and_output(compile("time -p true")?)?;
+        let lines: Vec<&str> = stderr.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("real "));
+        assert_eq!(&lines[1..], &["user 0.00", "sys 0.00"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_close_and_move_fd() -> anyhow::Result<()> {
+        let (_status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("echo hello 3>&1- >&3")?)?;
+        assert_eq!(stdout, "hello\n");
+
+        assert!(run_with_log(compile("echo hello >&-")?).is_err());
+        assert!(run_with_log(compile("echo hello 3>&1- >&1")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_redirection() -> anyhow::Result<()> {
+        let (_status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("exec 3>&1\necho hello >&3")?)?;
+        assert_eq!(stdout, "hello\n");
+
+        assert!(run_with_log(compile("exec 3>&1\nexec 3>&-\necho hello >&3")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_fd() -> anyhow::Result<()> {
+        let (_status, _log, stdout, _stderr) = run_with_log_and_output(compile(
+            "exec {out}>&1 {log}>&1\necho $out $log\necho hello >&$log",
+        )?)?;
+        assert_eq!(stdout, "10 11\nhello\n");
+
+        assert!(run_with_log(compile("exec {log}>&1\nexec {log}>&-\necho hello >&$log")?).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_glob() -> anyhow::Result<()> {
         assert_eq!(
@@ -1321,10 +2296,11 @@ mod test {
                     "echo".into(),
                     // This test is sensitive to the names of the files
                     // in this shell_compiler crate!
+                    OsString::from("src/braceexpand.rs").into(),
                     OsString::from("src/lib.rs").into(),
                     OsString::from("src/registeralloc.rs").into(),
                 ]),],
-                "src/lib.rs src/registeralloc.rs\n".to_owned(),
+                "src/braceexpand.rs src/lib.rs src/registeralloc.rs\n".to_owned(),
                 "".to_owned(),
             )
         );
@@ -1451,6 +2427,112 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn function_location() -> anyhow::Result<()> {
+        let mut parser = Parser::new("true\nf() { true }".as_bytes());
+        let command = parser.parse()?;
+        let mut compiler = Compiler::new();
+        compiler.set_source_name("script.wzsh");
+        compiler.compile_command(&command)?;
+        let locations: Vec<String> = compiler
+            .finish()?
+            .opcodes()
+            .iter()
+            .filter_map(|op| match op {
+                Operation::DefineFunction(def) => Some(def.location.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(locations, vec!["script.wzsh:2".to_owned()]);
+        Ok(())
+    }
+
+    /// Run prog with the errexit option enabled, returning its status
+    /// and the commands that it spawned
+    fn run_errexit(prog: &str) -> anyhow::Result<(Status, Vec<String>)> {
+        let mut env = Environment::new_empty();
+        env.set_option(ShellOption::ErrExit, true);
+        let mut machine = Machine::new(
+            &Program::new(compile(prog)?),
+            Some(env),
+            &std::env::current_dir()?,
+        )?;
+        let host = TestHost::default();
+        let log = Arc::clone(&host.spawn_log);
+        machine.set_host(Arc::new(host));
+        let status = machine.run()?;
+        let spawned = log
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                entry
+                    .argv
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        Ok((status, spawned))
+    }
+
+    #[test]
+    fn errexit_in_called_functions() -> anyhow::Result<()> {
+        let spawned = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        // A function called from a condition doesn't exit when one of
+        // its commands fails, unlike when it is called directly
+        assert_eq!(
+            run_errexit("f() { false; echo in; }\nif f; then echo t; fi\necho after")?,
+            (
+                Status::Complete(0.into()),
+                spawned(&["false", "echo in", "echo t", "echo after"])
+            )
+        );
+        assert_eq!(
+            run_errexit("f() { false; echo in; }\nf && echo and\necho after")?,
+            (
+                Status::Complete(0.into()),
+                spawned(&["false", "echo in", "echo and", "echo after"])
+            )
+        );
+        assert_eq!(
+            run_errexit("f() { false; echo in; }\nf || echo or\necho after")?,
+            (
+                Status::Complete(0.into()),
+                spawned(&["false", "echo in", "echo after"])
+            )
+        );
+        assert_eq!(
+            run_errexit("f() { false; echo in; }\nf\necho after")?,
+            (Status::Complete(1.into()), spawned(&["false"]))
+        );
+        // The exemption ends with the condition
+        assert_eq!(
+            run_errexit("f() { true; }\nif f; then false; echo t; fi")?,
+            (Status::Complete(1.into()), spawned(&["true", "false"]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errexit_exemptions() -> anyhow::Result<()> {
+        let count_errexit = |prog: &str| -> anyhow::Result<usize> {
+            Ok(compile(prog)?
+                .iter()
+                .filter(|op| matches!(op, Operation::ErrExit(_)))
+                .count())
+        };
+        assert_eq!(count_errexit("false")?, 1);
+        assert_eq!(count_errexit("if false; then true; else true; fi")?, 2);
+        assert_eq!(count_errexit("false || true")?, 1);
+        assert_eq!(count_errexit("! false")?, 0);
+        assert_eq!(count_errexit("false | true")?, 1);
+        assert_eq!(count_errexit("false &")?, 0);
+        Ok(())
+    }
+
     #[test]
     fn positional_len() -> anyhow::Result<()> {
         assert_eq!(
@@ -1462,4 +2544,165 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn function_call() -> anyhow::Result<()> {
+        let (status, log) = run_with_log(compile("f() { echo $1; false }\nf a\necho $#")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        let argv: Vec<Vec<Value>> = log.into_iter().map(|entry| entry.argv).collect();
+        assert_eq!(
+            argv,
+            vec![
+                strings(&["echo", "a"]),
+                strings(&["false"]),
+                strings(&["echo", "0"])
+            ]
+        );
+
+        assert_eq!(
+            run_with_log(compile("f() { false }\nf")?)?.0,
+            Status::Complete(1.into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn function_nesting_limit() -> anyhow::Result<()> {
+        let err = run_with_log(compile("FUNCNEST=3\nf() { echo $1; f }\nf")?).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("f: maximum function nesting level exceeded (3)"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    fn echo_argv(prog: &str) -> anyhow::Result<Vec<Value>> {
+        let (_status, mut log) = run_with_log(compile(prog)?)?;
+        Ok(log.remove(0).argv.split_off(1))
+    }
+
+    fn strings(list: &[&str]) -> Vec<Value> {
+        list.iter().map(|s| (*s).into()).collect()
+    }
+
+    #[test]
+    fn brace_expand_list() -> anyhow::Result<()> {
+        assert_eq!(
+            echo_argv("echo src/{lib,bin}")?,
+            strings(&["src/lib", "src/bin"])
+        );
+        assert_eq!(
+            echo_argv("echo a{b,c{d,e}f}g")?,
+            strings(&["abg", "acdfg", "acefg"])
+        );
+        assert_eq!(echo_argv("echo x{,y}")?, strings(&["x", "xy"]));
+        assert_eq!(
+            echo_argv("echo {a,b}{1,2}")?,
+            strings(&["a1", "a2", "b1", "b2"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn brace_expand_sequence() -> anyhow::Result<()> {
+        assert_eq!(echo_argv("echo {1..4}")?, strings(&["1", "2", "3", "4"]));
+        assert_eq!(echo_argv("echo {3..1}")?, strings(&["3", "2", "1"]));
+        assert_eq!(echo_argv("echo {1..10..4}")?, strings(&["1", "5", "9"]));
+        assert_eq!(echo_argv("echo {08..10}")?, strings(&["08", "09", "10"]));
+        assert_eq!(
+            echo_argv("echo {-05..3..2}")?,
+            strings(&["-05", "-03", "-01", "001", "003"])
+        );
+        assert_eq!(echo_argv("echo {a..e..2}")?, strings(&["a", "c", "e"]));
+        assert_eq!(echo_argv("echo {1..a}")?, strings(&["{1..a}"]));
+        assert_eq!(
+            echo_argv("echo {-1..9223372036854775807..9223372036854775807}")?,
+            strings(&["-1", "9223372036854775806"])
+        );
+        assert_eq!(
+            echo_argv("echo {-9223372036854775808..9223372036854775807..1}")?,
+            strings(&["{-9223372036854775808..9223372036854775807..1}"])
+        );
+        assert_eq!(
+            echo_argv("echo {1..3..-9223372036854775808}")?,
+            strings(&["{1..3..-9223372036854775808}"])
+        );
+        assert_eq!(
+            echo_argv("echo {1..99999999999}")?,
+            strings(&["{1..99999999999}"])
+        );
+        assert_eq!(echo_argv("echo {0..65536}")?.len(), 1);
+        assert_eq!(echo_argv("echo {0..131072..2}")?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn brace_expand_literal() -> anyhow::Result<()> {
+        assert_eq!(
+            echo_argv("echo {} {a} {a,b")?,
+            strings(&["{}", "{a}", "{a,b"])
+        );
+        assert_eq!(echo_argv("echo {a{b,c}}")?, strings(&["{ab}", "{ac}"]));
+        assert_eq!(
+            echo_argv("echo '{a,b}' \"{a,b}\" \\{a,b\\}")?,
+            strings(&["{a,b}", "{a,b}", "{a,b}"])
+        );
+        assert_eq!(
+            run_with_log(compile("a={x,y} echo")?)?,
+            (
+                Status::Complete(0.into()),
+                vec![SpawnEntry::new(vec!["echo".into()]).set_exported_env("a", "{x,y}")]
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn brace_expand_param() -> anyhow::Result<()> {
+        assert_eq!(
+            echo_argv("foo=1 echo {$foo,\"$foo\"2}")?,
+            strings(&["1", "12"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn process_subst() -> anyhow::Result<()> {
+        let (status, log) = run_with_log(compile("true <(echo hello) in:>(uppercase)")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        let argv: Vec<Vec<Value>> = log.into_iter().map(|entry| entry.argv).collect();
+        assert_eq!(
+            argv,
+            vec![
+                strings(&["echo", "hello"]),
+                strings(&["uppercase"]),
+                strings(&["true", "/dev/fd/10", "in:/dev/fd/11"]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn process_subst_redirection() -> anyhow::Result<()> {
+        // The shell opens the path itself here, so it has to resolve
+        // it to the descriptor in its IO environment
+        let (status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("uppercase < <(echo hello)")?)?;
+        assert_eq!(status, Status::Complete(0.into()));
+        assert_eq!(stdout, "HELLO\n");
+
+        let (_status, _log, stdout, _stderr) =
+            run_with_log_and_output(compile("echo there > >(uppercase)")?)?;
+        assert_eq!(stdout, "THERE\n");
+        Ok(())
+    }
+
+    #[test]
+    fn process_subst_pipeline() -> anyhow::Result<()> {
+        let (_status, log) = run_with_log(compile("true <(echo hello | uppercase)")?)?;
+        assert_eq!(log.last().unwrap().argv, strings(&["true", "/dev/fd/10"]));
+        Ok(())
+    }
 }
@@ -15,6 +15,8 @@ pub enum LexErrorKind {
     EofDuringParameterExpansion,
     #[error("EOF while lexing assignment word")]
     EofDuringAssignmentWord,
+    #[error("EOF while lexing compound array assignment")]
+    EofDuringCompoundAssignment,
     #[error("EOF while lexing command substitution")]
     EofDuringCommandSubstitution,
     #[error("IO Error")]
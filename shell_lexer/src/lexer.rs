@@ -16,6 +16,8 @@ lazy_static! {
     static ref PARAM_RE: Regex = Regex::new(r"^([0-9]+|[@*#?$!-]|[a-zA-Z_][a-zA-Z0-9_]+)")
         .expect("failed to compile PARAM_RE");
     static ref OPER_RE: Regex = Regex::new(r"^[%#:]?[%#-=?+]").expect("failed to compile OPER_RE");
+    static ref PROCESS_SUBST_RE: Regex =
+        Regex::new(r"^[<>]\(").expect("failed to compile PROCESS_SUBST_RE");
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -24,6 +26,12 @@ pub enum WordComponentKind {
     TildeExpand(Option<String>),
     ParamExpand(ParamExpr),
     CommandSubstitution(Vec<Token>),
+    /// `<(command)`; expands to a path from which the output
+    /// of command can be read
+    ReadProcessSubstitution(Vec<Token>),
+    /// `>(command)`; expands to a path which can be written to
+    /// in order to feed the input of command
+    WriteProcessSubstitution(Vec<Token>),
 }
 
 impl WordComponentKind {
@@ -43,8 +51,14 @@ pub struct WordComponent {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Assignment {
     pub name: String,
+    /// The subscript from `name[subscript]=value`
+    pub subscript: Option<Vec<WordComponent>>,
     pub span: Span,
     pub value: Vec<WordComponent>,
+    /// The words from a compound array assignment of the form
+    /// `name=(word1 word2 [subscript]=word3)`.  When set, value
+    /// is empty.
+    pub elements: Option<Vec<Vec<WordComponent>>>,
 }
 
 impl From<&Assignment> for Vec<WordComponent> {
@@ -53,13 +67,31 @@ impl From<&Assignment> for Vec<WordComponent> {
     /// but also de-tilde expanding any word components we
     /// find in the RHS.
     fn from(assignment: &Assignment) -> Vec<WordComponent> {
-        let mut components = vec![];
-        components.push(WordComponent {
-            kind: WordComponentKind::Literal(format!("{}=", assignment.name)),
+        let literal = |s: &str| WordComponent {
+            kind: WordComponentKind::Literal(s.to_owned()),
             span: assignment.span,
             splittable: true,
             remove_backslash: false,
-        });
+        };
+        let mut components = vec![];
+        match &assignment.subscript {
+            Some(subscript) => {
+                components.push(literal(&format!("{}[", assignment.name)));
+                components.extend(subscript.iter().cloned());
+                components.push(literal("]="));
+            }
+            None => components.push(literal(&format!("{}=", assignment.name))),
+        }
+        if let Some(elements) = &assignment.elements {
+            components.push(literal("("));
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    components.push(literal(" "));
+                }
+                components.extend(element.iter().cloned());
+            }
+            components.push(literal(")"));
+        }
 
         for comp in &assignment.value {
             match comp {
@@ -91,6 +123,8 @@ pub enum Token {
     Eof(Pos),
     Newline(Pos),
     IoNumber(usize, Span),
+    /// The `{name}` part of `{name}>file`
+    IoVarName(String, Span),
     Assignment(Assignment),
     EndCommandSubst(Pos),
     EndParamSubst(Pos),
@@ -134,7 +168,8 @@ impl Token {
             Token::Word(list) => list[0].span,
             Token::Operator(_, span)
             | Token::Assignment(Assignment { span, .. })
-            | Token::IoNumber(_, span) => *span,
+            | Token::IoNumber(_, span)
+            | Token::IoVarName(_, span) => *span,
             Token::Newline(pos)
             | Token::EndCommandSubst(pos)
             | Token::EndParamSubst(pos)
@@ -181,6 +216,22 @@ pub enum ParamOper {
     /// $NAME shall be expanded with the largest portion of the prefix
     /// matched by the pattern deleted.
     RemoveLargestPrefixPattern,
+    /// `${!NAME[@]}` or `${!NAME[*]}` returns the indices of the
+    /// array named NAME, or the keys if it is associative.
+    Keys,
+}
+
+/// The subscript of an array parameter expansion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamSubscript {
+    /// `${NAME[@]}`; when it makes up a whole word, each element
+    /// of the array is expanded as a separate field
+    At,
+    /// `${NAME[*]}`
+    Star,
+    /// `${NAME[word]}`; the element at the index or key produced
+    /// by expanding word
+    Word(Vec<WordComponent>),
 }
 
 /// Represents a parameter expansion expression
@@ -188,6 +239,7 @@ pub enum ParamOper {
 pub struct ParamExpr {
     pub kind: ParamOper,
     pub name: String,
+    pub subscript: Option<ParamSubscript>,
     pub word: Vec<Vec<WordComponent>>,
 }
 
@@ -259,6 +311,16 @@ impl<R: Read> Lexer<R> {
                 return Ok(token);
             }
 
+            let process_subst = self
+                .reader
+                .matches_regex(&PROCESS_SUBST_RE)?
+                .map(|(caps, pos)| (caps.get(0).unwrap().as_str() == ">(", pos));
+            if let Some((write, start)) = process_subst {
+                self.reader.fixup_matched_length(2);
+                self.process_substitution(start, write)?;
+                continue;
+            }
+
             if let MatchResult::Match(..) = self.reader.matches_literal(&OPERATORS)? {
                 if let Some(token) = self.delimit_current_word() {
                     return Ok(token);
@@ -291,21 +353,43 @@ impl<R: Read> Lexer<R> {
                 return Ok(Token::IoNumber(num, span));
             }
 
+            // `{name}>` is only recognized at the start of a word
+            if self.state().current_word.is_none() && self.reader.matches_io_var_name()? {
+                let (name, span) = self.reader.next_io_var_name()?.unwrap();
+                return Ok(Token::IoVarName(name, span));
+            }
+
             if self.reader.matches_assignment_word()? {
                 if let Some(token) = self.delimit_current_word() {
                     return Ok(token);
                 }
-                let (name, span) = self.reader.next_assignment_word()?.unwrap();
-                self.push_state(State::AssignmentWord);
-                let value = match self.top()? {
-                    Token::Word(value) => value,
-                    token => {
-                        self.unget_token(token);
-                        vec![]
-                    }
+                let (name, subscript, span) = self.reader.next_assignment_word()?.unwrap();
+                let subscript = match subscript {
+                    Some((text, pos)) => Some(lex_subscript(&text, pos)?),
+                    None => None,
+                };
+                let elements = self.compound_assignment()?;
+                let value = if elements.is_some() {
+                    vec![]
+                } else {
+                    self.push_state(State::AssignmentWord);
+                    let value = match self.top()? {
+                        Token::Word(value) => value,
+                        token => {
+                            self.unget_token(token);
+                            vec![]
+                        }
+                    };
+                    self.pop_state();
+                    value
                 };
-                self.pop_state();
-                return Ok(Token::Assignment(Assignment { name, span, value }));
+                return Ok(Token::Assignment(Assignment {
+                    name,
+                    subscript,
+                    span,
+                    value,
+                    elements,
+                }));
             }
 
             match self.reader.next_char() {
@@ -444,6 +528,58 @@ impl<R: Read> Lexer<R> {
         }
     }
 
+    /// If the assignment word that was just consumed is followed
+    /// by `(`, lex the words of the compound array assignment up
+    /// to the closing `)`.
+    fn compound_assignment(&mut self) -> anyhow::Result<Option<Vec<Vec<WordComponent>>>> {
+        match self.reader.next_char() {
+            Next::Char(c) if c.c == '(' => {}
+            Next::Char(c) => {
+                self.reader.unget(c);
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+
+        // A fresh state so that the closing paren isn't counted
+        // against an enclosing command substitution
+        self.push_state(State::Top);
+        let mut elements = vec![];
+        loop {
+            match self.top()? {
+                Token::Word(word) => elements.push(word),
+                Token::Assignment(assignment) => elements.push((&assignment).into()),
+                Token::Newline(_) => {}
+                Token::Operator(Operator::RightParen, _) => break,
+                Token::Eof(pos) => {
+                    return Err(LexErrorKind::EofDuringCompoundAssignment
+                        .at(pos.into())
+                        .into())
+                }
+                token => bail!("invalid token in compound assignment: {:?}", token),
+            }
+        }
+        self.pop_state();
+        Ok(Some(elements))
+    }
+
+    /// Consume the text of a subscript up to the closing `]`;
+    /// the opening `[` has already been consumed.
+    fn subscript_text(&mut self) -> anyhow::Result<String> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let c = self.next_char_or_err(LexErrorKind::EofDuringParameterExpansion)?;
+            match c.c {
+                '[' => depth += 1,
+                ']' if depth == 0 => return Ok(text),
+                ']' => depth -= 1,
+                _ => {}
+            }
+            text.push(c.c);
+        }
+    }
+
     fn backslash(&mut self, backslash: PositionedChar) -> anyhow::Result<()> {
         let quoted = self.next_char_or_err(LexErrorKind::EofDuringBackslash)?;
         if quoted.c != '\n' {
@@ -508,6 +644,48 @@ impl<R: Read> Lexer<R> {
     }
 
     fn command(&mut self, start: Pos, opener: PositionedChar) -> anyhow::Result<()> {
+        let (tokens, end) = self.command_tokens(start, opener)?;
+        let word = WordComponent {
+            kind: WordComponentKind::CommandSubstitution(tokens),
+            span: Span::new(start, end),
+            splittable: true,
+            remove_backslash: true,
+        };
+
+        self.add_to_word(word);
+        Ok(())
+    }
+
+    /// Lex `<(command)` or `>(command)`; the leading `<(` or `>(`
+    /// has already been consumed.
+    fn process_substitution(&mut self, start: Pos, write: bool) -> anyhow::Result<()> {
+        let opener = PositionedChar {
+            c: '(',
+            pos: Pos::new(start.line, start.col + 1),
+        };
+        let (tokens, end) = self.command_tokens(start, opener)?;
+        let word = WordComponent {
+            kind: if write {
+                WordComponentKind::WriteProcessSubstitution(tokens)
+            } else {
+                WordComponentKind::ReadProcessSubstitution(tokens)
+            },
+            span: Span::new(start, end),
+            splittable: true,
+            remove_backslash: true,
+        };
+
+        self.add_to_word(word);
+        Ok(())
+    }
+
+    /// Collect the tokens that comprise a command up until its closer.
+    /// Returns the tokens and the position of the closer.
+    fn command_tokens(
+        &mut self,
+        start: Pos,
+        opener: PositionedChar,
+    ) -> anyhow::Result<(Vec<Token>, Pos)> {
         let closer = match opener.c {
             '(' => ')',
             '`' => '`',
@@ -535,15 +713,7 @@ impl<R: Read> Lexer<R> {
             }
         }
         self.pop_state();
-        let word = WordComponent {
-            kind: WordComponentKind::CommandSubstitution(tokens),
-            span: Span::new(start, end),
-            splittable: true,
-            remove_backslash: true,
-        };
-
-        self.add_to_word(word);
-        Ok(())
+        Ok((tokens, end))
     }
 
     fn arithmetic(&mut self, _start: Pos) -> anyhow::Result<()> {
@@ -562,6 +732,16 @@ impl<R: Read> Lexer<R> {
             let hash = self.next_char_or_err(LexErrorKind::EofDuringParameterExpansion)?;
             if hash.c == '#' {
                 Some(ParamOper::StringLength)
+            } else if hash.c == '!' {
+                // `${!}` is the special parameter rather than `${!NAME[@]}`
+                let next = self.next_char_or_err(LexErrorKind::EofDuringParameterExpansion)?;
+                self.reader.unget(next);
+                if next.c == '}' {
+                    self.reader.unget(hash);
+                    None
+                } else {
+                    Some(ParamOper::Keys)
+                }
             } else {
                 self.reader.unget(hash);
                 None
@@ -584,6 +764,30 @@ impl<R: Read> Lexer<R> {
         let mut end = name_pos;
         end.col += name.len() - 1;
 
+        let subscript = if curlies {
+            let bracket = self.next_char_or_err(LexErrorKind::EofDuringParameterExpansion)?;
+            if bracket.c == '[' {
+                let pos = Pos::new(bracket.pos.line, bracket.pos.col + 1);
+                Some(match self.subscript_text()?.as_str() {
+                    "@" => ParamSubscript::At,
+                    "*" => ParamSubscript::Star,
+                    text => ParamSubscript::Word(lex_subscript(text, pos)?),
+                })
+            } else {
+                self.reader.unget(bracket);
+                None
+            }
+        } else {
+            None
+        };
+
+        if oper == Some(ParamOper::Keys) {
+            match subscript {
+                Some(ParamSubscript::At) | Some(ParamSubscript::Star) => {}
+                _ => bail!("indirect expansion of ${{!{}}} is not supported", name),
+            }
+        }
+
         if curlies && oper.is_none() {
             if let Some((caps, _oper_pos)) = self.reader.matches_regex(&OPER_RE)? {
                 let oper_len = caps.get(0).unwrap().as_str().len();
@@ -637,6 +841,7 @@ impl<R: Read> Lexer<R> {
             kind: WordComponentKind::ParamExpand(ParamExpr {
                 kind: oper,
                 name,
+                subscript,
                 word,
             }),
             span: Span::new(start, end),
@@ -832,6 +1037,29 @@ fn split_first_word_by_unquoted_colons(words: &mut Vec<WordComponent>) -> usize
     }
 }
 
+/// Lex the text of an array subscript, which is subject to
+/// parameter expansion, into a single word.  The spans of the
+/// returned components are adjusted to be relative to start.
+fn lex_subscript(text: &str, start: Pos) -> anyhow::Result<Vec<WordComponent>> {
+    let mut lexer = Lexer::new(text.as_bytes());
+    let mut word = match lexer.next_token()? {
+        Token::Word(word) => word,
+        Token::Eof(_) => vec![],
+        token => bail!("invalid array subscript {:?}: {:?}", text, token),
+    };
+    match lexer.next_token()? {
+        Token::Eof(_) => {}
+        token => bail!("invalid array subscript {:?}: {:?}", text, token),
+    }
+    for component in &mut word {
+        for pos in &mut [&mut component.span.start, &mut component.span.end] {
+            pos.line += start.line;
+            pos.col += start.col;
+        }
+    }
+    Ok(word)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -970,6 +1198,116 @@ mod test {
         );
     }
 
+    #[test]
+    fn arrays() {
+        let literal = |s: &str, start, end| WordComponent {
+            kind: WordComponentKind::literal(s),
+            span: Span::new_to(0, start, end),
+            splittable: true,
+            remove_backslash: true,
+        };
+        assert_eq!(
+            tokens("arr=(one two)"),
+            vec![Token::Assignment(Assignment {
+                name: "arr".to_owned(),
+                subscript: None,
+                elements: Some(vec![
+                    vec![literal("one", 5, 7)],
+                    vec![literal("two", 9, 11)]
+                ]),
+                span: Span::new_to(0, 0, 4),
+                value: vec![],
+            })]
+        );
+        assert_eq!(
+            tokens("arr[12]=x"),
+            vec![Token::Assignment(Assignment {
+                name: "arr".to_owned(),
+                subscript: Some(vec![literal("12", 4, 5)]),
+                elements: None,
+                span: Span::new_to(0, 0, 8),
+                value: vec![literal("x", 8, 8)],
+            })]
+        );
+        assert_eq!(
+            tokens("${#arr[@]}"),
+            vec![Token::Word(vec![WordComponent {
+                kind: WordComponentKind::ParamExpand(ParamExpr {
+                    kind: ParamOper::StringLength,
+                    name: "arr".to_owned(),
+                    subscript: Some(ParamSubscript::At),
+                    word: vec![]
+                }),
+                span: Span::new_to(0, 0, 9),
+                splittable: true,
+                remove_backslash: false,
+            }])]
+        );
+        assert_eq!(
+            tokens("${!arr[*]}"),
+            vec![Token::Word(vec![WordComponent {
+                kind: WordComponentKind::ParamExpand(ParamExpr {
+                    kind: ParamOper::Keys,
+                    name: "arr".to_owned(),
+                    subscript: Some(ParamSubscript::Star),
+                    word: vec![]
+                }),
+                span: Span::new_to(0, 0, 9),
+                splittable: true,
+                remove_backslash: false,
+            }])]
+        );
+        assert_eq!(
+            tokens("${arr[key]:-x}"),
+            vec![Token::Word(vec![WordComponent {
+                kind: WordComponentKind::ParamExpand(ParamExpr {
+                    kind: ParamOper::GetDefault { allow_null: false },
+                    name: "arr".to_owned(),
+                    subscript: Some(ParamSubscript::Word(vec![literal("key", 6, 8)])),
+                    word: vec![vec![literal("x", 12, 12)]]
+                }),
+                span: Span::new_to(0, 0, 13),
+                splittable: true,
+                remove_backslash: false,
+            }])]
+        );
+    }
+
+    #[test]
+    fn io_var_name() {
+        assert_eq!(
+            tokens("{log}>>foo"),
+            vec![
+                Token::IoVarName("log".to_owned(), Span::new_to(0, 0, 4)),
+                Token::Operator(Operator::DoubleGreat, Span::new_to(0, 5, 6)),
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("foo"),
+                    span: Span::new_to(0, 7, 9),
+                    splittable: true,
+                    remove_backslash: true
+                },]),
+            ]
+        );
+        assert_eq!(
+            tokens("x{log}>foo"),
+            vec![
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("x{log}"),
+                    span: Span::new_to(0, 0, 5),
+                    splittable: true,
+                    remove_backslash: true
+                },]),
+                Token::Operator(Operator::Great, Span::new_to(0, 6, 6)),
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("foo"),
+                    span: Span::new_to(0, 7, 9),
+                    splittable: true,
+                    remove_backslash: true
+                },]),
+            ]
+        );
+    }
+
     #[test]
     fn io_number() {
         assert_eq!(
@@ -1019,6 +1357,8 @@ mod test {
             tokens("FOO=bar"),
             vec![Token::Assignment(Assignment {
                 name: "FOO".to_owned(),
+                subscript: None,
+                elements: None,
                 span: Span::new_to(0, 0, 4),
                 value: vec![WordComponent {
                     kind: WordComponentKind::literal("bar"),
@@ -1033,6 +1373,8 @@ mod test {
             tokens("FOO="),
             vec![Token::Assignment(Assignment {
                 name: "FOO".to_owned(),
+                subscript: None,
+                elements: None,
                 span: Span::new_to(0, 0, 4),
                 value: vec![],
             })]
@@ -1043,6 +1385,8 @@ mod test {
             vec![
                 Token::Assignment(Assignment {
                     name: "FOO".to_owned(),
+                    subscript: None,
+                    elements: None,
                     span: Span::new_to(0, 0, 4),
                     value: vec![],
                 }),
@@ -1055,6 +1399,8 @@ mod test {
             vec![
                 Token::Assignment(Assignment {
                     name: "FOO".to_owned(),
+                    subscript: None,
+                    elements: None,
                     span: Span::new_to(0, 0, 4),
                     value: vec![],
                 }),
@@ -1066,6 +1412,8 @@ mod test {
             tokens("FOO=~bar"),
             vec![Token::Assignment(Assignment {
                 name: "FOO".to_owned(),
+                subscript: None,
+                elements: None,
                 span: Span::new_to(0, 0, 4),
                 value: vec![WordComponent {
                     kind: WordComponentKind::TildeExpand(Some("bar".to_owned())),
@@ -1081,6 +1429,8 @@ mod test {
             tilde_tokens,
             vec![Token::Assignment(Assignment {
                 name: "FOO".to_owned(),
+                subscript: None,
+                elements: None,
                 span: Span::new_to(0, 0, 4),
                 value: vec![
                     WordComponent {
@@ -1179,6 +1529,8 @@ mod test {
             vec![
                 Token::Assignment(Assignment {
                     name: "FOO".to_owned(),
+                    subscript: None,
+                    elements: None,
                     span: Span::new_to(0, 0, 4),
                     value: vec![WordComponent {
                         kind: WordComponentKind::literal("bar"),
@@ -1200,6 +1552,8 @@ mod test {
             tokens("FOO=\"bar baz\""),
             vec![Token::Assignment(Assignment {
                 name: "FOO".to_owned(),
+                subscript: None,
+                elements: None,
                 span: Span::new_to(0, 0, 4),
                 value: vec![WordComponent {
                     kind: WordComponentKind::literal("bar baz"),
@@ -1295,6 +1649,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::Get,
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![]
                 }),
                 span: Span::new_to(0, 0, 5),
@@ -1308,6 +1663,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::Get,
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![]
                 }),
                 span: Span::new_to(0, 0, 7),
@@ -1325,6 +1681,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::Get,
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![]
                 }),
                 span: Span::new_to(0, 0, 3),
@@ -1338,6 +1695,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::Get,
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![]
                 }),
                 span: Span::new_to(0, 0, 5),
@@ -1351,6 +1709,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::StringLength,
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![]
                 }),
                 span: Span::new_to(0, 0, 6),
@@ -1365,6 +1724,7 @@ mod test {
                     kind: WordComponentKind::ParamExpand(ParamExpr {
                         kind: ParamOper::Get,
                         name: "foo".to_owned(),
+                        subscript: None,
                         word: vec![]
                     }),
                     span: Span::new_to(0, 0, 5),
@@ -1387,6 +1747,7 @@ mod test {
                     kind: WordComponentKind::ParamExpand(ParamExpr {
                         kind: ParamOper::GetDefault { allow_null: false },
                         name: "foo".to_owned(),
+                        subscript: None,
                         word: vec![
                             vec![WordComponent {
                                 kind: WordComponentKind::literal("hello"),
@@ -1422,6 +1783,7 @@ mod test {
                     kind: WordComponentKind::ParamExpand(ParamExpr {
                         kind: ParamOper::GetDefault { allow_null: false },
                         name: "foo".to_owned(),
+                        subscript: None,
                         word: vec![
                             vec![WordComponent {
                                 kind: WordComponentKind::literal("hello"),
@@ -1457,10 +1819,12 @@ mod test {
                     kind: WordComponentKind::ParamExpand(ParamExpr {
                         kind: ParamOper::GetDefault { allow_null: false },
                         name: "foo".to_owned(),
+                        subscript: None,
                         word: vec![vec![WordComponent {
                             kind: WordComponentKind::ParamExpand(ParamExpr {
                                 kind: ParamOper::Get,
                                 name: "nest".to_owned(),
+                                subscript: None,
                                 word: vec![]
                             }),
                             span: Span::new_to(0, 7, 13),
@@ -1487,6 +1851,7 @@ mod test {
                 kind: WordComponentKind::ParamExpand(ParamExpr {
                     kind: ParamOper::GetDefault { allow_null: false },
                     name: "foo".to_owned(),
+                    subscript: None,
                     word: vec![vec![WordComponent {
                         kind: WordComponentKind::TildeExpand(Some("wez".to_owned())),
                         span: Span::new_to(0, 7, 10),
@@ -1697,6 +2062,68 @@ mod test {
         );
     }
 
+    #[test]
+    fn process_subst() {
+        assert_eq!(
+            tokens("diff <(ls) >(cat)"),
+            vec![
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("diff"),
+                    span: Span::new_to(0, 0, 3),
+                    splittable: true,
+                    remove_backslash: true
+                }]),
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::ReadProcessSubstitution(vec![Token::Word(vec![
+                        WordComponent {
+                            kind: WordComponentKind::literal("ls"),
+                            span: Span::new_to(0, 7, 8),
+                            splittable: true,
+                            remove_backslash: true
+                        }
+                    ])]),
+                    span: Span::new_to(0, 5, 9),
+                    splittable: true,
+                    remove_backslash: true,
+                }]),
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::WriteProcessSubstitution(vec![Token::Word(vec![
+                        WordComponent {
+                            kind: WordComponentKind::literal("cat"),
+                            span: Span::new_to(0, 13, 15),
+                            splittable: true,
+                            remove_backslash: true
+                        }
+                    ])]),
+                    span: Span::new_to(0, 11, 16),
+                    splittable: true,
+                    remove_backslash: true,
+                }]),
+            ]
+        );
+
+        assert_eq!(
+            tokens("cat < (ls)"),
+            vec![
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("cat"),
+                    span: Span::new_to(0, 0, 2),
+                    splittable: true,
+                    remove_backslash: true
+                }]),
+                Token::Operator(Operator::Less, Span::new_to(0, 4, 4)),
+                Token::Operator(Operator::LeftParen, Span::new_to(0, 6, 6)),
+                Token::Word(vec![WordComponent {
+                    kind: WordComponentKind::literal("ls"),
+                    span: Span::new_to(0, 7, 8),
+                    splittable: true,
+                    remove_backslash: true
+                }]),
+                Token::Operator(Operator::RightParen, Span::new_to(0, 9, 9)),
+            ]
+        );
+    }
+
     #[test]
     fn arithmetic() {
         assert_eq!(token_err("$(($x-1))"), "arithmetic not done");
@@ -6,7 +6,10 @@ mod reader;
 mod tokenenum;
 
 pub use errors::{LexError, LexErrorKind};
-pub use lexer::{Assignment, Lexer, ParamExpr, ParamOper, Token, WordComponent, WordComponentKind};
+pub use lexer::{
+    Assignment, Lexer, ParamExpr, ParamOper, ParamSubscript, Token, WordComponent,
+    WordComponentKind,
+};
 pub use position::{Pos, Span};
 pub use reader::CharReader;
 pub use tokenenum::LiteralMatcher;
@@ -14,6 +17,7 @@ pub use tokenenum::LiteralMatcher;
 TokenEnum!(
     Operator,
     OPERATORS,
+    "<<<": TripleLess,
     "<<-": DoubleLessDash,
     "<<": DoubleLess,
     "<&": LessAnd,
@@ -21,7 +25,10 @@ TokenEnum!(
     ">>": DoubleGreat,
     ">|": Clobber,
     ">&": GreatAnd,
+    "&>>": AndDoubleGreat,
+    "&>": AndGreat,
     "&&": AndIf,
+    "|&": PipeAnd,
     "||": OrIf,
     ";;": DoubleSemicolon,
     "<": Less,
@@ -51,5 +58,13 @@ TokenEnum!(
     "{": LeftBrace,
     "}": RightBrace,
     "!": Bang,
-    "in": In
+    "in": In,
+    "time": Time
 );
+
+impl ReservedWord {
+    /// Returns the reserved word spelled by text, if any
+    pub fn lookup(text: &str) -> Option<Self> {
+        RESERVED_WORDS.lookup(text)
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::{Display, Error, Formatter};
 
 /// A position within the input text
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Copy)]
 pub struct Pos {
     pub line: usize,
     pub col: usize,
@@ -21,7 +21,7 @@ impl Display for Pos {
 
 /// A token may span multiple positions; this struct
 /// represents the span of such a thing.
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Copy)]
 pub struct Span {
     pub start: Pos,
     pub end: Pos,
@@ -8,10 +8,16 @@ use std::io::{BufRead, BufReader, Read};
 lazy_static! {
     static ref IO_NUMBER_RE: Regex =
         Regex::new(r"^[0-9]+[<>]").expect("failed to compile IO_NUMBER_RE");
-    static ref ASSIGNMENT_WORD_RE: Regex =
-        Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*=").expect("failed to compile ASSIGNMENT_WORD_RE");
+    static ref IO_VAR_NAME_RE: Regex =
+        Regex::new(r"^\{([a-zA-Z_][a-zA-Z0-9_]*)\}[<>]").expect("failed to compile IO_VAR_NAME_RE");
+    static ref ASSIGNMENT_WORD_RE: Regex = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(\[([^\]]*)\])?=")
+        .expect("failed to compile ASSIGNMENT_WORD_RE");
 }
 
+/// The name, the subscript text and its position, and the span
+/// of an assignment word
+pub type AssignmentWord = (String, Option<(String, Pos)>, Span);
+
 pub struct CharReader<R: Read> {
     stream: BufReader<R>,
     line_buffer: String,
@@ -125,6 +131,35 @@ impl<R: Read> CharReader<R> {
         }
     }
 
+    pub fn matches_io_var_name(&mut self) -> anyhow::Result<bool> {
+        match self.check_and_fill_buffer() {
+            Next::Eof(_) => Ok(false),
+            Next::Error(err, pos) => Err(err.context(pos)),
+            _ => Ok(IO_VAR_NAME_RE.is_match(&self.line_buffer[self.line_idx..])),
+        }
+    }
+
+    /// Consumes `{name}` from `{name}>` and returns name
+    pub fn next_io_var_name(&mut self) -> anyhow::Result<Option<(String, Span)>> {
+        match self.check_and_fill_buffer() {
+            Next::Eof(_) => Ok(None),
+            Next::Error(err, pos) => Err(err.context(pos)),
+            _ => {
+                if let Some(caps) = IO_VAR_NAME_RE.captures(&self.line_buffer[self.line_idx..]) {
+                    let name = caps.get(1).unwrap().as_str().to_owned();
+                    let len = name.len() + 2;
+                    let start = self.position;
+                    let end = Pos::new(start.line, start.col + len - 1);
+                    self.line_idx += len;
+                    self.position.col += len;
+                    Ok(Some((name, Span::new(start, end))))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
     pub fn matches_assignment_word(&mut self) -> anyhow::Result<bool> {
         match self.check_and_fill_buffer() {
             Next::Eof(_) => Ok(false),
@@ -133,20 +168,28 @@ impl<R: Read> CharReader<R> {
         }
     }
 
-    pub fn next_assignment_word(&mut self) -> anyhow::Result<Option<(String, Span)>> {
+    /// Consumes `name=` or `name[subscript]=`, returning the name
+    /// and the subscript text along with its starting position
+    pub fn next_assignment_word(&mut self) -> anyhow::Result<Option<AssignmentWord>> {
         match self.check_and_fill_buffer() {
             Next::Eof(_) => Ok(None),
-            Next::Error(err, pos) => return Err(err.context(pos).into()),
+            Next::Error(err, pos) => Err(err.context(pos)),
             _ => {
-                if let Some(m) = ASSIGNMENT_WORD_RE.find(&self.line_buffer[self.line_idx..]) {
-                    let num_str = m.as_str();
-                    let len = m.end() - 1;
-                    let name = num_str[..len].to_string();
+                if let Some(caps) = ASSIGNMENT_WORD_RE.captures(&self.line_buffer[self.line_idx..])
+                {
+                    let len = caps.get(0).unwrap().end();
+                    let name = caps.get(1).unwrap().as_str().to_string();
                     let start = self.position;
-                    let end = Pos::new(start.line, start.col + len + 1);
-                    self.line_idx += len + 1;
-                    self.position.col += len + 1;
-                    Ok(Some((name, Span::new(start, end))))
+                    let subscript = caps.get(3).map(|m| {
+                        (
+                            m.as_str().to_string(),
+                            Pos::new(start.line, start.col + m.start()),
+                        )
+                    });
+                    let end = Pos::new(start.line, start.col + len);
+                    self.line_idx += len;
+                    self.position.col += len;
+                    Ok(Some((name, subscript, Span::new(start, end))))
                 } else {
                     Ok(None)
                 }
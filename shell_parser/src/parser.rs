@@ -1,6 +1,8 @@
 use crate::types::*;
 use anyhow::{bail, Error};
-use shell_lexer::{Lexer, Operator, ReservedWord, Token};
+use shell_lexer::{
+    Lexer, Operator, Pos, ReservedWord, Span, Token, WordComponent, WordComponentKind,
+};
 use std::collections::VecDeque;
 use std::io::Read;
 use thiserror::*;
@@ -9,8 +11,10 @@ use thiserror::*;
 pub enum ParseErrorContext {
     List,
     PipelineStartingWithBang,
+    PipelineStartingWithTime,
     PipeSequence,
     IoFileAfterIoNumber,
+    IoFileAfterIoVarName,
     FileNameAfterRedirectionOperator,
     ExpectingPipelineAfter(Operator),
     FdRedirectionExpectsNumber,
@@ -26,9 +30,45 @@ pub enum ParseErrorKind {
     UnexpectedToken(Token, ParseErrorContext),
 }
 
+/// The operand of the `>&` and `<&` redirection operators
+enum FdOperand {
+    /// `>&2`
+    Number(usize),
+    /// `>&2-`
+    Move(usize),
+    /// `>&-`
+    Close,
+    /// `>&$fd`
+    Word(Vec<WordComponent>),
+}
+
 pub struct Parser<R: Read> {
     lexer: Lexer<R>,
     lookahead: VecDeque<Token>,
+    /// The end positions of the most recently consumed tokens,
+    /// so that the spans of the nodes that they complete can be
+    /// determined, even after some have been put back
+    consumed_ends: VecDeque<Pos>,
+}
+
+/// How many of the consumed_ends are retained; this only needs
+/// to be more than the number of tokens that are ever put back
+const CONSUMED_ENDS: usize = 8;
+
+/// Returns the span of the whole of a token.  Token::span only
+/// covers the first component of a word.
+fn token_span(tok: &Token) -> Span {
+    match tok {
+        Token::Word(word) => match word.last() {
+            Some(last) => Span::new(word[0].span.start, last.span.end),
+            None => tok.span(),
+        },
+        Token::Assignment(assign) => match assign.value.last() {
+            Some(last) => Span::new(assign.span.start, last.span.end),
+            None => assign.span,
+        },
+        _ => tok.span(),
+    }
 }
 
 impl<R: Read> Parser<R> {
@@ -37,6 +77,7 @@ impl<R: Read> Parser<R> {
         Self {
             lexer,
             lookahead: VecDeque::new(),
+            consumed_ends: VecDeque::new(),
         }
     }
 
@@ -46,6 +87,24 @@ impl<R: Read> Parser<R> {
     }
 }
 
+impl Parser<&'static [u8]> {
+    /// Create a parser that consumes an already lexed sequence of
+    /// tokens, such as the body of a process substitution.
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        let end = tokens
+            .last()
+            .map(|tok| tok.span().end)
+            .unwrap_or_else(|| Pos::new(0, 0));
+        let mut lookahead: VecDeque<Token> = tokens.into();
+        lookahead.push_back(Token::Eof(end));
+        Self {
+            lexer: Lexer::new(&b""[..]),
+            lookahead,
+            consumed_ends: VecDeque::new(),
+        }
+    }
+}
+
 impl<R: Read> Parser<R> {
     fn unexpected_next_token(&mut self, context: ParseErrorContext) -> Error {
         match self.next_token() {
@@ -83,19 +142,39 @@ impl<R: Read> Parser<R> {
 
     /// Consume the next token
     fn next_token(&mut self) -> anyhow::Result<Token> {
-        if let Some(tok) = self.lookahead.pop_front() {
-            Ok(tok)
+        let tok = if let Some(tok) = self.lookahead.pop_front() {
+            tok
         } else {
-            self.lexer.next_token()
+            self.lexer.next_token()?
+        };
+        if self.consumed_ends.len() == CONSUMED_ENDS {
+            self.consumed_ends.pop_front();
         }
+        self.consumed_ends.push_back(token_span(&tok).end);
+        Ok(tok)
     }
 
     /// Place a token into the lookahead.
     /// The lookahead must be vacant, or else this will cause
     /// a panic; we only support a single lookahead.
     fn unget_token(&mut self, tok: Token) {
+        self.consumed_ends.pop_back();
         self.lookahead.push_front(tok);
     }
+
+    /// Returns the position at which the next token starts
+    fn next_token_start(&mut self) -> anyhow::Result<Pos> {
+        let tok = self.next_token()?;
+        let start = tok.span().start;
+        self.unget_token(tok);
+        Ok(start)
+    }
+
+    /// Returns the span from start through to the end of the most
+    /// recently consumed token
+    fn span_from(&self, start: Pos) -> Span {
+        Span::new(start, self.consumed_ends.back().copied().unwrap_or(start))
+    }
 }
 
 impl<R: Read> Parser<R> {
@@ -125,8 +204,9 @@ impl<R: Read> Parser<R> {
             Ok(commands.pop().unwrap())
         } else {
             let is_async = commands.last().unwrap().asynchronous;
+            let span = Span::new(commands[0].span.start, commands.last().unwrap().span.end);
 
-            let mut command: Command = CommandType::Program(CompoundList { commands }).into();
+            let mut command: Command = CommandType::Program(CompoundList { commands, span }).into();
             command.asynchronous = is_async;
             Ok(command)
         }
@@ -158,6 +238,8 @@ impl<R: Read> Parser<R> {
         .into();
         let condition: CompoundList = Command::from(condition).into();
 
+        let span = Span::new(condition.span.start, then.span.end);
+
         let (true_part, false_part) = if op == Operator::AndIf {
             (Some(then), None)
         } else {
@@ -166,18 +248,41 @@ impl<R: Read> Parser<R> {
 
         Ok(Some(
             CommandType::If(If {
-                condition: condition.into(),
+                condition,
                 true_part,
                 false_part,
+                span,
             })
             .into(),
         ))
     }
 
     fn pipeline(&mut self) -> anyhow::Result<Option<Pipeline>> {
+        let start = self.next_token_start()?;
+        let timed = self.next_token_is_reserved_word(ReservedWord::Time)?;
+        let posix_time_format = if timed {
+            let t = self.next_token()?;
+            if t.as_single_literal_word_string() == Some("-p") {
+                true
+            } else {
+                self.unget_token(t);
+                false
+            }
+        } else {
+            false
+        };
         let inverted = self.next_token_is_reserved_word(ReservedWord::Bang)?;
         if let Some(commands) = self.pipe_sequence()? {
-            Ok(Some(Pipeline { inverted, commands }))
+            let span = Span::new(start, commands.last().unwrap().span.end);
+            Ok(Some(Pipeline {
+                inverted,
+                timed,
+                posix_time_format,
+                commands,
+                span,
+            }))
+        } else if timed {
+            Err(self.unexpected_next_token(ParseErrorContext::PipelineStartingWithTime))
         } else if inverted {
             Err(self.unexpected_next_token(ParseErrorContext::PipelineStartingWithBang))
         } else {
@@ -193,7 +298,21 @@ impl<R: Read> Parser<R> {
 
         let mut commands = vec![command];
 
-        while self.next_token_is_operator(&[Operator::Pipe])?.is_some() {
+        while let Some(pipe) = self.next_token_is_operator(&[Operator::Pipe, Operator::PipeAnd])? {
+            if let Token::Operator(Operator::PipeAnd, ..) = pipe {
+                // `|&` is shorthand for `2>&1 |`; the duplication takes
+                // place after any redirections specified by the command
+                let dup = Redirection::Fd(FdDuplication {
+                    src_fd_number: 1,
+                    dest_fd_number: 2,
+                    span: pipe.span(),
+                });
+                let command = commands.last_mut().unwrap();
+                match &mut command.command {
+                    CommandType::SimpleCommand(simple) => simple.redirects.push(dup),
+                    _ => command.redirects.push(dup),
+                }
+            }
             self.linebreak()?;
             match self.command()? {
                 Some(cmd) => commands.push(cmd),
@@ -209,6 +328,7 @@ impl<R: Read> Parser<R> {
     }
 
     fn command(&mut self) -> anyhow::Result<Option<Command>> {
+        let start = self.next_token_start()?;
         if let Some(command) = self.function_definition()? {
             Ok(Some(command))
         } else if let Some(cmd) = self.compound_command()? {
@@ -218,13 +338,10 @@ impl<R: Read> Parser<R> {
                 command: CommandType::Subshell(group),
                 asynchronous: false,
                 redirects: vec![],
+                span: self.span_from(start),
             }))
         } else if let Some(command) = self.simple_command()? {
-            Ok(Some(Command {
-                command: CommandType::SimpleCommand(command),
-                asynchronous: false,
-                redirects: vec![],
-            }))
+            Ok(Some(CommandType::SimpleCommand(command).into()))
         } else {
             Ok(None)
         }
@@ -249,12 +366,14 @@ impl<R: Read> Parser<R> {
 
             if let Some(cmd) = self.compound_command()? {
                 Ok(Some(Command {
+                    span: Span::new(fname.span().start, cmd.span.end),
                     command: CommandType::FunctionDefinition {
                         name: fname
                             .as_single_literal_word_string()
                             .expect("already verified fname is single literal")
                             .to_owned(),
                         body: Box::new(cmd),
+                        span: fname.span(),
                     },
                     asynchronous: false,
                     redirects: vec![],
@@ -285,34 +404,29 @@ impl<R: Read> Parser<R> {
     }
 
     fn compound_command(&mut self) -> anyhow::Result<Option<Command>> {
-        let mut command = if let Some(group) = self.brace_group()? {
-            Command {
-                command: CommandType::BraceGroup(group),
-                asynchronous: false,
-                redirects: vec![],
-            }
+        let start = self.next_token_start()?;
+        let command = if let Some(group) = self.brace_group()? {
+            CommandType::BraceGroup(group)
         } else if let Some(group) = self.subshell()? {
-            Command {
-                command: CommandType::Subshell(group),
-                asynchronous: false,
-                redirects: vec![],
-            }
+            CommandType::Subshell(group)
         } else if let Some(if_) = self.if_clause()? {
-            Command {
-                command: CommandType::If(if_),
-                asynchronous: false,
-                redirects: vec![],
-            }
+            CommandType::If(if_)
         } else {
             // TODO: for_clause, case_clause, while_clause, until_clause
             return Ok(None);
         };
 
-        command.redirects = self.redirect_list()?;
-        Ok(Some(command))
+        let redirects = self.redirect_list()?;
+        Ok(Some(Command {
+            command,
+            asynchronous: false,
+            redirects,
+            span: self.span_from(start),
+        }))
     }
 
     fn if_clause(&mut self) -> anyhow::Result<Option<If>> {
+        let start = self.next_token_start()?;
         if !self.next_token_is_reserved_word(ReservedWord::If)? {
             return Ok(None);
         }
@@ -330,11 +444,13 @@ impl<R: Read> Parser<R> {
                 condition,
                 true_part: Some(true_part),
                 false_part,
+                span: self.span_from(start),
             }))
         }
     }
 
     fn else_part(&mut self) -> anyhow::Result<Option<CompoundList>> {
+        let start = self.next_token_start()?;
         if self.next_token_is_reserved_word(ReservedWord::Else)? {
             let false_part = self.compound_list()?;
             Ok(Some(false_part))
@@ -346,17 +462,19 @@ impl<R: Read> Parser<R> {
             let true_part = self.compound_list()?;
             let false_part = self.else_part()?;
 
-            Ok(Some(CompoundList {
-                commands: vec![Command {
-                    asynchronous: false,
-                    command: CommandType::If(If {
-                        condition,
-                        true_part: Some(true_part),
-                        false_part,
-                    }),
-                    redirects: vec![],
-                }],
-            }))
+            // The `elif` clause extends to the end of the clauses that
+            // follow it; the `fi` belongs to the enclosing `if`
+            let end = false_part.as_ref().unwrap_or(&true_part).span.end;
+            let span = Span::new(start, end);
+            Ok(Some(
+                Command::from(CommandType::If(If {
+                    condition,
+                    true_part: Some(true_part),
+                    false_part,
+                    span,
+                }))
+                .into(),
+            ))
         } else {
             Ok(None)
         }
@@ -388,6 +506,7 @@ impl<R: Read> Parser<R> {
     }
 
     fn compound_list(&mut self) -> anyhow::Result<CompoundList> {
+        let start = self.next_token_start()?;
         let mut commands = vec![];
 
         loop {
@@ -402,7 +521,11 @@ impl<R: Read> Parser<R> {
             self.separator()?;
         }
 
-        Ok(CompoundList { commands })
+        let span = match (commands.first(), commands.last()) {
+            (Some(first), Some(last)) => Span::new(first.span.start, last.span.end),
+            _ => start.into(),
+        };
+        Ok(CompoundList { commands, span })
     }
 
     fn brace_group(&mut self) -> anyhow::Result<Option<CompoundList>> {
@@ -423,29 +546,54 @@ impl<R: Read> Parser<R> {
         let mut redirections = vec![];
         loop {
             if let Some(redir) = self.io_redirect()? {
-                redirections.push(redir);
+                redirections.extend(redir);
             } else {
                 return Ok(redirections);
             }
         }
     }
 
-    fn io_redirect(&mut self) -> anyhow::Result<Option<Redirection>> {
+    /// Parse a single redirection.  The shorthand forms such as `&>`
+    /// are returned as the equivalent sequence of long-hand redirections.
+    fn io_redirect(&mut self) -> anyhow::Result<Option<Vec<Redirection>>> {
         let t = self.next_token()?;
+        let start = t.span().start;
         if let Token::IoNumber(fd_number, ..) = &t {
-            match self.io_file(Some(*fd_number))? {
+            match self.io_file(Some(*fd_number), Some(start))? {
                 Some(redir) => return Ok(Some(redir)),
                 None => {
                     return Err(self.unexpected_next_token(ParseErrorContext::IoFileAfterIoNumber));
                 }
             }
         }
+        if let Token::IoVarName(name, ..) = &t {
+            match self.io_file(None, Some(start))? {
+                Some(mut redir) if redir.len() == 1 => {
+                    return Ok(Some(vec![Redirection::NamedFd(NamedFdRedirection {
+                        name: name.clone(),
+                        redirection: Box::new(redir.remove(0)),
+                        span: self.span_from(start),
+                    })]));
+                }
+                _ => {
+                    return Err(self.unexpected_next_token(ParseErrorContext::IoFileAfterIoVarName));
+                }
+            }
+        }
         self.unget_token(t);
-        self.io_file(None)
+        self.io_file(None, None)
     }
 
-    fn io_file(&mut self, fd_number: Option<usize>) -> anyhow::Result<Option<Redirection>> {
+    /// Parse a redirection operator and its operand.  start is the
+    /// position of the descriptor number or name that preceded the
+    /// operator, if any.
+    fn io_file(
+        &mut self,
+        fd_number: Option<usize>,
+        start: Option<Pos>,
+    ) -> anyhow::Result<Option<Vec<Redirection>>> {
         let t = self.next_token()?;
+        let start = start.unwrap_or_else(|| t.span().start);
         let oper = if let Token::Operator(oper, ..) = &t {
             match oper {
                 Operator::Less
@@ -454,7 +602,10 @@ impl<R: Read> Parser<R> {
                 | Operator::GreatAnd
                 | Operator::DoubleGreat
                 | Operator::LessGreat
-                | Operator::Clobber => oper,
+                | Operator::Clobber
+                | Operator::TripleLess
+                | Operator::AndGreat
+                | Operator::AndDoubleGreat => oper,
                 _ => {
                     self.unget_token(t);
                     return Ok(None);
@@ -467,25 +618,44 @@ impl<R: Read> Parser<R> {
 
         match oper {
             Operator::GreatAnd | Operator::LessAnd => {
-                if let Some(src_fd_number) = self.number()? {
-                    let dest_fd_number =
-                        fd_number.unwrap_or(if *oper == Operator::GreatAnd { 1 } else { 0 });
-                    return Ok(Some(Redirection::Fd(FdDuplication {
+                let dest_fd_number =
+                    fd_number.unwrap_or(if *oper == Operator::GreatAnd { 1 } else { 0 });
+                let operand = self.fd_operand()?;
+                let span = self.span_from(start);
+                let redir = match operand {
+                    Some(FdOperand::Number(src_fd_number)) => Redirection::Fd(FdDuplication {
                         src_fd_number,
                         dest_fd_number,
-                    })));
-                } else {
-                    return Err(
-                        self.unexpected_next_token(ParseErrorContext::FdRedirectionExpectsNumber)
-                    );
-                }
+                        span,
+                    }),
+                    Some(FdOperand::Move(src_fd_number)) => Redirection::Move(FdDuplication {
+                        src_fd_number,
+                        dest_fd_number,
+                        span,
+                    }),
+                    Some(FdOperand::Close) => Redirection::Close(FdClose {
+                        fd_number: dest_fd_number,
+                        span,
+                    }),
+                    Some(FdOperand::Word(src_word)) => Redirection::FdWord(FdWordDuplication {
+                        src_word,
+                        dest_fd_number,
+                        span,
+                    }),
+                    None => {
+                        return Err(self
+                            .unexpected_next_token(ParseErrorContext::FdRedirectionExpectsNumber));
+                    }
+                };
+                return Ok(Some(vec![redir]));
             }
             _ => {}
         }
 
         let file_name = self.next_token()?;
         if let Token::Word(file_name) = file_name {
-            Ok(Some(match oper {
+            let span = self.span_from(start);
+            Ok(Some(vec![match oper {
                 Operator::Less => Redirection::File(FileRedirection {
                     fd_number: fd_number.unwrap_or(0),
                     file_name,
@@ -493,6 +663,7 @@ impl<R: Read> Parser<R> {
                     output: false,
                     clobber: false,
                     append: false,
+                    span,
                 }),
                 Operator::LessGreat => Redirection::File(FileRedirection {
                     fd_number: fd_number.unwrap_or(0),
@@ -501,6 +672,7 @@ impl<R: Read> Parser<R> {
                     output: true,
                     clobber: false,
                     append: false,
+                    span,
                 }),
                 Operator::Great => Redirection::File(FileRedirection {
                     fd_number: fd_number.unwrap_or(1),
@@ -509,6 +681,7 @@ impl<R: Read> Parser<R> {
                     output: true,
                     clobber: false,
                     append: false,
+                    span,
                 }),
                 Operator::DoubleGreat => Redirection::File(FileRedirection {
                     fd_number: fd_number.unwrap_or(1),
@@ -517,6 +690,7 @@ impl<R: Read> Parser<R> {
                     output: true,
                     clobber: false,
                     append: true,
+                    span,
                 }),
                 Operator::Clobber => Redirection::File(FileRedirection {
                     fd_number: fd_number.unwrap_or(1),
@@ -525,9 +699,34 @@ impl<R: Read> Parser<R> {
                     output: true,
                     clobber: true,
                     append: false,
+                    span,
+                }),
+                Operator::AndGreat | Operator::AndDoubleGreat => {
+                    // Equivalent to `>file 2>&1` or `>>file 2>&1`
+                    return Ok(Some(vec![
+                        Redirection::File(FileRedirection {
+                            fd_number: 1,
+                            file_name,
+                            input: false,
+                            output: true,
+                            clobber: false,
+                            append: *oper == Operator::AndDoubleGreat,
+                            span,
+                        }),
+                        Redirection::Fd(FdDuplication {
+                            src_fd_number: 1,
+                            dest_fd_number: 2,
+                            span,
+                        }),
+                    ]));
+                }
+                Operator::TripleLess => Redirection::HereString(HereString {
+                    fd_number: fd_number.unwrap_or(0),
+                    word: file_name,
+                    span,
                 }),
                 _ => bail!("impossible redirection oper {:?}", oper),
-            }))
+            }]))
         } else {
             self.unget_token(file_name);
             Err(self.unexpected_next_token(ParseErrorContext::FileNameAfterRedirectionOperator))
@@ -537,6 +736,7 @@ impl<R: Read> Parser<R> {
     fn simple_command(&mut self) -> anyhow::Result<Option<SimpleCommand>> {
         let mut assignments = vec![];
         let mut words = vec![];
+        let mut array_arguments = vec![];
         let mut redirects = vec![];
 
         // Apply shell grammar rule 1: the first word of a command, if it
@@ -544,6 +744,7 @@ impl<R: Read> Parser<R> {
         // command
         let tok = self.next_token()?;
         let is_reserved = tok.is_any_reserved_word();
+        let start = tok.span().start;
         self.unget_token(tok);
         if is_reserved {
             return Ok(None);
@@ -551,7 +752,7 @@ impl<R: Read> Parser<R> {
 
         loop {
             if let Some(redir) = self.io_redirect()? {
-                redirects.push(redir);
+                redirects.extend(redir);
                 continue;
             }
 
@@ -561,6 +762,9 @@ impl<R: Read> Parser<R> {
                     if words.is_empty() {
                         assignments.push(assign.clone());
                     } else {
+                        if assign.elements.is_some() {
+                            array_arguments.push((words.len(), assign.clone()));
+                        }
                         words.push(assign.into());
                     }
                 }
@@ -587,6 +791,8 @@ impl<R: Read> Parser<R> {
             assignments,
             redirects,
             words,
+            array_arguments,
+            span: self.span_from(start),
         }))
     }
 }
@@ -618,11 +824,33 @@ impl<R: Read> Parser<R> {
     /// This is used in fd redirection for constructs
     /// like `1>&1`.  The first number is recognized as
     /// an IoNumber and we are called for the second number.
-    fn number(&mut self) -> anyhow::Result<Option<usize>> {
+    /// Matches the operand of `>&` or `<&`: either a descriptor
+    /// number, a descriptor number followed by `-`, just `-`,
+    /// or a word that will expand to a descriptor number
+    fn fd_operand(&mut self) -> anyhow::Result<Option<FdOperand>> {
         let t = self.next_token()?;
+        if let Token::Word(word) = &t {
+            if word
+                .iter()
+                .any(|c| !matches!(c.kind, WordComponentKind::Literal(_)))
+            {
+                return Ok(Some(FdOperand::Word(word.clone())));
+            }
+        }
         if let Some(word) = t.as_single_literal_word_string() {
-            if let Ok(num) = usize::from_str_radix(word, 10) {
-                return Ok(Some(num));
+            if word == "-" {
+                return Ok(Some(FdOperand::Close));
+            }
+            let (digits, move_fd) = match word.strip_suffix('-') {
+                Some(digits) => (digits, true),
+                None => (word, false),
+            };
+            if let Ok(num) = usize::from_str_radix(digits, 10) {
+                return Ok(Some(if move_fd {
+                    FdOperand::Move(num)
+                } else {
+                    FdOperand::Number(num)
+                }));
             }
         }
         self.unget_token(t);
@@ -1,6 +1,8 @@
 use super::*;
 use pretty_assertions::assert_eq;
-use shell_lexer::{Assignment, Pos, Span, Token, WordComponent, WordComponentKind};
+use shell_lexer::{
+    Assignment, ParamExpr, ParamOper, Pos, Span, Token, WordComponent, WordComponentKind,
+};
 
 fn parse(text: &str) -> anyhow::Result<Command> {
     let mut parser = Parser::new(text.as_bytes());
@@ -13,9 +15,12 @@ fn test_assign() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![
                 Assignment {
                     name: "FOO".to_owned(),
+                    subscript: None,
+                    elements: None,
                     span: Span::new_to(0, 0, 4),
                     value: vec![WordComponent {
                         kind: WordComponentKind::literal("bar"),
@@ -26,6 +31,8 @@ fn test_assign() {
                 },
                 Assignment {
                     name: "BAR".to_owned(),
+                    subscript: None,
+                    elements: None,
                     span: Span::new_to(0, 8, 12),
                     value: vec![WordComponent {
                         kind: WordComponentKind::literal("baz"),
@@ -57,7 +64,8 @@ fn test_assign() {
                         remove_backslash: true
                     },
                 ],
-            ]
+            ],
+            span: Span::new_to(0, 0, 29),
         }))
     );
 }
@@ -68,6 +76,7 @@ fn test_parse() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![],
             words: vec![
@@ -89,7 +98,8 @@ fn test_parse() {
                     splittable: true,
                     remove_backslash: true
                 }],
-            ]
+            ],
+            span: Span::new_to(0, 0, 8),
         }))
     );
 }
@@ -102,6 +112,7 @@ fn test_two_lines() {
         Command::from(CommandType::Program(CompoundList {
             commands: vec![
                 Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -109,9 +120,11 @@ fn test_two_lines() {
                         span: Span::new_to(0, 0, 4),
                         splittable: true,
                         remove_backslash: true
-                    }],]
+                    }],],
+                    span: Span::new_to(0, 0, 4),
                 })),
                 Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -119,9 +132,11 @@ fn test_two_lines() {
                         span: Span::new_to(1, 0, 3),
                         splittable: true,
                         remove_backslash: true
-                    }],]
+                    }],],
+                    span: Span::new_to(1, 0, 3),
                 }))
-            ]
+            ],
+            span: Span::new(Pos::new(0, 0), Pos::new(1, 3)),
         }))
     );
 }
@@ -132,6 +147,7 @@ fn redirect_out() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::File(FileRedirection {
                 fd_number: 1,
@@ -144,14 +160,16 @@ fn redirect_out() {
                 input: false,
                 output: true,
                 clobber: false,
-                append: false
+                append: false,
+                span: Span::new_to(0, 5, 8),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 8),
         }))
     );
 }
@@ -162,6 +180,7 @@ fn redirect_append() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::File(FileRedirection {
                 fd_number: 1,
@@ -174,14 +193,16 @@ fn redirect_append() {
                 input: false,
                 output: true,
                 clobber: false,
-                append: true
+                append: true,
+                span: Span::new_to(0, 5, 9),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 9),
         }))
     );
 }
@@ -192,6 +213,7 @@ fn redirect_clobber() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::File(FileRedirection {
                 fd_number: 1,
@@ -205,13 +227,15 @@ fn redirect_clobber() {
                 output: true,
                 clobber: true,
                 append: false,
+                span: Span::new_to(0, 5, 9),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 9),
         }))
     );
 }
@@ -222,6 +246,7 @@ fn redirect_input() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::File(FileRedirection {
                 fd_number: 0,
@@ -235,13 +260,15 @@ fn redirect_input() {
                 output: false,
                 clobber: false,
                 append: false,
+                span: Span::new_to(0, 5, 8),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 8),
         }))
     );
 }
@@ -252,17 +279,20 @@ fn redirect_stderr_stdout() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::Fd(FdDuplication {
                 src_fd_number: 1,
-                dest_fd_number: 2
+                dest_fd_number: 2,
+                span: Span::new_to(0, 5, 8),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 8),
         }))
     );
 }
@@ -273,17 +303,20 @@ fn redirect_dup_for_input() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::Fd(FdDuplication {
                 src_fd_number: 1,
-                dest_fd_number: 0
+                dest_fd_number: 0,
+                span: Span::new_to(0, 5, 8),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 8),
         }))
     );
 }
@@ -294,6 +327,7 @@ fn redirect_input_and_output() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![Redirection::File(FileRedirection {
                 fd_number: 0,
@@ -307,17 +341,199 @@ fn redirect_input_and_output() {
                 output: true,
                 clobber: false,
                 append: false,
+                span: Span::new_to(0, 5, 10),
             })],
             words: vec![vec![WordComponent {
                 kind: WordComponentKind::literal("echo"),
                 span: Span::new_to(0, 0, 3),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 10),
+        }))
+    );
+}
+
+#[test]
+fn redirect_stdout_and_stderr() {
+    let list = parse("echo &>>file").unwrap();
+    assert_eq!(
+        list,
+        Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
+            assignments: vec![],
+            redirects: vec![
+                Redirection::File(FileRedirection {
+                    fd_number: 1,
+                    file_name: vec![WordComponent {
+                        kind: WordComponentKind::literal("file"),
+                        span: Span::new_to(0, 8, 11),
+                        remove_backslash: true,
+                        splittable: true,
+                    }],
+                    input: false,
+                    output: true,
+                    clobber: false,
+                    append: true,
+                    span: Span::new_to(0, 5, 11),
+                }),
+                Redirection::Fd(FdDuplication {
+                    src_fd_number: 1,
+                    dest_fd_number: 2,
+                    span: Span::new_to(0, 5, 11),
+                })
+            ],
+            words: vec![vec![WordComponent {
+                kind: WordComponentKind::literal("echo"),
+                span: Span::new_to(0, 0, 3),
+                splittable: true,
+                remove_backslash: true
+            }],],
+            span: Span::new_to(0, 0, 11),
+        }))
+    );
+}
+
+#[test]
+fn redirect_here_string() {
+    let list = parse("cat 3<<< word").unwrap();
+    assert_eq!(
+        list,
+        Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
+            assignments: vec![],
+            redirects: vec![Redirection::HereString(HereString {
+                fd_number: 3,
+                word: vec![WordComponent {
+                    kind: WordComponentKind::literal("word"),
+                    span: Span::new_to(0, 9, 12),
+                    remove_backslash: true,
+                    splittable: true,
+                }],
+                span: Span::new_to(0, 4, 12),
+            })],
+            words: vec![vec![WordComponent {
+                kind: WordComponentKind::literal("cat"),
+                span: Span::new_to(0, 0, 2),
+                splittable: true,
+                remove_backslash: true
+            }],],
+            span: Span::new_to(0, 0, 12),
         }))
     );
 }
 
+#[test]
+fn pipe_stderr() {
+    let list = parse("foo >out |& bar").unwrap();
+    let commands = match list.command {
+        CommandType::Pipeline(pipeline) => pipeline.commands,
+        _ => panic!("expected a pipeline, got {:?}", list),
+    };
+    assert_eq!(commands.len(), 2);
+    match &commands[0].command {
+        CommandType::SimpleCommand(simple) => assert_eq!(
+            simple.redirects[1],
+            Redirection::Fd(FdDuplication {
+                src_fd_number: 1,
+                dest_fd_number: 2,
+                span: Span::new_to(0, 9, 10),
+            })
+        ),
+        cmd => panic!("expected a simple command, got {:?}", cmd),
+    }
+    assert!(commands[0].asynchronous);
+}
+
+#[test]
+fn redirect_close_and_move() {
+    let list = parse("echo >&- 3>&1-").unwrap();
+    assert_eq!(
+        list,
+        Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
+            assignments: vec![],
+            redirects: vec![
+                Redirection::Close(FdClose {
+                    fd_number: 1,
+                    span: Span::new_to(0, 5, 7)
+                }),
+                Redirection::Move(FdDuplication {
+                    src_fd_number: 1,
+                    dest_fd_number: 3,
+                    span: Span::new_to(0, 9, 13),
+                })
+            ],
+            words: vec![vec![WordComponent {
+                kind: WordComponentKind::literal("echo"),
+                span: Span::new_to(0, 0, 3),
+                splittable: true,
+                remove_backslash: true
+            }],],
+            span: Span::new_to(0, 0, 13),
+        }))
+    );
+
+    let list = parse("echo <&-").unwrap();
+    match list.command {
+        CommandType::SimpleCommand(simple) => assert_eq!(
+            simple.redirects,
+            vec![Redirection::Close(FdClose {
+                fd_number: 0,
+                span: Span::new_to(0, 5, 7)
+            })]
+        ),
+        cmd => panic!("expected a simple command, got {:?}", cmd),
+    }
+}
+
+#[test]
+fn redirect_named_fd() {
+    let list = parse("echo {log}>file >&$log").unwrap();
+    let redirects = match list.command {
+        CommandType::SimpleCommand(simple) => simple.redirects,
+        cmd => panic!("expected a simple command, got {:?}", cmd),
+    };
+    assert_eq!(
+        redirects,
+        vec![
+            Redirection::NamedFd(NamedFdRedirection {
+                name: "log".to_owned(),
+                redirection: Box::new(Redirection::File(FileRedirection {
+                    fd_number: 1,
+                    file_name: vec![WordComponent {
+                        kind: WordComponentKind::literal("file"),
+                        span: Span::new_to(0, 11, 14),
+                        remove_backslash: true,
+                        splittable: true,
+                    }],
+                    input: false,
+                    output: true,
+                    clobber: false,
+                    append: false,
+                    span: Span::new_to(0, 5, 14),
+                })),
+                span: Span::new_to(0, 5, 14),
+            }),
+            Redirection::FdWord(FdWordDuplication {
+                src_word: vec![WordComponent {
+                    kind: WordComponentKind::ParamExpand(ParamExpr {
+                        kind: ParamOper::Get,
+                        name: "log".to_owned(),
+                        subscript: None,
+                        word: vec![],
+                    }),
+                    span: Span::new_to(0, 18, 21),
+                    remove_backslash: false,
+                    splittable: true,
+                }],
+                dest_fd_number: 1,
+                span: Span::new_to(0, 16, 21),
+            }),
+        ]
+    );
+}
+
 #[test]
 fn redirect_fd_not_number() {
     assert_eq!(
@@ -356,18 +572,26 @@ fn subshell() {
     let list = parse("(echo)").unwrap();
     assert_eq!(
         list,
-        Command::from(CommandType::Subshell(CompoundList {
-            commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
-                assignments: vec![],
-                redirects: vec![],
-                words: vec![vec![WordComponent {
-                    kind: WordComponentKind::literal("echo"),
+        Command {
+            asynchronous: false,
+            redirects: vec![],
+            command: CommandType::Subshell(CompoundList {
+                commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
+                    assignments: vec![],
+                    redirects: vec![],
+                    words: vec![vec![WordComponent {
+                        kind: WordComponentKind::literal("echo"),
+                        span: Span::new_to(0, 1, 4),
+                        splittable: true,
+                        remove_backslash: true
+                    }],],
                     span: Span::new_to(0, 1, 4),
-                    splittable: true,
-                    remove_backslash: true
-                }],]
-            }))]
-        }))
+                }))],
+                span: Span::new_to(0, 1, 4),
+            }),
+            span: Span::new_to(0, 0, 5),
+        }
     );
 }
 
@@ -390,9 +614,11 @@ fn subshell_redirected() {
                 output: true,
                 clobber: false,
                 append: false,
+                span: Span::new_to(0, 6, 9),
             })],
             command: CommandType::Subshell(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -400,9 +626,12 @@ fn subshell_redirected() {
                         span: Span::new_to(0, 1, 4),
                         splittable: true,
                         remove_backslash: true
-                    }],]
-                }))]
-            })
+                    }],],
+                    span: Span::new_to(0, 1, 4),
+                }))],
+                span: Span::new_to(0, 1, 4),
+            }),
+            span: Span::new_to(0, 0, 9),
         }
     );
 }
@@ -418,6 +647,7 @@ fn brace_group_no_spaces() {
     assert_eq!(
         list,
         Command::from(CommandType::SimpleCommand(SimpleCommand {
+            array_arguments: vec![],
             assignments: vec![],
             redirects: vec![],
             words: vec![vec![WordComponent {
@@ -425,7 +655,8 @@ fn brace_group_no_spaces() {
                 span: Span::new_to(0, 0, 5),
                 splittable: true,
                 remove_backslash: true
-            }],]
+            }],],
+            span: Span::new_to(0, 0, 5),
         }))
     );
 }
@@ -449,18 +680,26 @@ fn brace_group() {
     let list = parse("{ echo }").unwrap();
     assert_eq!(
         list,
-        Command::from(CommandType::BraceGroup(CompoundList {
-            commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
-                assignments: vec![],
-                redirects: vec![],
-                words: vec![vec![WordComponent {
-                    kind: WordComponentKind::literal("echo"),
+        Command {
+            asynchronous: false,
+            redirects: vec![],
+            command: CommandType::BraceGroup(CompoundList {
+                commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
+                    assignments: vec![],
+                    redirects: vec![],
+                    words: vec![vec![WordComponent {
+                        kind: WordComponentKind::literal("echo"),
+                        span: Span::new_to(0, 2, 5),
+                        splittable: true,
+                        remove_backslash: true
+                    }],],
                     span: Span::new_to(0, 2, 5),
-                    splittable: true,
-                    remove_backslash: true
-                }],]
-            }))]
-        }))
+                }))],
+                span: Span::new_to(0, 2, 5),
+            }),
+            span: Span::new_to(0, 0, 7),
+        }
     );
 }
 
@@ -469,30 +708,40 @@ fn brace_group_sep_list() {
     let list = parse("{ echo ; boo }").unwrap();
     assert_eq!(
         list,
-        Command::from(CommandType::BraceGroup(CompoundList {
-            commands: vec![
-                Command::from(CommandType::SimpleCommand(SimpleCommand {
-                    assignments: vec![],
-                    redirects: vec![],
-                    words: vec![vec![WordComponent {
-                        kind: WordComponentKind::literal("echo"),
+        Command {
+            asynchronous: false,
+            redirects: vec![],
+            command: CommandType::BraceGroup(CompoundList {
+                commands: vec![
+                    Command::from(CommandType::SimpleCommand(SimpleCommand {
+                        array_arguments: vec![],
+                        assignments: vec![],
+                        redirects: vec![],
+                        words: vec![vec![WordComponent {
+                            kind: WordComponentKind::literal("echo"),
+                            span: Span::new_to(0, 2, 5),
+                            splittable: true,
+                            remove_backslash: true
+                        }],],
                         span: Span::new_to(0, 2, 5),
-                        splittable: true,
-                        remove_backslash: true
-                    }],]
-                })),
-                Command::from(CommandType::SimpleCommand(SimpleCommand {
-                    assignments: vec![],
-                    redirects: vec![],
-                    words: vec![vec![WordComponent {
-                        kind: WordComponentKind::literal("boo"),
+                    })),
+                    Command::from(CommandType::SimpleCommand(SimpleCommand {
+                        array_arguments: vec![],
+                        assignments: vec![],
+                        redirects: vec![],
+                        words: vec![vec![WordComponent {
+                            kind: WordComponentKind::literal("boo"),
+                            span: Span::new_to(0, 9, 11),
+                            splittable: true,
+                            remove_backslash: true
+                        }],],
                         span: Span::new_to(0, 9, 11),
-                        splittable: true,
-                        remove_backslash: true
-                    }],]
-                })),
-            ]
-        }))
+                    })),
+                ],
+                span: Span::new_to(0, 2, 11),
+            }),
+            span: Span::new_to(0, 0, 13),
+        }
     );
 }
 
@@ -501,30 +750,40 @@ fn brace_group_sep_newlien() {
     let list = parse("{\n\techo\n\tboo\n}").unwrap();
     assert_eq!(
         list,
-        Command::from(CommandType::BraceGroup(CompoundList {
-            commands: vec![
-                Command::from(CommandType::SimpleCommand(SimpleCommand {
-                    assignments: vec![],
-                    redirects: vec![],
-                    words: vec![vec![WordComponent {
-                        kind: WordComponentKind::literal("echo"),
+        Command {
+            asynchronous: false,
+            redirects: vec![],
+            command: CommandType::BraceGroup(CompoundList {
+                commands: vec![
+                    Command::from(CommandType::SimpleCommand(SimpleCommand {
+                        array_arguments: vec![],
+                        assignments: vec![],
+                        redirects: vec![],
+                        words: vec![vec![WordComponent {
+                            kind: WordComponentKind::literal("echo"),
+                            span: Span::new_to(1, 1, 4),
+                            splittable: true,
+                            remove_backslash: true
+                        }],],
                         span: Span::new_to(1, 1, 4),
-                        splittable: true,
-                        remove_backslash: true
-                    }],]
-                })),
-                Command::from(CommandType::SimpleCommand(SimpleCommand {
-                    assignments: vec![],
-                    redirects: vec![],
-                    words: vec![vec![WordComponent {
-                        kind: WordComponentKind::literal("boo"),
+                    })),
+                    Command::from(CommandType::SimpleCommand(SimpleCommand {
+                        array_arguments: vec![],
+                        assignments: vec![],
+                        redirects: vec![],
+                        words: vec![vec![WordComponent {
+                            kind: WordComponentKind::literal("boo"),
+                            span: Span::new_to(2, 1, 3),
+                            splittable: true,
+                            remove_backslash: true
+                        }],],
                         span: Span::new_to(2, 1, 3),
-                        splittable: true,
-                        remove_backslash: true
-                    }],]
-                })),
-            ]
-        }))
+                    })),
+                ],
+                span: Span::new(Pos::new(1, 1), Pos::new(2, 3)),
+            }),
+            span: Span::new(Pos::new(0, 0), Pos::new(3, 0)),
+        }
     );
 }
 
@@ -536,6 +795,7 @@ fn if_clause() {
         Command::from(CommandType::If(If {
             condition: CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -543,12 +803,15 @@ fn if_clause() {
                         span: Span::new_to(0, 3, 6),
                         splittable: true,
                         remove_backslash: true
-                    }],]
-                }))]
+                    }],],
+                    span: Span::new_to(0, 3, 6),
+                }))],
+                span: Span::new_to(0, 3, 6),
             },
 
             true_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![
@@ -564,11 +827,14 @@ fn if_clause() {
                             splittable: true,
                             remove_backslash: true
                         }],
-                    ]
-                })),]
+                    ],
+                    span: Span::new_to(2, 1, 8),
+                })),],
+                span: Span::new_to(2, 1, 8),
             }),
 
             false_part: None,
+            span: Span::new(Pos::new(0, 0), Pos::new(3, 1)),
         }))
     );
 }
@@ -581,6 +847,7 @@ fn if_clause_with_semicolon() {
         Command::from(CommandType::If(If {
             condition: CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -588,12 +855,15 @@ fn if_clause_with_semicolon() {
                         span: Span::new_to(0, 3, 6),
                         splittable: true,
                         remove_backslash: true
-                    }],]
-                }))]
+                    }],],
+                    span: Span::new_to(0, 3, 6),
+                }))],
+                span: Span::new_to(0, 3, 6),
             },
 
             true_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![
@@ -609,11 +879,14 @@ fn if_clause_with_semicolon() {
                             splittable: true,
                             remove_backslash: true
                         }],
-                    ]
-                })),]
+                    ],
+                    span: Span::new_to(1, 1, 8),
+                })),],
+                span: Span::new_to(1, 1, 8),
             }),
 
             false_part: None,
+            span: Span::new(Pos::new(0, 0), Pos::new(2, 1)),
         }))
     );
 }
@@ -626,6 +899,7 @@ fn if_clause_with_else() {
         Command::from(CommandType::If(If {
             condition: CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -633,12 +907,15 @@ fn if_clause_with_else() {
                         span: Span::new_to(0, 3, 6),
                         splittable: true,
                         remove_backslash: true
-                    }],]
-                }))]
+                    }],],
+                    span: Span::new_to(0, 3, 6),
+                }))],
+                span: Span::new_to(0, 3, 6),
             },
 
             true_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![
@@ -654,12 +931,15 @@ fn if_clause_with_else() {
                             splittable: true,
                             remove_backslash: true
                         }],
-                    ]
-                })),]
+                    ],
+                    span: Span::new_to(2, 1, 8),
+                })),],
+                span: Span::new_to(2, 1, 8),
             }),
 
             false_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![
@@ -675,9 +955,12 @@ fn if_clause_with_else() {
                             splittable: true,
                             remove_backslash: true
                         }],
-                    ]
-                })),]
+                    ],
+                    span: Span::new_to(3, 5, 11),
+                })),],
+                span: Span::new_to(3, 5, 11),
             }),
+            span: Span::new(Pos::new(0, 0), Pos::new(3, 16)),
         }))
     );
 }
@@ -692,6 +975,7 @@ fn if_clause_with_else_if() {
         Command::from(CommandType::If(If {
             condition: CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![vec![WordComponent {
@@ -699,12 +983,15 @@ fn if_clause_with_else_if() {
                         span: Span::new_to(0, 3, 6),
                         splittable: true,
                         remove_backslash: true
-                    }],]
-                }))]
+                    }],],
+                    span: Span::new_to(0, 3, 6),
+                }))],
+                span: Span::new_to(0, 3, 6),
             },
 
             true_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                    array_arguments: vec![],
                     assignments: vec![],
                     redirects: vec![],
                     words: vec![
@@ -720,14 +1007,17 @@ fn if_clause_with_else_if() {
                             splittable: true,
                             remove_backslash: true
                         }],
-                    ]
-                })),]
+                    ],
+                    span: Span::new_to(2, 1, 8),
+                })),],
+                span: Span::new_to(2, 1, 8),
             }),
 
             false_part: Some(CompoundList {
                 commands: vec![Command::from(CommandType::If(If {
                     condition: CompoundList {
                         commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                            array_arguments: vec![],
                             assignments: vec![],
                             redirects: vec![],
                             words: vec![vec![WordComponent {
@@ -735,12 +1025,15 @@ fn if_clause_with_else_if() {
                                 span: Span::new_to(3, 5, 9),
                                 splittable: true,
                                 remove_backslash: true
-                            }],]
-                        }))]
+                            }],],
+                            span: Span::new_to(3, 5, 9),
+                        }))],
+                        span: Span::new_to(3, 5, 9),
                     },
 
                     true_part: Some(CompoundList {
                         commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                            array_arguments: vec![],
                             assignments: vec![],
                             redirects: vec![],
                             words: vec![
@@ -756,12 +1049,15 @@ fn if_clause_with_else_if() {
                                     splittable: true,
                                     remove_backslash: true
                                 }],
-                            ]
-                        })),]
+                            ],
+                            span: Span::new_to(3, 18, 28),
+                        })),],
+                        span: Span::new_to(3, 18, 28),
                     }),
 
                     false_part: Some(CompoundList {
                         commands: vec![Command::from(CommandType::SimpleCommand(SimpleCommand {
+                            array_arguments: vec![],
                             assignments: vec![],
                             redirects: vec![],
                             words: vec![
@@ -777,11 +1073,39 @@ fn if_clause_with_else_if() {
                                     splittable: true,
                                     remove_backslash: true
                                 }],
-                            ]
-                        })),]
+                            ],
+                            span: Span::new_to(3, 37, 43),
+                        })),],
+                        span: Span::new_to(3, 37, 43),
                     }),
-                }))]
-            })
+                    span: Span::new_to(3, 0, 43),
+                }))],
+                span: Span::new_to(3, 0, 43),
+            }),
+            span: Span::new(Pos::new(0, 0), Pos::new(3, 48)),
         }))
     );
 }
+
+#[test]
+fn timed_pipeline() {
+    let list = parse("time -p ! foo | bar").unwrap();
+    let pipeline = match list.command {
+        CommandType::Pipeline(pipeline) => pipeline,
+        _ => panic!("expected a pipeline, got {:?}", list),
+    };
+    assert!(pipeline.timed);
+    assert!(pipeline.posix_time_format);
+    assert!(pipeline.inverted);
+    assert_eq!(pipeline.commands.len(), 2);
+
+    let list = parse("time foo").unwrap();
+    match list.command {
+        CommandType::Pipeline(pipeline) => {
+            assert!(pipeline.timed);
+            assert!(!pipeline.posix_time_format);
+            assert_eq!(pipeline.commands.len(), 1);
+        }
+        _ => panic!("expected a pipeline, got {:?}", list),
+    }
+}
@@ -1,11 +1,16 @@
 /// https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_10_02
-use shell_lexer::{Assignment, WordComponent};
+use shell_lexer::{Assignment, Span, WordComponent};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct SimpleCommand {
     pub assignments: Vec<Assignment>,
     pub words: Vec<Vec<WordComponent>>,
+    /// Compound array assignments that appear as arguments, as in
+    /// `declare -a name=(...)`, each with the index of the word
+    /// that it was converted into
+    pub array_arguments: Vec<(usize, Assignment)>,
     pub redirects: Vec<Redirection>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -13,6 +18,9 @@ pub struct Command {
     pub asynchronous: bool,
     pub command: CommandType,
     pub redirects: Vec<Redirection>,
+    /// The extent of the command, including any keywords and
+    /// delimiters that surround it and its redirections
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -26,7 +34,12 @@ pub enum CommandType {
     If(If),
     UntilLoop(UntilLoop),
     WhileLoop(WhileLoop),
-    FunctionDefinition { name: String, body: Box<Command> },
+    FunctionDefinition {
+        name: String,
+        body: Box<Command>,
+        /// The location of the name of the function
+        span: Span,
+    },
     // TODO: Case
 }
 
@@ -34,12 +47,19 @@ pub enum CommandType {
 pub struct Pipeline {
     /// true if the pipeline starts with a bang
     pub inverted: bool,
+    /// true if the pipeline starts with `time`
+    pub timed: bool,
+    /// true if the pipeline starts with `time -p`, which
+    /// reports using the POSIX format rather than TIMEFORMAT
+    pub posix_time_format: bool,
     pub commands: Vec<Command>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CompoundList {
     pub commands: Vec<Command>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -47,30 +67,56 @@ pub struct If {
     pub condition: CompoundList,
     pub true_part: Option<CompoundList>,
     pub false_part: Option<CompoundList>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UntilLoop {
     pub body: CompoundList,
     pub condition: CompoundList,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WhileLoop {
     pub condition: CompoundList,
     pub body: CompoundList,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ForEach {
     pub wordlist: Vec<Vec<WordComponent>>,
     pub body: CompoundList,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Redirection {
     File(FileRedirection),
     Fd(FdDuplication),
+    /// `dest>&src-`: duplicate src as dest and then close src
+    Move(FdDuplication),
+    Close(FdClose),
+    FdWord(FdWordDuplication),
+    HereString(HereString),
+    NamedFd(NamedFdRedirection),
+}
+
+impl Redirection {
+    /// Returns the extent of the redirection, from its descriptor
+    /// number or operator through to its operand
+    pub fn span(&self) -> Span {
+        match self {
+            Redirection::File(FileRedirection { span, .. })
+            | Redirection::Fd(FdDuplication { span, .. })
+            | Redirection::Move(FdDuplication { span, .. })
+            | Redirection::Close(FdClose { span, .. })
+            | Redirection::FdWord(FdWordDuplication { span, .. })
+            | Redirection::HereString(HereString { span, .. })
+            | Redirection::NamedFd(NamedFdRedirection { span, .. }) => *span,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -85,6 +131,7 @@ pub struct FileRedirection {
     pub clobber: bool,
     /// `>>`
     pub append: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -93,11 +140,73 @@ pub struct FdDuplication {
     pub src_fd_number: usize,
     /// ... into `dest_fd_number` for the child
     pub dest_fd_number: usize,
+    pub span: Span,
+}
+
+/// `>&-` or `<&-`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdClose {
+    pub fd_number: usize,
+    pub span: Span,
+}
+
+/// `>&word` where word expands to a descriptor number
+/// at runtime, for example `>&$fd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdWordDuplication {
+    pub src_word: Vec<WordComponent>,
+    pub dest_fd_number: usize,
+    pub span: Span,
+}
+
+/// `{varname}>file` and similar.  For all but the close
+/// form, `redirection` is applied to a newly allocated descriptor
+/// whose number is then stored in the variable `name`.
+/// The close form, `{varname}>&-`, closes the descriptor whose
+/// number is the value of the variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedFdRedirection {
+    pub name: String,
+    /// The fd_number of this redirection is not used
+    pub redirection: Box<Redirection>,
+    pub span: Span,
+}
+
+/// `<<< word`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereString {
+    pub fd_number: usize,
+    /// The expansion of this word, followed by a newline,
+    /// is made available for reading via `fd_number`
+    pub word: Vec<WordComponent>,
+    pub span: Span,
+}
+
+impl CommandType {
+    /// Returns the extent of the command, which for a compound
+    /// command excludes the keywords or delimiters that surround it
+    pub fn span(&self) -> Span {
+        match self {
+            CommandType::Pipeline(Pipeline { span, .. })
+            | CommandType::SimpleCommand(SimpleCommand { span, .. })
+            | CommandType::Program(CompoundList { span, .. })
+            | CommandType::BraceGroup(CompoundList { span, .. })
+            | CommandType::Subshell(CompoundList { span, .. })
+            | CommandType::ForEach(ForEach { span, .. })
+            | CommandType::If(If { span, .. })
+            | CommandType::UntilLoop(UntilLoop { span, .. })
+            | CommandType::WhileLoop(WhileLoop { span, .. }) => *span,
+            CommandType::FunctionDefinition { body, span, .. } => {
+                Span::new(span.start, body.span.end)
+            }
+        }
+    }
 }
 
 impl From<CommandType> for Command {
     fn from(command: CommandType) -> Command {
         Command {
+            span: command.span(),
             command,
             redirects: vec![],
             asynchronous: false,
@@ -108,7 +217,7 @@ impl From<CommandType> for Command {
 impl From<Pipeline> for Command {
     fn from(pipeline: Pipeline) -> Command {
         // Simplify a pipeline to the command itself if possible
-        if !pipeline.inverted && pipeline.commands.len() == 1 {
+        if !pipeline.inverted && !pipeline.timed && pipeline.commands.len() == 1 {
             pipeline.commands.into_iter().next().unwrap()
         } else {
             CommandType::Pipeline(pipeline).into()
@@ -119,6 +228,7 @@ impl From<Pipeline> for Command {
 impl From<Command> for CompoundList {
     fn from(cmd: Command) -> CompoundList {
         CompoundList {
+            span: cmd.span,
             commands: vec![cmd],
         }
     }
@@ -0,0 +1,327 @@
+//! Evaluation of shell arithmetic expressions, as used for the
+//! assignments to variables that have the integer attribute.
+//! With reference to
+//! https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_04
+use crate::Environment;
+use anyhow::{anyhow, bail};
+
+/// Variables are evaluated as expressions in their own right;
+/// this limits how deeply that may recurse
+const MAX_RECURSION: usize = 32;
+
+/// Limits how deeply parentheses and operators may nest, counting
+/// across the evaluation of any variables, so that a pathological
+/// expression fails rather than overflowing the stack
+const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(isize),
+    Name(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+/// Operators, longest first so that the first match is the correct one
+const OPERATORS: &[&str] = &[
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~",
+];
+
+fn tokenize(expr: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = expr;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_owned()));
+            rest = &rest[len..];
+        } else if c == '(' {
+            tokens.push(Token::LeftParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(Token::RightParen);
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[op.len()..];
+        } else {
+            bail!(
+                "{}: syntax error: invalid arithmetic operator `{}`",
+                expr,
+                c
+            );
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0` prefixed octal number
+fn parse_number(s: &str) -> anyhow::Result<isize> {
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        isize::from_str_radix(hex, 16)
+    } else if s.len() > 1 && s.starts_with('0') {
+        isize::from_str_radix(&s[1..], 8)
+    } else {
+        s.parse::<isize>()
+    };
+    result.map_err(|_| anyhow!("{}: value too great for base", s))
+}
+
+/// Returns the binding power of a binary operator; larger numbers
+/// bind more tightly
+fn binding_power(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+struct Evaluator<'a> {
+    expr: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    env: &'a Environment,
+    depth: usize,
+    nesting: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn syntax_error(&self) -> anyhow::Error {
+        anyhow!(
+            "{}: syntax error in expression (error token is {:?})",
+            self.expr,
+            self.peek()
+        )
+    }
+
+    /// Runs f one nesting level deeper, failing if that exceeds MAX_NESTING
+    fn nested(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<isize>,
+    ) -> anyhow::Result<isize> {
+        if self.nesting >= MAX_NESTING {
+            bail!("{}: expression nested too deeply", self.expr);
+        }
+        self.nesting += 1;
+        let result = f(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn expression(&mut self, min_power: u8) -> anyhow::Result<isize> {
+        self.nested(|this| this.binary(min_power))
+    }
+
+    fn binary(&mut self, min_power: u8) -> anyhow::Result<isize> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Operator(op)) = self.peek() {
+            let op = *op;
+            let power = binding_power(op).ok_or_else(|| self.syntax_error())?;
+            if power < min_power {
+                break;
+            }
+            self.next();
+            // `**` is right associative; everything else is left associative
+            let rhs = if op == "**" {
+                self.expression(power)?
+            } else {
+                self.expression(power + 1)?
+            };
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<isize> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) => self.variable(&name),
+            Some(Token::LeftParen) => {
+                let value = self.expression(0)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(value),
+                    _ => Err(anyhow!("{}: missing `)'", self.expr)),
+                }
+            }
+            Some(Token::Operator(op)) => {
+                let value = self.nested(Self::unary)?;
+                match op {
+                    "-" => Ok(value.wrapping_neg()),
+                    "+" => Ok(value),
+                    "!" => Ok((value == 0) as isize),
+                    "~" => Ok(!value),
+                    _ => {
+                        self.position -= 1;
+                        Err(self.syntax_error())
+                    }
+                }
+            }
+            _ => {
+                self.position = self.position.saturating_sub(1);
+                Err(self.syntax_error())
+            }
+        }
+    }
+
+    fn variable(&self, name: &str) -> anyhow::Result<isize> {
+        let value = match self.env.get_str(name)? {
+            Some(value) => value,
+            None => return Ok(0),
+        };
+        if self.depth >= MAX_RECURSION {
+            bail!("{}: expression recursion level exceeded", name);
+        }
+        evaluate_at_depth(value, self.env, self.depth + 1, self.nesting)
+    }
+}
+
+fn apply(op: &str, lhs: isize, rhs: isize) -> anyhow::Result<isize> {
+    Ok(match op {
+        "||" => (lhs != 0 || rhs != 0) as isize,
+        "&&" => (lhs != 0 && rhs != 0) as isize,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as isize,
+        "!=" => (lhs != rhs) as isize,
+        "<" => (lhs < rhs) as isize,
+        "<=" => (lhs <= rhs) as isize,
+        ">" => (lhs > rhs) as isize,
+        ">=" => (lhs >= rhs) as isize,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => bail!("division by 0"),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" if rhs < 0 => bail!("exponent less than 0"),
+        "**" => lhs.wrapping_pow(rhs as u32),
+        _ => bail!("unhandled operator {}", op),
+    })
+}
+
+fn evaluate_at_depth(
+    expr: &str,
+    env: &Environment,
+    depth: usize,
+    nesting: usize,
+) -> anyhow::Result<isize> {
+    let mut evaluator = Evaluator {
+        expr,
+        tokens: tokenize(expr)?,
+        position: 0,
+        env,
+        depth,
+        nesting,
+    };
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = evaluator.expression(0)?;
+    if evaluator.peek().is_some() {
+        return Err(evaluator.syntax_error());
+    }
+    Ok(value)
+}
+
+/// Evaluate an arithmetic expression.  Variables referenced by name
+/// are looked up in env and their values are themselves evaluated
+/// as expressions; unset or null variables evaluate to 0.
+pub fn evaluate(expr: &str, env: &Environment) -> anyhow::Result<isize> {
+    evaluate_at_depth(expr, env, 0, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(expr: &str) -> isize {
+        let mut env = Environment::new_empty();
+        env.set("five", "5");
+        env.set("expr", "five * 2");
+        evaluate(expr, &env).unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("2 ** 3 ** 2"), 512);
+        assert_eq!(eval("-3 + +1"), -2);
+        assert_eq!(eval("1 < 2 && 3 == 3"), 1);
+        assert_eq!(eval("!0 | 4"), 5);
+        assert_eq!(eval("0x10 + 010"), 24);
+        assert_eq!(eval(""), 0);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("five + 1"), 6);
+        assert_eq!(eval("expr + unset"), 10);
+    }
+
+    #[test]
+    fn errors() {
+        let env = Environment::new_empty();
+        assert!(evaluate("1 / 0", &env).is_err());
+        assert!(evaluate("1 +", &env).is_err());
+        assert!(evaluate("(1", &env).is_err());
+        assert!(evaluate("1 2", &env).is_err());
+        assert!(evaluate("1 $ 2", &env).is_err());
+
+        let deep = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        let err = evaluate(&deep, &env).unwrap_err().to_string();
+        assert!(err.ends_with("expression nested too deeply"), "{}", err);
+        let err = evaluate(&"-".repeat(100_000), &env)
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("expression nested too deeply"), "{}", err);
+        let err = evaluate(&"2**".repeat(100_000), &env)
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("expression nested too deeply"), "{}", err);
+        assert_eq!(
+            evaluate(&format!("{}1{}", "(".repeat(200), ")".repeat(200)), &env).unwrap(),
+            1
+        );
+
+        let mut env = Environment::new_empty();
+        env.set("loop", "loop");
+        assert!(evaluate("loop", &env).is_err());
+    }
+}
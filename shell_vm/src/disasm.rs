@@ -0,0 +1,211 @@
+use crate::{InstructionAddress, Operand, Operation, Program, SourceLocation, Value};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// Formats the value of a field of an op for a disassembly listing.
+/// address is that of the op, which is needed to resolve the target
+/// of a relative jump.
+pub trait OpField {
+    fn describe(&self, address: usize) -> String;
+}
+
+impl OpField for Operand {
+    fn describe(&self, _address: usize) -> String {
+        match self {
+            Operand::Immediate(value) => disassemble_value(value),
+            Operand::FrameRelative(slot) => format!("r{}", slot),
+            Operand::LastWaitStatus => "$?".to_owned(),
+        }
+    }
+}
+
+fn disassemble_value(value: &Value) -> String {
+    match value {
+        Value::None => "none".to_owned(),
+        Value::String(s) => format!("{:?}", s),
+        Value::OsString(s) => format!("{:?}", s),
+        Value::Integer(n) => n.to_string(),
+        Value::List(list) => format!(
+            "[{}]",
+            list.iter()
+                .map(disassemble_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::WaitableStatus(status) => format!("{:?}", status),
+    }
+}
+
+impl OpField for InstructionAddress {
+    fn describe(&self, address: usize) -> String {
+        let target = match *self {
+            InstructionAddress::Absolute(target) => Some(target),
+            InstructionAddress::Relative(offset) => (address as isize)
+                .checked_add(offset)
+                .and_then(|target| usize::try_from(target).ok()),
+        };
+        match target {
+            Some(target) => format!("@{}", target),
+            None => format!("{:?}", self),
+        }
+    }
+}
+
+impl OpField for Arc<Program> {
+    fn describe(&self, _address: usize) -> String {
+        format!("<program of {} ops>", self.opcodes().len())
+    }
+}
+
+impl OpField for SourceLocation {
+    fn describe(&self, _address: usize) -> String {
+        self.to_string()
+    }
+}
+
+impl OpField for String {
+    fn describe(&self, _address: usize) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl OpField for bool {
+    fn describe(&self, _address: usize) -> String {
+        self.to_string()
+    }
+}
+
+impl OpField for usize {
+    fn describe(&self, _address: usize) -> String {
+        self.to_string()
+    }
+}
+
+/// Formats a single op, as in `JumpIfZero condition=r2 target=@9`
+pub fn disassemble_op(op: &Operation, address: usize) -> String {
+    let mut text = op.name().to_owned();
+    for (name, value) in op.fields() {
+        text.push(' ');
+        text.push_str(name);
+        text.push('=');
+        text.push_str(&value.describe(address));
+    }
+    text
+}
+
+/// A listing of the ops in a program, followed by the listings of
+/// the functions that it defines.  Frame slots are shown as `r0`..`rN`,
+/// jump targets as the absolute address `@N`, and the source position
+/// of each op, if known, follows it.
+pub struct Disassembly<'a> {
+    program: &'a Program,
+    title: String,
+}
+
+impl<'a> Disassembly<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            title: "program".to_owned(),
+        }
+    }
+
+    /// Replace the heading of the listing, which is `program` by
+    /// default, such as to name the function that it belongs to
+    pub fn with_title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+}
+
+fn write_listing(
+    fmt: &mut Formatter,
+    title: &str,
+    program: &Program,
+    functions: &mut VecDeque<(String, Arc<Program>)>,
+) -> std::fmt::Result {
+    let ops = program.opcodes();
+    match program.source_name() {
+        Some(source_name) => writeln!(fmt, "{} from {} ({} ops):", title, source_name, ops.len())?,
+        None => writeln!(fmt, "{} ({} ops):", title, ops.len())?,
+    }
+    let width = ops.len().saturating_sub(1).to_string().len();
+    for (address, op) in ops.iter().enumerate() {
+        let text = disassemble_op(op, address);
+        match program.span(address) {
+            Some(span) => writeln!(
+                fmt,
+                "  {:>width$}  {:<60} ; {}:{}",
+                address,
+                text,
+                span.start.line + 1,
+                span.start.col + 1,
+                width = width
+            )?,
+            None => writeln!(fmt, "  {:>width$}  {}", address, text, width = width)?,
+        }
+        if let Operation::DefineFunction(define) = op {
+            functions.push_back((define.name.clone(), Arc::clone(&define.program)));
+        }
+    }
+    Ok(())
+}
+
+impl Display for Disassembly<'_> {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        let mut functions = VecDeque::new();
+        write_listing(fmt, &self.title, self.program, &mut functions)?;
+        while let Some((name, program)) = functions.pop_front() {
+            writeln!(fmt)?;
+            write_listing(fmt, &format!("function {}", name), &program, &mut functions)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::op;
+
+    #[test]
+    fn disassemble() {
+        let function = Program::new(vec![op::Return {
+            value: Operand::Immediate(Value::Integer(0)),
+        }
+        .into()]);
+        let program = Program::new(vec![
+            op::Copy {
+                source: Operand::Immediate(Value::List(vec!["a".into(), 1.into()])),
+                destination: Operand::FrameRelative(1),
+            }
+            .into(),
+            op::JumpIfZero {
+                condition: Operand::LastWaitStatus,
+                target: InstructionAddress::Relative(2),
+            }
+            .into(),
+            op::DefineFunction {
+                name: "f".to_owned(),
+                program: function,
+                location: SourceLocation {
+                    file: None,
+                    line: 0,
+                },
+            }
+            .into(),
+        ]);
+        assert_eq!(
+            program.disassemble().to_string(),
+            "program (3 ops):\n\
+             \x20 0  Copy source=[\"a\", 1] destination=r1\n\
+             \x20 1  JumpIfZero condition=$? target=@3\n\
+             \x20 2  DefineFunction name=\"f\" program=<program of 1 ops> location=line 1\n\
+             \n\
+             function f (1 ops):\n\
+             \x20 0  Return value=0\n"
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use anyhow::bail;
+use crate::{ShellOption, SourceLocation, Value};
+use anyhow::{anyhow, bail};
 use caseless::{canonical_caseless_match_str, Caseless};
+use shell_lexer::Span;
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[derive(Debug, Clone)]
 struct CaseInsensitiveOsString(OsString);
@@ -99,10 +103,35 @@ impl Hash for CaseInsensitiveOsString {
     }
 }
 
+/// The attributes of a variable
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    /// The variable is passed to spawned processes
+    pub exported: bool,
+    /// The variable cannot be assigned or unset
+    pub readonly: bool,
+    /// Assignments to the variable are evaluated arithmetically
+    pub integer: bool,
+    /// Assigned values are converted to lower case
+    pub lowercase: bool,
+    /// Assigned values are converted to upper case
+    pub uppercase: bool,
+}
+
+/// A variable tracks its attributes even while it has no value,
+/// so that `export FOO` can precede the assignment to FOO.
+/// An array variable holds its attributes here and its value
+/// in Environment::arrays.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Variable {
+    value: Option<OsString>,
+    attributes: Attributes,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum EnvMap {
-    Posix(BTreeMap<OsString, OsString>),
-    Windows(BTreeMap<CaseInsensitiveOsString, OsString>),
+    Posix(BTreeMap<OsString, Variable>),
+    Windows(BTreeMap<CaseInsensitiveOsString, Variable>),
 }
 
 impl Default for EnvMap {
@@ -124,19 +153,20 @@ impl EnvMap {
         EnvMap::Windows(BTreeMap::new())
     }
 
-    fn set(&mut self, key: OsString, value: OsString) {
+    /// Returns the variable named key, creating it without a value
+    /// or attributes if it doesn't exist
+    fn entry(&mut self, key: OsString) -> &mut Variable {
         match self {
-            EnvMap::Posix(map) => map.insert(key, value),
-            EnvMap::Windows(map) => map.insert(CaseInsensitiveOsString(key), value),
-        };
+            EnvMap::Posix(map) => map.entry(key).or_default(),
+            EnvMap::Windows(map) => map.entry(CaseInsensitiveOsString(key)).or_default(),
+        }
     }
 
-    fn get(&self, key: &OsStr) -> Option<&OsStr> {
+    fn get(&self, key: &OsStr) -> Option<&Variable> {
         match self {
             EnvMap::Posix(map) => map.get(key),
             EnvMap::Windows(map) => map.get(&CaseInsensitiveOsString(key.to_os_string())),
         }
-        .map(OsString::as_os_str)
     }
 
     fn unset(&mut self, key: &OsStr) {
@@ -146,7 +176,7 @@ impl EnvMap {
         };
     }
 
-    fn iter(&self) -> impl Iterator<Item = (&OsString, &OsString)> {
+    fn iter(&self) -> impl Iterator<Item = (&OsString, &Variable)> {
         // Using this technique to avoid incompatible match arms errors:
         // https://stackoverflow.com/a/54728634/149111
         let mut posix = None;
@@ -163,20 +193,237 @@ impl EnvMap {
     }
 }
 
-/// The environment represents the environmental variables
-/// associated with the shell and the processes that it spawns.
+/// The value of an array variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Array {
+    Indexed(BTreeMap<usize, OsString>),
+    Associative(BTreeMap<String, OsString>),
+}
+
+fn parse_index(subscript: &str) -> anyhow::Result<usize> {
+    match subscript.trim().parse::<usize>() {
+        Ok(index) => Ok(index),
+        Err(_) => bail!("{}: bad array subscript", subscript),
+    }
+}
+
+impl Array {
+    pub fn is_associative(&self) -> bool {
+        matches!(self, Array::Associative(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Array::Indexed(map) => map.len(),
+            Array::Associative(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, subscript: &str) -> anyhow::Result<Option<&OsStr>> {
+        Ok(match self {
+            Array::Indexed(map) => map.get(&parse_index(subscript)?),
+            Array::Associative(map) => map.get(subscript),
+        }
+        .map(OsString::as_os_str))
+    }
+
+    pub fn set(&mut self, subscript: &str, value: OsString) -> anyhow::Result<()> {
+        match self {
+            Array::Indexed(map) => map.insert(parse_index(subscript)?, value),
+            Array::Associative(map) => map.insert(subscript.to_owned(), value),
+        };
+        Ok(())
+    }
+
+    /// Returns the values, ordered by index or key
+    pub fn values(&self) -> Vec<&OsStr> {
+        match self {
+            Array::Indexed(map) => map.values().map(OsString::as_os_str).collect(),
+            Array::Associative(map) => map.values().map(OsString::as_os_str).collect(),
+        }
+    }
+
+    /// Returns the indices or keys, in order
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Array::Indexed(map) => map.keys().map(ToString::to_string).collect(),
+            Array::Associative(map) => map.keys().cloned().collect(),
+        }
+    }
+
+    /// The element that is used when the array is referenced
+    /// without a subscript
+    fn first(&self) -> Option<&OsStr> {
+        match self {
+            Array::Indexed(map) => map.get(&0),
+            Array::Associative(map) => map.get("0"),
+        }
+        .map(OsString::as_os_str)
+    }
+}
+
+/// Tracks the progress of `getopts` through an argument that combines
+/// several options, such as `-abc`.  offset is the index of the next
+/// option character within the argument at optind; when OPTIND no
+/// longer matches optind, the cursor is stale and parsing restarts at
+/// the beginning of the argument.  The cursor is likewise stale if
+/// the argument at optind is no longer arg, and it is discarded
+/// whenever OPTIND is assigned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptionCursor {
+    pub optind: usize,
+    pub offset: usize,
+    /// The argument that offset indexes into
+    pub arg: String,
+}
+
+/// A remembered location of a command, along with the number of
+/// times that it has been used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashedCommand {
+    pub path: PathBuf,
+    pub hits: usize,
+}
+
+/// Remembers where commands were found by searching PATH, keyed by
+/// command name.  The table holds the PATH and PATHEXT values that
+/// were in effect when it was populated, and is only valid while
+/// those remain unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandHash {
+    search_path: (Option<OsString>, Option<OsString>),
+    commands: BTreeMap<String, HashedCommand>,
+}
+
+impl CommandHash {
+    /// Returns the remembered location of name, counting this as
+    /// a use of that command
+    pub fn lookup(&mut self, name: &str) -> Option<PathBuf> {
+        let command = self.commands.get_mut(name)?;
+        command.hits += 1;
+        Some(command.path.clone())
+    }
+
+    pub fn insert(&mut self, name: &str, path: PathBuf) {
+        self.commands
+            .insert(name.to_owned(), HashedCommand { path, hits: 0 });
+    }
+
+    /// Forgets name; returns false if it wasn't remembered
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &HashedCommand)> {
+        self.commands.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// The command hash is shared by an environment and all of its
+/// clones, such as those made for prefix assignments, pipeline stages
+/// and nested machines, so that a location found by any of them is
+/// remembered by the shell as a whole.  A clone that searches a
+/// different PATH, as for `PATH=/x cmd`, gets a table of its own.
+#[derive(Clone, Debug, Default)]
+struct SharedCommandHash(Arc<Mutex<CommandHash>>);
+
+impl PartialEq for SharedCommandHash {
+    fn eq(&self, other: &Self) -> bool {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        // Only one of the tables is locked at a time, so that this
+        // can't deadlock with a comparison in the opposite order
+        let commands = self.0.lock().unwrap().clone();
+        commands == *other.0.lock().unwrap()
+    }
+}
+
+impl Eq for SharedCommandHash {}
+
+/// Records that the `exit` builtin has asked for the shell to exit.
+/// The request remains in the environment while the running machines
+/// and functions unwind, until it is consumed by the shell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitRequest {
+    /// Exit with the status of the most recently completed command
+    LastStatus,
+    Status(isize),
+}
+
+/// A function call that is in progress, along with the place that
+/// it was called from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    /// The name of the function that was called
+    pub function: String,
+    /// The source span of the command that called it, if known
+    pub span: Option<Span>,
+    /// The name of the file containing that command
+    pub source_name: Option<String>,
+}
+
+impl CallSite {
+    /// Returns the line from which the function was called
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.span.map(|span| SourceLocation {
+            file: self.source_name.clone(),
+            line: span.start.line,
+        })
+    }
+}
+
+/// The environment represents the variables associated with the
+/// shell.  Only those with the exported attribute are passed to
+/// the processes that it spawns.  Array variables are held
+/// separately from the scalar variables and are never exported.
+/// The positional parameters `$0`..`$N` are also held here, so that
+/// builtins such as `set` and `shift` are able to change them.
+/// Likewise, the function call stack is held here so that it can
+/// be reported by the `caller` builtin.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Environment {
     map: EnvMap,
+    arrays: BTreeMap<String, Array>,
+    positional: Vec<Value>,
+    option_cursor: OptionCursor,
+    command_hash: SharedCommandHash,
+    options: BTreeSet<ShellOption>,
+    exit_request: Option<ExitRequest>,
+    exit_trap: Option<String>,
+    call_stack: Vec<CallSite>,
+    last_status: isize,
 }
 
 impl Environment {
     pub fn new() -> Self {
         let mut environ = Self {
             map: Default::default(),
+            arrays: BTreeMap::new(),
+            positional: vec![],
+            option_cursor: OptionCursor::default(),
+            command_hash: SharedCommandHash::default(),
+            options: BTreeSet::new(),
+            exit_request: None,
+            exit_trap: None,
+            call_stack: vec![],
+            last_status: 0,
         };
         for (key, value) in std::env::vars_os() {
-            environ.set(key, value);
+            environ.set(key.clone(), value);
+            environ.export(key);
         }
         environ
     }
@@ -184,9 +431,153 @@ impl Environment {
     pub fn new_empty() -> Self {
         Self {
             map: Default::default(),
+            arrays: BTreeMap::new(),
+            positional: vec![],
+            option_cursor: OptionCursor::default(),
+            command_hash: SharedCommandHash::default(),
+            options: BTreeSet::new(),
+            exit_request: None,
+            exit_trap: None,
+            call_stack: vec![],
+            last_status: 0,
         }
     }
 
+    /// Returns the positional parameters; the first element, if any,
+    /// is `$0` and the remainder are `$1`..`$N`
+    pub fn positional(&self) -> &[Value] {
+        &self.positional
+    }
+
+    /// Replaces all of the positional parameters, including `$0`
+    pub fn set_positional(&mut self, argv: Vec<Value>) {
+        self.positional = argv;
+    }
+
+    /// Replaces `$1`..`$N` with params, preserving `$0`
+    pub fn set_positional_params(&mut self, params: Vec<Value>) {
+        let zero = self
+            .positional
+            .first()
+            .cloned()
+            .unwrap_or_else(|| Value::String("wzsh".to_owned()));
+        self.positional = std::iter::once(zero).chain(params).collect();
+    }
+
+    /// Removes the first n of `$1`..`$N`, renumbering those that
+    /// remain.  Fails, leaving the parameters unchanged, if there
+    /// are fewer than n of them.
+    pub fn shift_positional(&mut self, n: usize) -> anyhow::Result<()> {
+        let count = self.positional.len().saturating_sub(1);
+        if n > count {
+            bail!("{}: shift count out of range", n);
+        }
+        if n > 0 {
+            self.positional.drain(1..=n);
+        }
+        Ok(())
+    }
+
+    /// Returns the table of remembered command locations, which is
+    /// shared with the clones of this environment.  If PATH or PATHEXT
+    /// differ from those that the table was populated with, this
+    /// environment is given an empty table of its own, leaving the
+    /// shared table intact for the others.
+    pub fn command_hash(&mut self) -> MutexGuard<'_, CommandHash> {
+        let search_path = (
+            self.get("PATH").map(OsStr::to_os_string),
+            self.get("PATHEXT").map(OsStr::to_os_string),
+        );
+        let detach = {
+            let mut hash = self.command_hash.0.lock().unwrap();
+            if hash.search_path == search_path {
+                false
+            } else if hash.is_empty() {
+                // Nothing is lost by re-keying an empty table in place,
+                // and the clones continue to share what is found
+                hash.search_path = search_path.clone();
+                false
+            } else {
+                true
+            }
+        };
+        if detach {
+            self.command_hash = SharedCommandHash(Arc::new(Mutex::new(CommandHash {
+                search_path,
+                commands: BTreeMap::new(),
+            })));
+        }
+        self.command_hash.0.lock().unwrap()
+    }
+
+    pub fn is_option_set(&self, option: ShellOption) -> bool {
+        self.options.contains(&option)
+    }
+
+    pub fn set_option(&mut self, option: ShellOption, enable: bool) {
+        if enable {
+            self.options.insert(option);
+        } else {
+            self.options.remove(&option);
+        }
+    }
+
+    pub fn exit_request(&self) -> Option<ExitRequest> {
+        self.exit_request
+    }
+
+    pub fn request_exit(&mut self, request: ExitRequest) {
+        self.exit_request = Some(request);
+    }
+
+    pub fn take_exit_request(&mut self) -> Option<ExitRequest> {
+        self.exit_request.take()
+    }
+
+    /// The status of the last command run by an earlier program, such
+    /// as a previous line entered at the prompt.  Each line runs in a
+    /// machine of its own, so this is what a bare `exit` uses when the
+    /// current machine has yet to run a command.
+    pub fn last_status(&self) -> isize {
+        self.last_status
+    }
+
+    pub fn set_last_status(&mut self, status: isize) {
+        self.last_status = status;
+    }
+
+    /// The commands to run when the shell exits, as set by `trap`
+    pub fn exit_trap(&self) -> Option<&str> {
+        self.exit_trap.as_deref()
+    }
+
+    pub fn set_exit_trap(&mut self, action: Option<String>) {
+        self.exit_trap = action;
+    }
+
+    /// Returns the function calls that are in progress, with the
+    /// outermost first
+    pub fn call_stack(&self) -> &[CallSite] {
+        &self.call_stack
+    }
+
+    pub fn push_call(&mut self, call: CallSite) {
+        self.call_stack.push(call);
+    }
+
+    /// Discards the innermost calls until only depth of them remain
+    pub fn truncate_call_stack(&mut self, depth: usize) {
+        self.call_stack.truncate(depth);
+    }
+
+    pub fn option_cursor(&self) -> OptionCursor {
+        self.option_cursor.clone()
+    }
+
+    pub fn set_option_cursor(&mut self, cursor: OptionCursor) {
+        self.option_cursor = cursor;
+    }
+
     pub fn get_str<K: AsRef<OsStr> + std::fmt::Debug>(
         &self,
         key: K,
@@ -209,7 +600,16 @@ impl Environment {
         key: K,
         value: V,
     ) {
-        self.map.set(key.into(), value.into());
+        let key = key.into();
+        // Assigning to an array without a subscript assigns to
+        // its first element
+        if let Some(array) = key.to_str().and_then(|k| self.arrays.get_mut(k)) {
+            array
+                .set("0", value.into())
+                .expect("0 is a valid subscript for any array");
+            return;
+        }
+        self.map.entry(key).value = Some(value.into());
     }
 
     pub fn append_path<K: Into<OsString> + ?Sized, V: Into<OsString> + ?Sized>(
@@ -247,15 +647,253 @@ impl Environment {
     }
 
     pub fn get<K: AsRef<OsStr>>(&self, key: K) -> Option<&OsStr> {
-        self.map.get(key.as_ref())
+        let key = key.as_ref();
+        match key.to_str().and_then(|k| self.arrays.get(k)) {
+            Some(array) => array.first(),
+            None => self.map.get(key)?.value.as_deref(),
+        }
+    }
+
+    /// Assign value to the named variable, honoring its attributes.
+    /// Unlike set, this fails if the variable is readonly, and the
+    /// value is evaluated arithmetically or case converted if the
+    /// variable has the corresponding attribute.
+    pub fn assign<K: Into<OsString>, V: Into<OsString>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> anyhow::Result<()> {
+        let key = key.into();
+        let value = self.apply_attributes(&key, value.into())?;
+        self.special_variable_changed(&key);
+        self.set(key, value);
+        Ok(())
+    }
+
+    /// Update the state of the shell that depends upon the value of
+    /// key, which is about to be assigned or unset
+    fn special_variable_changed(&mut self, key: &OsStr) {
+        if key == "OPTIND" {
+            // The script is restarting or redirecting `getopts`
+            self.option_cursor = OptionCursor::default();
+        }
+    }
+
+    /// Check that the named variable may be assigned, and return
+    /// value transformed according to the variable's attributes
+    fn apply_attributes(&self, key: &OsStr, value: OsString) -> anyhow::Result<OsString> {
+        let attributes = self.attributes(key);
+        if attributes.readonly {
+            bail!("{}: readonly variable", key.to_string_lossy());
+        }
+        let mut value = value;
+        if attributes.integer {
+            let expr = value
+                .to_str()
+                .ok_or_else(|| anyhow!("{:?} is not a valid arithmetic expression", value))?;
+            value = crate::arith::evaluate(expr, self)?.to_string().into();
+        }
+        if attributes.lowercase || attributes.uppercase {
+            if let Some(s) = value.to_str() {
+                value = if attributes.lowercase {
+                    s.to_lowercase()
+                } else {
+                    s.to_uppercase()
+                }
+                .into();
+            }
+        }
+        Ok(value)
+    }
+
+    /// Removes the variable along with its attributes.
+    /// Fails if the variable is readonly.
+    pub fn unset<K: AsRef<OsStr>>(&mut self, key: K) -> anyhow::Result<()> {
+        let key = key.as_ref();
+        if self.attributes(key).readonly {
+            bail!("{}: cannot unset: readonly variable", key.to_string_lossy());
+        }
+        self.special_variable_changed(key);
+        if let Some(k) = key.to_str() {
+            self.arrays.remove(k);
+        }
+        self.map.unset(key);
+        Ok(())
+    }
+
+    /// Returns the attributes of the named variable.  A variable
+    /// that doesn't exist has no attributes set.
+    pub fn attributes<K: AsRef<OsStr>>(&self, key: K) -> Attributes {
+        self.map
+            .get(key.as_ref())
+            .map(|var| var.attributes)
+            .unwrap_or_default()
+    }
+
+    /// Replace the attributes of the named variable.  If the variable
+    /// doesn't exist it is created without a value, so that the
+    /// attributes apply to a subsequent assignment.
+    pub fn set_attributes<K: Into<OsString>>(&mut self, key: K, attributes: Attributes) {
+        self.map.entry(key.into()).attributes = attributes;
+    }
+
+    /// Set the exported attribute of the named variable
+    pub fn export<K: Into<OsString>>(&mut self, key: K) {
+        self.map.entry(key.into()).attributes.exported = true;
+    }
+
+    /// Clear the exported attribute of the named variable
+    pub fn unexport<K: Into<OsString>>(&mut self, key: K) {
+        self.map.entry(key.into()).attributes.exported = false;
+    }
+
+    /// Iterates the exported variables that have values; these are
+    /// the variables that are passed to spawned processes
+    pub fn iter_exported(&self) -> impl Iterator<Item = (&OsString, &OsString)> {
+        self.map.iter().filter_map(|(k, var)| match &var.value {
+            Some(value) if var.attributes.exported => Some((k, value)),
+            _ => None,
+        })
+    }
+
+    /// Iterates the scalar variables that have values, along with
+    /// their attributes
+    pub fn iter_variables(&self) -> impl Iterator<Item = (&OsString, &OsString, Attributes)> {
+        self.map
+            .iter()
+            .filter_map(|(k, var)| var.value.as_ref().map(|value| (k, value, var.attributes)))
+    }
+
+    /// Iterates the names of all variables, including arrays and
+    /// those that have attributes but no value, along with their
+    /// attributes
+    pub fn iter_attributes(&self) -> impl Iterator<Item = (&OsString, Attributes)> {
+        self.map.iter().map(|(k, var)| (k, var.attributes))
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&Array> {
+        self.arrays.get(name)
+    }
+
+    /// Iterates the array variables
+    pub fn iter_arrays(&self) -> impl Iterator<Item = (&String, &Array)> {
+        self.arrays.iter()
+    }
+
+    /// Ensure that name is an array of the requested kind.
+    /// A scalar variable of the same name becomes the first
+    /// element of a new indexed array.
+    pub fn declare_array(&mut self, name: &str, associative: bool) -> anyhow::Result<()> {
+        if let Some(array) = self.arrays.get(name) {
+            if array.is_associative() != associative {
+                bail!(
+                    "{}: cannot convert {} array to {} array",
+                    name,
+                    if associative {
+                        "indexed"
+                    } else {
+                        "associative"
+                    },
+                    if associative {
+                        "associative"
+                    } else {
+                        "indexed"
+                    }
+                );
+            }
+            return Ok(());
+        }
+
+        let mut array = if associative {
+            Array::Associative(BTreeMap::new())
+        } else {
+            Array::Indexed(BTreeMap::new())
+        };
+        if let Some(value) = self.map.entry(name.into()).value.take() {
+            array.set("0", value)?;
+        }
+        self.arrays.insert(name.to_owned(), array);
+        Ok(())
+    }
+
+    /// Replace the elements of the array name, as for
+    /// `name=(a b [key]=c)`.  Elements without a subscript are
+    /// assigned to the index that follows the prior element.
+    /// The array remains associative if it was declared that way,
+    /// in which case every element must have a subscript.
+    pub fn assign_array(
+        &mut self,
+        name: &str,
+        elements: Vec<(Option<String>, OsString)>,
+    ) -> anyhow::Result<()> {
+        let elements = elements
+            .into_iter()
+            .map(|(subscript, value)| Ok((subscript, self.apply_attributes(name.as_ref(), value)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let associative = self
+            .arrays
+            .get(name)
+            .map(Array::is_associative)
+            .unwrap_or(false);
+
+        let array = if associative {
+            let mut map = BTreeMap::new();
+            for (subscript, value) in elements {
+                match subscript {
+                    Some(key) => map.insert(key, value),
+                    None => bail!(
+                        "{}: must use subscript when assigning associative array",
+                        name
+                    ),
+                };
+            }
+            Array::Associative(map)
+        } else {
+            let mut map = BTreeMap::new();
+            let mut next = 0;
+            for (subscript, value) in elements {
+                let index = match subscript {
+                    Some(subscript) => parse_index(&subscript)?,
+                    None => next,
+                };
+                map.insert(index, value);
+                next = index + 1;
+            }
+            Array::Indexed(map)
+        };
+
+        self.map.entry(name.into()).value = None;
+        self.arrays.insert(name.to_owned(), array);
+        Ok(())
     }
 
-    pub fn unset<K: AsRef<OsStr>>(&mut self, key: K) {
-        self.map.unset(key.as_ref());
+    /// Assign to the element of name at subscript, as for
+    /// `name[subscript]=value`, converting name to an indexed
+    /// array if it isn't already an array
+    pub fn set_element(
+        &mut self,
+        name: &str,
+        subscript: &str,
+        value: OsString,
+    ) -> anyhow::Result<()> {
+        let value = self.apply_attributes(name.as_ref(), value)?;
+        if !self.arrays.contains_key(name) {
+            self.declare_array(name, false)?;
+        }
+        self.arrays
+            .get_mut(name)
+            .expect("declared above")
+            .set(subscript, value)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&OsString, &OsString)> {
-        self.map.iter()
+    /// Returns the element of name at subscript.  A scalar
+    /// variable is treated as an array of one element.
+    pub fn get_element(&self, name: &str, subscript: &str) -> anyhow::Result<Option<&OsStr>> {
+        match self.arrays.get(name) {
+            Some(array) => array.get(subscript),
+            None if parse_index(subscript)? == 0 => Ok(self.get(name)),
+            None => Ok(None),
+        }
     }
 }
 
@@ -277,4 +915,164 @@ mod test {
         assert_eq!(foo, foo_os_str);
         assert_eq!(foo.partial_cmp(foo_os_str), Some(Ordering::Equal));
     }
+
+    #[test]
+    fn attributes() {
+        let mut env = Environment::new_empty();
+        env.set("local", "1");
+        env.set("exported", "2");
+        env.export("exported");
+        env.export("later");
+        assert_eq!(
+            env.iter_exported().collect::<Vec<_>>(),
+            vec![(&OsString::from("exported"), &OsString::from("2"))]
+        );
+
+        env.set("later", "3");
+        assert!(env.attributes("later").exported);
+        assert_eq!(env.iter_exported().count(), 2);
+
+        env.unexport("exported");
+        assert_eq!(env.get("exported"), Some(OsStr::new("2")));
+        assert_eq!(env.iter_exported().count(), 1);
+        assert_eq!(env.iter_variables().count(), 3);
+
+        env.unset("later").unwrap();
+        assert_eq!(env.attributes("later"), Attributes::default());
+    }
+
+    #[test]
+    fn assign_with_attributes() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        env.set_attributes(
+            "num",
+            Attributes {
+                integer: true,
+                ..Attributes::default()
+            },
+        );
+        env.assign("num", "2 * 3")?;
+        env.assign("num", "num + 1")?;
+        assert_eq!(env.get("num"), Some(OsStr::new("7")));
+        assert!(env.assign("num", "1 +").is_err());
+
+        env.set_attributes(
+            "upper",
+            Attributes {
+                uppercase: true,
+                ..Attributes::default()
+            },
+        );
+        env.assign("upper", "Hello")?;
+        assert_eq!(env.get("upper"), Some(OsStr::new("HELLO")));
+
+        env.assign("fixed", "value")?;
+        env.set_attributes(
+            "fixed",
+            Attributes {
+                readonly: true,
+                ..Attributes::default()
+            },
+        );
+        assert!(env.assign("fixed", "other").is_err());
+        assert!(env.unset("fixed").is_err());
+        assert!(env.set_element("fixed", "1", "other".into()).is_err());
+        assert_eq!(env.get("fixed"), Some(OsStr::new("value")));
+        Ok(())
+    }
+
+    #[test]
+    fn positional() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        env.set_positional_params(vec!["a".into(), "b".into(), "c".into()]);
+        assert_eq!(
+            env.positional(),
+            &["wzsh".into(), "a".into(), "b".into(), "c".into()][..]
+        );
+
+        env.shift_positional(2)?;
+        assert_eq!(env.positional(), &["wzsh".into(), "c".into()][..]);
+        assert!(env.shift_positional(2).is_err());
+        assert_eq!(env.positional().len(), 2);
+
+        env.set_positional(vec!["script".into(), "x".into()]);
+        env.set_positional_params(vec![]);
+        assert_eq!(env.positional(), &["script".into()][..]);
+        env.shift_positional(0)?;
+        assert!(env.shift_positional(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn command_hash() {
+        let mut env = Environment::new_empty();
+        env.set("PATH", "/bin");
+        env.command_hash().insert("ls", "/bin/ls".into());
+        assert_eq!(env.command_hash().lookup("ls"), Some("/bin/ls".into()));
+        assert_eq!(env.command_hash().iter().next().unwrap().1.hits, 1);
+
+        env.set("PATH", "/bin");
+        assert!(!env.command_hash().is_empty());
+
+        env.set("PATH", "/usr/bin:/bin");
+        assert!(env.command_hash().is_empty());
+        env.command_hash().insert("ls", "/bin/ls".into());
+        env.unset("PATH").unwrap();
+        assert_eq!(env.command_hash().lookup("ls"), None);
+
+        // Clones share the table, so that what one finds is
+        // remembered by the others
+        env.set("PATH", "/bin");
+        let mut clone = env.clone();
+        clone.command_hash().insert("ls", "/bin/ls".into());
+        assert_eq!(env.command_hash().lookup("ls"), Some("/bin/ls".into()));
+        clone.command_hash().remove("ls");
+        assert!(env.command_hash().is_empty());
+        assert_eq!(clone, env);
+
+        // A clone that searches a different PATH has a table of its
+        // own, and leaves the shared one intact
+        env.command_hash().insert("ls", "/bin/ls".into());
+        let mut clone = env.clone();
+        clone.set("PATH", "/usr/bin");
+        assert!(clone.command_hash().is_empty());
+        clone.command_hash().insert("ls", "/usr/bin/ls".into());
+        assert_eq!(env.command_hash().lookup("ls"), Some("/bin/ls".into()));
+        assert_eq!(
+            clone.command_hash().lookup("ls"),
+            Some("/usr/bin/ls".into())
+        );
+        assert_ne!(clone, env);
+    }
+
+    #[test]
+    fn arrays() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        env.set("scalar", "first");
+        env.set_element("scalar", "2", "third".into())?;
+        assert_eq!(env.get("scalar"), Some(OsStr::new("first")));
+        assert_eq!(
+            env.get_array("scalar").unwrap().values(),
+            vec![OsStr::new("first"), OsStr::new("third")]
+        );
+        assert_eq!(env.iter_variables().count(), 0);
+        assert!(env.set_element("scalar", "x", "bad".into()).is_err());
+
+        env.declare_array("map", true)?;
+        assert!(env.declare_array("map", false).is_err());
+        env.assign_array(
+            "map",
+            vec![
+                (Some("b".to_owned()), "2".into()),
+                (Some("a".to_owned()), "1".into()),
+            ],
+        )?;
+        assert_eq!(env.get_array("map").unwrap().keys(), vec!["a", "b"]);
+        assert_eq!(env.get_element("map", "b")?, Some(OsStr::new("2")));
+        assert!(env.assign_array("map", vec![(None, "x".into())]).is_err());
+
+        env.unset("map")?;
+        assert!(env.get_array("map").is_none());
+        Ok(())
+    }
 }
@@ -1,14 +1,15 @@
-use crate::{Environment, IoEnvironment, Program, Status, Value};
+use crate::{Environment, IoEnvironment, Program, SourceLocation, Status, Value};
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// The WaitForStatus trait allows waiting on a spawned command.
 /// Since the command could be a child process, some action
 /// running in a another thread, or perhaps even be an inline
 /// or immediately ready thing, the trait gives some flexibility
 /// in waiting on whatever that implementation may be.
-pub trait WaitForStatus: std::fmt::Debug {
+pub trait WaitForStatus: std::fmt::Debug + Send + Sync {
     /// Non-blocking check for the status of the item
     fn poll(&self) -> Option<Status>;
     /// Block until the status of the item changes from Running
@@ -123,5 +124,75 @@ pub trait ShellHost: std::fmt::Debug {
         io_env: &IoEnvironment,
     ) -> anyhow::Result<WaitableStatus>;
 
-    fn define_function(&self, name: &str, program: &Arc<Program>) -> anyhow::Result<()>;
+    /// Spawn a command that must run concurrently with the commands
+    /// that are spawned after it, as is required for the stages of a
+    /// pipeline.  The command is run as though in a subshell: it can
+    /// see but not change the environment and current directory of
+    /// the shell.  Hosts that run builtins or functions inline in
+    /// spawn_command should run them on another thread here, as
+    /// otherwise a stage that fills the pipe to the next stage can
+    /// never finish.
+    /// The default implementation delegates to spawn_command.
+    fn spawn_concurrent_command(
+        &self,
+        argv: &Vec<Value>,
+        environment: &Environment,
+        current_directory: &Path,
+        io_env: &IoEnvironment,
+    ) -> anyhow::Result<WaitableStatus> {
+        self.spawn_command(
+            argv,
+            &mut environment.clone(),
+            &mut current_directory.to_path_buf(),
+            io_env,
+        )
+    }
+
+    /// Run program concurrently with the commands that are spawned
+    /// after it, as is required for a pipeline stage that is a compound
+    /// command, such as a brace group.  As with spawn_concurrent_command,
+    /// the program runs as though in a subshell.
+    /// The default implementation reports that this is unsupported.
+    fn spawn_concurrent_program(
+        &self,
+        _program: &Arc<Program>,
+        _environment: &Environment,
+        _current_directory: &Path,
+        _io_env: &IoEnvironment,
+    ) -> anyhow::Result<WaitableStatus> {
+        anyhow::bail!("this shell host cannot run compound commands in a pipeline")
+    }
+
+    /// Returns the program of the function name, if one has been
+    /// defined.  The machine calls such functions itself rather
+    /// than passing them to spawn_command.
+    /// The default implementation knows of no functions.
+    fn lookup_function(&self, _name: &str) -> Option<Arc<Program>> {
+        None
+    }
+
+    /// Define (or replace) the function name; location is where
+    /// the definition appears in the source.
+    fn define_function(
+        &self,
+        name: &str,
+        program: &Arc<Program>,
+        location: &SourceLocation,
+    ) -> anyhow::Result<()>;
+
+    /// Returns the CPU time consumed so far by the shell itself,
+    /// plus that of its children that have terminated and been
+    /// waited for.  This is used to implement `time`.
+    /// The default implementation is unable to measure this,
+    /// and reports zero.
+    fn cpu_times(&self) -> anyhow::Result<CpuTimes> {
+        Ok(CpuTimes::default())
+    }
+}
+
+/// CPU time consumption, as reported by getrusage(2)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: Duration,
+    pub system: Duration,
 }
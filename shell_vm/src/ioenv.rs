@@ -1,8 +1,20 @@
 use anyhow::anyhow;
 use filedescriptor::FileDescriptor;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+/// Descriptors below this number are reserved for use by the
+/// user; descriptors that the shell allocates on its own behalf
+/// are numbered from here upwards.
+pub const FIRST_SPARE_FD: usize = 10;
+
+/// A descriptor number that is never visible to the user.
+/// Descriptors that are allocated dynamically, as with
+/// `{varname}>file`, are first set up with this number and
+/// then moved to their final number by the AllocateFd operation.
+pub const STAGING_FD: usize = usize::MAX;
+
 #[derive(Clone)]
 pub struct IoEnvironment {
     fds: HashMap<usize, Arc<Mutex<FileDescriptor>>>,
@@ -14,39 +26,60 @@ impl std::fmt::Debug for IoEnvironment {
     }
 }
 
+fn closed_error() -> std::io::Error {
+    std::io::Error::other("file descriptor is closed")
+}
+
+/// Readable is None if the descriptor has been closed
 pub struct Readable {
-    fd: Arc<Mutex<FileDescriptor>>,
+    fd: Option<Arc<Mutex<FileDescriptor>>>,
 }
 
 impl Readable {
     pub fn dup(&self) -> anyhow::Result<FileDescriptor> {
-        self.fd.lock().unwrap().try_clone()
+        match &self.fd {
+            Some(fd) => fd.lock().unwrap().try_clone(),
+            None => Err(closed_error().into()),
+        }
     }
 }
 
 impl std::io::Read for Readable {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        self.fd.lock().unwrap().read(buf)
+        match &self.fd {
+            Some(fd) => fd.lock().unwrap().read(buf),
+            None => Err(closed_error()),
+        }
     }
 }
 
+/// Writable is None if the descriptor has been closed
 pub struct Writable {
-    fd: Arc<Mutex<FileDescriptor>>,
+    fd: Option<Arc<Mutex<FileDescriptor>>>,
 }
 
 impl Writable {
     pub fn dup(&self) -> anyhow::Result<FileDescriptor> {
-        self.fd.lock().unwrap().try_clone()
+        match &self.fd {
+            Some(fd) => fd.lock().unwrap().try_clone(),
+            None => Err(closed_error().into()),
+        }
     }
 }
 
 impl std::io::Write for Writable {
     fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
-        self.fd.lock().unwrap().write(buf)
+        match &self.fd {
+            Some(fd) => fd.lock().unwrap().write(buf),
+            None => Err(closed_error()),
+        }
     }
 
     fn flush(&mut self) -> Result<(), std::io::Error> {
-        self.fd.lock().unwrap().flush()
+        match &self.fd {
+            Some(fd) => fd.lock().unwrap().flush(),
+            None => Err(closed_error()),
+        }
     }
 }
 
@@ -68,20 +101,25 @@ impl IoEnvironment {
     }
 
     pub fn stdin(&self) -> Readable {
-        let fd = Arc::clone(self.fds.get(&0).expect("stdin fd is missing"));
+        let fd = self.fds.get(&0).map(Arc::clone);
         Readable { fd }
     }
 
     pub fn stdout(&self) -> Writable {
-        let fd = Arc::clone(self.fds.get(&1).expect("stdout fd is missing"));
+        let fd = self.fds.get(&1).map(Arc::clone);
         Writable { fd }
     }
 
     pub fn stderr(&self) -> Writable {
-        let fd = Arc::clone(self.fds.get(&2).expect("stdout fd is missing"));
+        let fd = self.fds.get(&2).map(Arc::clone);
         Writable { fd }
     }
 
+    /// Returns true if fd_number has not been closed
+    pub fn is_open(&self, fd_number: usize) -> bool {
+        self.fds.contains_key(&fd_number)
+    }
+
     pub fn assign_fd(&mut self, fd_number: usize, fd: FileDescriptor) {
         self.fds.insert(fd_number, Arc::new(Mutex::new(fd)));
     }
@@ -96,6 +134,63 @@ impl IoEnvironment {
         Ok(())
     }
 
+    /// Close fd_number.  Closing a descriptor that is not
+    /// open is not an error.
+    pub fn close_fd(&mut self, fd_number: usize) {
+        self.fds.remove(&fd_number);
+    }
+
+    /// Duplicate src_fd as dest_fd and then close src_fd
+    pub fn move_to(&mut self, src_fd: usize, dest_fd: usize) -> anyhow::Result<()> {
+        if src_fd != dest_fd {
+            let fd = self
+                .fds
+                .remove(&src_fd)
+                .ok_or_else(|| anyhow!("move_to: src_fd {} not present", src_fd))?;
+            self.fds.insert(dest_fd, fd);
+        }
+        Ok(())
+    }
+
+    /// Returns the lowest descriptor number greater than or equal
+    /// to `minimum` that is not currently assigned.
+    pub fn next_unused_fd(&self, minimum: usize) -> usize {
+        (minimum..)
+            .find(|fd_number| !self.fds.contains_key(fd_number))
+            .expect("descriptor numbers exhausted")
+    }
+
+    /// Returns N if path is `/dev/fd/N` and N is open in this
+    /// environment.  The descriptor numbers of an IO environment don't
+    /// correspond to those of the shell process, so such a path, as
+    /// produced by process substitution, must be resolved this way
+    /// when it is opened by the shell itself rather than by a child.
+    pub fn fd_number_for_path(&self, path: &Path) -> Option<usize> {
+        let fd_number = path.strip_prefix("/dev/fd").ok()?.to_str()?.parse().ok()?;
+        if self.is_open(fd_number) {
+            Some(fd_number)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the descriptor numbers, along with the underlying
+    /// OS handles, of the descriptors other than stdio that are
+    /// present in this environment.  These need to be passed
+    /// down to child processes explicitly.
+    #[cfg(unix)]
+    pub fn extra_fds(&self) -> Vec<(usize, std::os::unix::io::RawFd)> {
+        use std::os::unix::io::AsRawFd;
+        let mut fds: Vec<_> = self
+            .fds
+            .iter()
+            .filter(|(fd_number, _)| **fd_number > 2)
+            .map(|(fd_number, fd)| (*fd_number, fd.lock().unwrap().as_raw_fd()))
+            .collect();
+        fds.sort();
+        fds
+    }
+
     pub fn fd_as_stdio(&self, fd_number: usize) -> anyhow::Result<std::process::Stdio> {
         let fd = self
             .fds
@@ -2,21 +2,30 @@
 use anyhow::{anyhow, bail, Error};
 use bstr::{BStr, BString};
 use filedescriptor::FileDescriptor;
+use shell_lexer::Span;
 use std::collections::VecDeque;
 use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
+pub mod arith;
+mod disasm;
 mod environment;
 mod host;
 mod ioenv;
 
 pub mod op;
+mod options;
+mod timeformat;
+pub use disasm::*;
 pub use environment::*;
 pub use host::*;
 pub use ioenv::*;
 pub use op::Operation;
 use op::*;
+pub use options::*;
+pub use timeformat::*;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
@@ -62,6 +71,37 @@ impl Value {
         }
     }
 
+    /// Interpret a list built up for `name=(...)` as the elements
+    /// to pass to Environment::assign_array.  Each item is either
+    /// a value or a list holding a subscript and a value.
+    pub fn array_elements(&self) -> anyhow::Result<Vec<(Option<String>, OsString)>> {
+        let list = match self {
+            Value::List(list) => list,
+            _ => bail!("array elements are not a list"),
+        };
+        let mut elements = vec![];
+        for element in list {
+            let (subscript, value) = match element {
+                Value::List(pair) if pair.len() == 2 => (
+                    Some(
+                        pair[0]
+                            .as_str()
+                            .ok_or_else(|| anyhow!("array subscript is not a string"))?
+                            .to_owned(),
+                    ),
+                    &pair[1],
+                ),
+                value => (None, value),
+            };
+            let value = value
+                .as_os_str()
+                .ok_or_else(|| anyhow!("array element is not a string"))?
+                .to_os_string();
+            elements.push((subscript, value));
+        }
+        Ok(elements)
+    }
+
     pub fn truthy(&self) -> bool {
         match self {
             Value::None => false,
@@ -143,21 +183,121 @@ pub enum InstructionAddress {
     Relative(isize),
 }
 
+/// Identifies a line in the source of a program; file is None
+/// for input that didn't come from a file, such as that
+/// entered interactively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: Option<String>,
+    /// The zero-based line number
+    pub line: usize,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(fmt, "{}:{}", file, self.line + 1),
+            None => write!(fmt, "line {}", self.line + 1),
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct Program {
     opcodes: Vec<Operation>,
+    /// The source span that each of the opcodes was compiled from,
+    /// if known.  This is either empty or the same length as opcodes.
+    spans: Vec<Option<Span>>,
+    /// The addresses at which each simple command and function
+    /// definition begins, in ascending order.  These are the places
+    /// at which a debugger pauses when stepping through the program.
+    commands: Vec<usize>,
+    /// The name of the file that the program was compiled from
+    source_name: Option<String>,
 }
 
 impl Program {
     pub fn new(opcodes: Vec<Operation>) -> Arc<Program> {
-        Arc::new(Self { opcodes })
+        Arc::new(Self {
+            opcodes,
+            ..Default::default()
+        })
+    }
+
+    /// Create a program that knows the source span of each of its
+    /// opcodes, so that errors can indicate where they occurred, and
+    /// the addresses at which its commands begin
+    pub fn with_spans(
+        opcodes: Vec<Operation>,
+        spans: Vec<Option<Span>>,
+        commands: Vec<usize>,
+        source_name: Option<String>,
+    ) -> anyhow::Result<Arc<Program>> {
+        if spans.len() != opcodes.len() {
+            bail!(
+                "{} spans were provided for {} opcodes",
+                spans.len(),
+                opcodes.len()
+            );
+        }
+        Ok(Arc::new(Self {
+            opcodes,
+            spans,
+            commands,
+            source_name,
+        }))
     }
 
     pub fn opcodes(&self) -> &[Operation] {
         &self.opcodes
     }
+
+    /// Returns the source span of the opcode at address
+    pub fn span(&self, address: usize) -> Option<Span> {
+        self.spans.get(address).copied().flatten()
+    }
+
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_name.as_deref()
+    }
+
+    /// Returns true if a simple command or function definition
+    /// begins at address
+    pub fn is_command_start(&self, address: usize) -> bool {
+        self.commands.binary_search(&address).is_ok()
+    }
+
+    /// Returns a human readable listing of the opcodes
+    pub fn disassemble(&self) -> Disassembly<'_> {
+        Disassembly::new(self)
+    }
+}
+
+/// An error that occurred while running a program.  The location
+/// of the failing opcode is retained so that the corresponding
+/// source can be shown alongside the error; its address is shown
+/// only by the Debug representation.
+#[derive(Debug)]
+pub struct RuntimeError {
+    /// The address of the opcode that failed
+    pub pc: usize,
+    /// The source span of the opcode that failed, if known
+    pub span: Option<Span>,
+    /// The name of the file that the failing program was compiled from
+    pub source_name: Option<String>,
+    /// The function calls that were in progress, with the outermost first
+    pub backtrace: Vec<CallSite>,
+    pub error: Error,
 }
 
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
 #[derive(Debug, Default)]
 pub struct Frame {
     /// Absolute index to the top of the stack including the
@@ -171,21 +311,51 @@ pub struct Frame {
     frame_size: usize,
 }
 
+/// The state of the caller that is saved by Call and restored by Return
+#[derive(Debug)]
+pub struct CallFrame {
+    /// The program and the address at which to resume the caller
+    program: Arc<Program>,
+    return_address: usize,
+    /// Where the status of the call is stored in the caller's frame
+    status: Operand,
+    /// The positional parameters of the caller
+    positional: Vec<Value>,
+    /// The depth of the frame, environment and IO environment stacks
+    /// at the point of the call
+    frames: usize,
+    environment: usize,
+    io_env: usize,
+    /// The depth of the call stack in the environment, before
+    /// this call was pushed onto it
+    call_stack: usize,
+    /// The number of conditions being evaluated by the caller
+    condition_depth: usize,
+}
+
 #[derive(Debug, Default)]
 pub struct Machine {
     stack: VecDeque<Value>,
     frames: VecDeque<Frame>,
     environment: VecDeque<Environment>,
     io_env: VecDeque<IoEnvironment>,
-    positional: Vec<Value>,
     cwd: PathBuf,
     host: Option<Arc<dyn ShellHost>>,
     pipes: VecDeque<FileDescriptor>,
+    timers: VecDeque<(Instant, CpuTimes)>,
 
     program: Arc<Program>,
     program_counter: usize,
+    /// The callers of the function calls that are in progress
+    calls: VecDeque<CallFrame>,
+    /// The number of conditions, such as those of `if`, that are
+    /// being evaluated; errexit doesn't apply while it is non-zero
+    condition_depth: usize,
 
     last_wait_status: Option<Value>,
+    /// The statuses of commands spawned concurrently that have yet
+    /// to be collected by WaitConcurrent
+    concurrent: Vec<WaitableStatus>,
 }
 
 /// This enum is essentially why this vm exists; it allows stepping
@@ -252,8 +422,17 @@ impl Machine {
         })
     }
 
+    /// Replace the IO environment that the program starts with, such
+    /// as with that of the command that invoked a function
+    pub fn set_io_env(&mut self, io_env: IoEnvironment) {
+        self.io_env.clear();
+        self.io_env.push_back(io_env);
+    }
+
     pub fn set_positional(&mut self, argv: Vec<Value>) {
-        self.positional = argv;
+        if let Some(env) = self.environment.back_mut() {
+            env.set_positional(argv);
+        }
     }
 
     pub fn top_environment(&self) -> (PathBuf, Environment) {
@@ -264,7 +443,7 @@ impl Machine {
         self.host = Some(host)
     }
 
-    fn environment(&self) -> anyhow::Result<&Environment> {
+    pub fn environment(&self) -> anyhow::Result<&Environment> {
         self.environment
             .back()
             .ok_or_else(|| anyhow!("no current environment"))
@@ -293,6 +472,22 @@ impl Machine {
             .ok_or_else(|| anyhow!("no current IoEnvironment"))
     }
 
+    /// Returns the program that is running, which is that of the
+    /// innermost function call, if any
+    pub fn program(&self) -> &Arc<Program> {
+        &self.program
+    }
+
+    /// Returns the address of the next op to be run in program()
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    /// Returns the number of function calls in progress
+    pub fn call_depth(&self) -> usize {
+        self.calls.len()
+    }
+
     /// Attempt to make a single step of progress with the program.
     pub fn step(&mut self) -> anyhow::Result<Status> {
         let program = Arc::clone(&self.program);
@@ -308,11 +503,53 @@ impl Machine {
                 self.program_counter -= 1;
                 status
             }
-            Err(e) => Err(anyhow!("PC={}: {}", pc, e)),
+            Err(error) => {
+                let backtrace = self
+                    .environment()
+                    .map(|env| env.call_stack().to_vec())
+                    .unwrap_or_default();
+                self.unwind_calls();
+                Err(RuntimeError {
+                    pc,
+                    span: program.span(pc),
+                    source_name: program.source_name().map(str::to_owned),
+                    backtrace,
+                    error,
+                }
+                .into())
+            }
             status => status,
         }
     }
 
+    /// Pop frames until only depth of them remain
+    fn truncate_frames(&mut self, depth: usize) {
+        while self.frames.len() > depth {
+            if let Some(frame) = self.frames.pop_back() {
+                self.stack
+                    .resize(frame.frame_pointer - frame.frame_size, Value::None);
+            }
+        }
+    }
+
+    /// Abandon any function calls that are in progress, restoring
+    /// the positional parameters and the stacks of the outermost caller
+    fn unwind_calls(&mut self) {
+        let outermost = self.calls.drain(..).next();
+        if let Some(call) = outermost {
+            self.truncate_frames(call.frames);
+            self.environment.truncate(call.environment);
+            self.io_env.truncate(call.io_env);
+            if let Some(env) = self.environment.back_mut() {
+                env.set_positional(call.positional);
+                env.truncate_call_stack(call.call_stack);
+            }
+            self.condition_depth = call.condition_depth;
+            self.program = call.program;
+            self.program_counter = call.return_address;
+        }
+    }
+
     /// Continually invoke step() while the status == Running.
     /// Returns either Stopped or Complete at the appropriate time.
     pub fn run(&mut self) -> anyhow::Result<Status> {
@@ -431,11 +668,14 @@ impl Machine {
     }
 }
 
+/// Note that `{` is not considered to be special here: brace
+/// expressions are expanded by the compiler before the glob is
+/// evaluated, so any braces that remain are literal.
 fn contains_glob_specials(v: &Value) -> bool {
     match v.as_str() {
         Some(s) => {
             for c in s.chars() {
-                if c == '*' || c == '[' || c == '{' {
+                if c == '*' || c == '[' {
                     return true;
                 }
             }
@@ -458,8 +698,12 @@ mod test {
         Machine::new(&prog(ops), None, &std::env::current_dir().unwrap()).unwrap()
     }
 
-    fn run_err(m: &mut Machine) -> String {
-        format!("{}", m.run().unwrap_err())
+    /// Returns the address of the opcode that failed, if known,
+    /// and the error
+    fn run_err(m: &mut Machine) -> (Option<usize>, String) {
+        let err = m.run().unwrap_err();
+        let pc = err.downcast_ref::<RuntimeError>().map(|err| err.pc);
+        (pc, format!("{}", err))
     }
 
     #[test]
@@ -476,13 +720,13 @@ mod test {
         let mut m = machine(&[Operation::Exit(Exit {
             value: Operand::FrameRelative(0),
         })]);
-        assert_eq!(run_err(&mut m), "PC=0: no frame?");
+        assert_eq!(run_err(&mut m), (Some(0), "no frame?".to_owned()));
     }
 
     #[test]
     fn test_pop_too_many_frames() {
         let mut m = machine(&[Operation::PopFrame(PopFrame {})]);
-        assert_eq!(run_err(&mut m), "PC=0: frame underflow");
+        assert_eq!(run_err(&mut m), (Some(0), "frame underflow".to_owned()));
     }
 
     #[test]
@@ -493,7 +737,10 @@ mod test {
                 value: Operand::FrameRelative(0),
             }),
         ]);
-        assert_eq!(run_err(&mut m), "PC=1: FrameRelative offset out of range");
+        assert_eq!(
+            run_err(&mut m),
+            (Some(1), "FrameRelative offset out of range".to_owned())
+        );
     }
 
     #[test]
@@ -504,14 +751,20 @@ mod test {
         })]);
         assert_eq!(
             run_err(&mut m),
-            "PC=0: cannot mutably reference an Immediate operand"
+            (
+                Some(0),
+                "cannot mutably reference an Immediate operand".to_owned()
+            )
         );
     }
 
     #[test]
     fn test_unterminated() {
         let mut m = machine(&[]);
-        assert_eq!(run_err(&mut m), "walked off the end of the program");
+        assert_eq!(
+            run_err(&mut m),
+            (None, "walked off the end of the program".to_owned())
+        );
     }
 
     #[test]
@@ -539,4 +792,23 @@ mod test {
         assert_eq!(split_by_ifs("foo  bar ", ifs), vec!["foo", "bar"]);
         assert_eq!(split_by_ifs("\t foo  bar ", ifs), vec!["foo", "bar"]);
     }
+
+    #[test]
+    fn test_format_times() {
+        use std::time::Duration;
+        let real = Duration::from_millis(83_250);
+        let user = Duration::from_millis(1_500);
+        let system = Duration::from_millis(250);
+        let format = |f| format_times(f, real, user, system);
+
+        assert_eq!(format("%R %U %S"), "83.250 1.500 0.250");
+        assert_eq!(format("%0R %1U %2S"), "83 1.5 0.25");
+        assert_eq!(format("%lR %0lU"), "1m23.250s 0m2s");
+        assert_eq!(format("%P%%"), "2.10%");
+        assert_eq!(format("%X %5R %"), "%X 83.250 %");
+        assert_eq!(
+            format(DEFAULT_TIMEFORMAT),
+            "\nreal\t1m23.250s\nuser\t0m1.500s\nsys\t0m0.250s"
+        );
+    }
 }
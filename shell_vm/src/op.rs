@@ -63,6 +63,31 @@ impl Dispatch for Operation {
         }
     }
 }
+
+impl Operation {
+    /// Returns the name of the op, such as `Copy`
+    pub fn name(&self) -> &'static str {
+        match self {
+            $(
+                Operation::$name(_) => stringify!($name),
+            )*
+        }
+    }
+
+    /// Returns the names and values of the fields of the op, for
+    /// the purpose of disassembling it
+    pub fn fields(&self) -> Vec<(&'static str, &dyn OpField)> {
+        match self {
+            $(
+                Operation::$name(_inner) => vec![
+                    $(
+                        (stringify!($field), &_inner.$field as &dyn OpField),
+                    )*
+                ],
+            )*
+        }
+    }
+}
     };
 }
 
@@ -80,7 +105,8 @@ op!(
         source: Operand,
         destination: Operand
     },
-    /// Evaluates to the length of the specified string operand
+    /// Evaluates to the length of the specified string operand,
+    /// or to the number of elements if it is a list
     StringLength {
         string: Operand,
         length: Operand,
@@ -90,7 +116,8 @@ op!(
     /// the string is converted to an integer and that value is
     /// returned to the host program.
     Exit { value: Operand },
-    /// Emit an error and halt the program
+    /// Halt the program with an error, which carries the message
+    /// and the location of this op
     Error { message: Operand },
     /// Append the value from the source to the list value at the destination.
     /// If split is true, split value using the current IFS value
@@ -156,20 +183,58 @@ op!(
     PushIo {},
     /// Pop the top of the IO environment stack
     PopIo {},
+    /// Record the current time and CPU usage on the timer stack.
+    PushTimer {},
+    /// Pop the most recently pushed timer off the timer stack and
+    /// report the time elapsed since it was pushed to stderr, in the
+    /// format given by the TIMEFORMAT variable, or in the POSIX
+    /// format if posix_format is true.
+    PopTimer { posix_format: bool },
     /// Create a pair of connected pipes and assign the writable
     /// end as stdout in the current IO environment, and push
     /// the readable end on to the pipe stack.
     PushPipe {},
     /// Pop the most recently pushed readable pipe end off the
-    /// pipe stack and assign it as stdin in the current IO
-    /// environment
-    PopPipe {},
+    /// pipe stack and assign it as fd_number (usually stdin)
+    /// in the current IO environment
+    PopPipe { fd_number: usize },
+    /// Create a pair of connected pipes and assign the readable
+    /// end as stdin in the current IO environment, and push
+    /// the writable end on to the pipe stack.
+    PushStdinPipe {},
+    /// Pop the most recently pushed pipe end off the pipe stack
+    /// and assign it to an unused descriptor number in the current
+    /// IO environment.  The `/dev/fd/N` path that refers to that
+    /// descriptor is stored into path.
+    PopPipeFd { path: Operand },
+    /// Write the string value to stdout in the current IO
+    /// environment.  The write is performed by a background
+    /// thread so that a value larger than the pipe buffer cannot
+    /// deadlock against a reader that has yet to be spawned.
+    WriteOutput { value: Operand },
     /// Duplicate the src_fd number as dest_fd in the current
     /// IO environment.
     DupFd {
         src_fd: usize,
         dest_fd: usize,
     },
+    /// Duplicate the src_fd number as dest_fd in the current
+    /// IO environment, then close src_fd.
+    MoveFd {
+        src_fd: usize,
+        dest_fd: usize,
+    },
+    /// Close fd_number in the current IO environment
+    CloseFd { fd_number: usize },
+    /// Like DupFd, except that the src_fd number is a runtime
+    /// value, such as the result of expanding `$fd`.
+    DupFdFrom { src_fd: Operand, dest_fd: usize },
+    /// Like CloseFd, except that fd_number is a runtime value.
+    CloseFdFrom { fd_number: Operand },
+    /// Move src_fd in the current IO environment to the lowest
+    /// unused descriptor number that is at least FIRST_SPARE_FD,
+    /// and store that number into fd_number.
+    AllocateFd { src_fd: usize, fd_number: Operand },
     /// Open a file and assign it as fd_number in the current IO environment
     OpenFile {
         /// The file to open.  Can either be an immediate string
@@ -188,11 +253,15 @@ op!(
     PushEnvironment {},
     /// Pop the top of the environment stack
     PopEnvironment {},
-    /// Set a variable in the current environment
+    /// Assign a variable in the current environment.  This fails
+    /// if the variable is readonly.
     SetEnv {
         name: Operand,
         value: Operand,
     },
+    /// Set the exported attribute of the named variable in the
+    /// current environment, so that it is passed to spawned processes.
+    ExportEnv { name: Operand },
     /// Get a variable from the current environment and store it
     /// into the destination.  If the variable isn't present,
     /// Value::None is stored instead.
@@ -200,6 +269,32 @@ op!(
         name: Operand,
         target: Operand,
     },
+    /// Get the element at subscript from the array variable name
+    /// and store it into the target.  If the element isn't present,
+    /// Value::None is stored instead.
+    GetArrayElement {
+        name: Operand,
+        subscript: Operand,
+        target: Operand,
+    },
+    /// Store the list of values of the array variable name into
+    /// the target.  A scalar variable yields a list of one value.
+    GetArrayValues { name: Operand, target: Operand },
+    /// Store the list of indices or keys of the array variable name
+    /// into the target.
+    GetArrayKeys { name: Operand, target: Operand },
+    /// Assign value to the element at subscript in the array
+    /// variable name.
+    SetArrayElement {
+        name: Operand,
+        subscript: Operand,
+        value: Operand,
+    },
+    /// Replace the array variable name with the list of elements.
+    /// Each element is either a value, which is assigned to the
+    /// index following the prior element, or a list holding
+    /// a subscript and a value.
+    SetArray { name: Operand, elements: Operand },
     /// Perform tilde expansion on the input and store in the output.
     TildeExpand {
         name: Operand,
@@ -228,19 +323,58 @@ op!(
     /// Invokes ShellHost::spawn_command, passing the argument vector specified.
     /// The resultant WaitableStatus value is stored into the status operand.
     /// This does not automatically wait for the command to complete.
+    /// If concurrent is true, ShellHost::spawn_concurrent_command is used
+    /// instead, and the status is also retained for WaitConcurrent.
     SpawnCommand {
         argv: Operand,
         status: Operand,
+        concurrent: bool,
     },
+    /// Run program by way of ShellHost::spawn_concurrent_program, with a
+    /// copy of the current environment.  Its status is retained for
+    /// WaitConcurrent, just as for a concurrent SpawnCommand.
+    SpawnConcurrentProgram { program: Arc<Program> },
     /// Wait for the status of a WaitableStatus to change.
     /// This calls WaitableStatus::wait and may be subject to spurious wakeups.
     Wait { status: Operand },
+    /// Wait for the most recent count commands that were spawned with
+    /// the concurrent flag, such as the stages of a pipeline, to complete.
+    /// The status of the last of them becomes the last wait status.
+    WaitConcurrent { count: usize },
     /// Invert the truthiness of the last wait status
     InvertLastWait {},
+    /// If the errexit option is enabled and the last wait status
+    /// indicates failure, complete the program with that status.
+    /// Nothing happens while a condition is being evaluated.
+    ErrExit {},
+    /// Begin evaluating a condition, such as that of an `if`, whose
+    /// status is tested.  Until the matching LeaveCondition, commands,
+    /// including those in the functions that they call, are exempt
+    /// from errexit.
+    EnterCondition {},
+    /// Finish evaluating the condition begun by EnterCondition
+    LeaveCondition {},
+    /// Call the function program, passing argv as its positional
+    /// parameters.  The function runs on this machine until it executes
+    /// Return, at which point the caller resumes with the status of the
+    /// function stored into status.  Functions are resolved at runtime,
+    /// so this is dispatched by SpawnCommand rather than being emitted
+    /// by the compiler.
+    /// The call depth is limited by the FUNCNEST variable.
+    Call {
+        program: Arc<Program>,
+        argv: Operand,
+        status: Operand,
+    },
+    /// Return from the current function call with the specified value.
+    /// Outside of a function call, this terminates the program in the
+    /// same way as Exit.
+    Return { value: Operand },
     /// Define a function
     DefineFunction {
         name: String,
         program: Arc<Program>,
+        location: SourceLocation,
     },
 );
 
@@ -306,11 +440,94 @@ impl Dispatch for PushPipe {
 
 impl Dispatch for PopPipe {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
-        let stdin = machine
+        let fd = machine
             .pipes
             .pop_back()
             .ok_or_else(|| anyhow!("pipe stack underflow"))?;
-        machine.io_env_mut()?.assign_fd(0, stdin);
+        machine.io_env_mut()?.assign_fd(self.fd_number, fd);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for WriteOutput {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let data = machine
+            .operand(&self.value)?
+            .as_bstr()
+            .ok_or_else(|| anyhow!("WriteOutput: value is not representable as a BStr"))?
+            .to_bstring();
+        let mut stdout = machine.io_env()?.stdout();
+        std::thread::spawn(move || {
+            // The reader is free to exit without consuming everything,
+            // so a failure to write is not an error here.
+            stdout.write_all(data.as_ref()).ok();
+        });
+        Ok(Status::Running)
+    }
+}
+
+fn host_cpu_times(machine: &Machine) -> anyhow::Result<CpuTimes> {
+    match &machine.host {
+        Some(host) => host.cpu_times(),
+        None => Ok(CpuTimes::default()),
+    }
+}
+
+impl Dispatch for PushTimer {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let cpu_times = host_cpu_times(machine)?;
+        machine.timers.push_back((Instant::now(), cpu_times));
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for PopTimer {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let (start, start_cpu) = machine
+            .timers
+            .pop_back()
+            .ok_or_else(|| anyhow!("timer stack underflow"))?;
+        let real = start.elapsed();
+        let cpu = host_cpu_times(machine)?;
+        let user = cpu.user.saturating_sub(start_cpu.user);
+        let system = cpu.system.saturating_sub(start_cpu.system);
+
+        let format = if self.posix_format {
+            POSIX_TIMEFORMAT.to_string()
+        } else {
+            match machine.environment()?.get("TIMEFORMAT") {
+                Some(format) => format.to_string_lossy().into_owned(),
+                None => DEFAULT_TIMEFORMAT.to_string(),
+            }
+        };
+        // A null TIMEFORMAT suppresses the report
+        if !format.is_empty() {
+            let report = format_times(&format, real, user, system);
+            writeln!(machine.io_env()?.stderr(), "{}", report)?;
+        }
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for PushStdinPipe {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let pipe = Pipe::new()?;
+        machine.io_env_mut()?.assign_fd(0, pipe.read);
+        machine.pipes.push_back(pipe.write);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for PopPipeFd {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let fd = machine
+            .pipes
+            .pop_back()
+            .ok_or_else(|| anyhow!("pipe stack underflow"))?;
+        let io_env = machine.io_env_mut()?;
+        let fd_number = io_env.next_unused_fd(FIRST_SPARE_FD);
+        io_env.assign_fd(fd_number, fd);
+        *machine.operand_mut(&self.path)? = format!("/dev/fd/{}", fd_number).into();
         Ok(Status::Running)
     }
 }
@@ -336,32 +553,28 @@ impl Dispatch for PopIo {
 impl Dispatch for GetEnv {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let name = machine.operand_as_str(&self.name)?;
+        let positional = machine.environment()?.positional();
         if name == "@" || name == "*" {
             /*
             let joined = join_list_ifs(
                 machine,
-                Value::List(machine.positional.iter().skip(1).cloned().collect()),
+                Value::List(positional.iter().skip(1).cloned().collect()),
             )?;
             *machine.operand_mut(&self.target)? = joined.into();
             */
             *machine.operand_mut(&self.target)? =
-                Value::List(machine.positional.iter().skip(1).cloned().collect());
+                Value::List(positional.iter().skip(1).cloned().collect());
         } else if name == "#" {
             *machine.operand_mut(&self.target)? =
-                Value::String(machine.positional.len().saturating_sub(1).to_string());
+                Value::String(positional.len().saturating_sub(1).to_string());
         } else if let Ok(numeric) = name.parse::<usize>() {
             let value = if numeric == 0 {
-                machine
-                    .positional
+                positional
                     .first()
                     .cloned()
                     .unwrap_or_else(|| Value::String("wzsh".to_owned()))
             } else {
-                machine
-                    .positional
-                    .get(numeric)
-                    .cloned()
-                    .unwrap_or(Value::None)
+                positional.get(numeric).cloned().unwrap_or(Value::None)
             };
             *machine.operand_mut(&self.target)? = value;
         } else {
@@ -376,55 +589,140 @@ impl Dispatch for GetEnv {
     }
 }
 
+impl Dispatch for GetArrayElement {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_str(&self.name)?;
+        let subscript = machine.operand_as_str(&self.subscript)?;
+        let value = machine
+            .environment()?
+            .get_element(name, subscript)?
+            .map(|x| Value::OsString(x.into()))
+            .unwrap_or(Value::None);
+        *machine.operand_mut(&self.target)? = value;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for GetArrayValues {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_str(&self.name)?;
+        let environment = machine.environment()?;
+        let values = match environment.get_array(name) {
+            Some(array) => array
+                .values()
+                .into_iter()
+                .map(|x| Value::OsString(x.into()))
+                .collect(),
+            None => environment
+                .get(name)
+                .map(|x| Value::OsString(x.into()))
+                .into_iter()
+                .collect(),
+        };
+        *machine.operand_mut(&self.target)? = Value::List(values);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for GetArrayKeys {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_str(&self.name)?;
+        let environment = machine.environment()?;
+        let keys = match environment.get_array(name) {
+            Some(array) => array.keys().into_iter().map(Value::String).collect(),
+            None if environment.get(name).is_some() => vec![Value::String("0".to_owned())],
+            None => vec![],
+        };
+        *machine.operand_mut(&self.target)? = Value::List(keys);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for SetArrayElement {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_str(&self.name)?.to_owned();
+        let subscript = machine.operand_as_str(&self.subscript)?.to_owned();
+        let value = machine.operand_as_os_str(&self.value)?.to_os_string();
+        machine
+            .environment_mut()?
+            .set_element(&name, &subscript, value)?;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for SetArray {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_str(&self.name)?.to_owned();
+        let elements = machine
+            .operand(&self.elements)?
+            .array_elements()
+            .map_err(|err| anyhow!("SetArray: {}", err))?;
+        machine.environment_mut()?.assign_array(&name, elements)?;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for ExportEnv {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let name = machine.operand_as_os_str(&self.name)?.to_os_string();
+        machine.environment_mut()?.export(name);
+        Ok(Status::Running)
+    }
+}
+
 impl Dispatch for SetEnv {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let name = machine.operand_as_os_str(&self.name)?.to_os_string();
         let value = machine.operand_as_os_str(&self.value)?.to_os_string();
-        machine.environment_mut()?.set(name, value);
+        machine.environment_mut()?.assign(name, value)?;
         Ok(Status::Running)
     }
 }
 
-impl Dispatch for Exit {
-    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
-        let value = match machine.operand(&self.value)? {
-            Value::String(s) => {
+/// Resolve the value operand of Exit or Return.  A string that can be
+/// represented as an integer is converted to that integer, and a
+/// waitable status is resolved to its completed value.
+fn exit_value(machine: &Machine, value: &Operand) -> anyhow::Result<Value> {
+    Ok(match machine.operand(value)? {
+        Value::String(s) => {
+            if let Ok(n) = isize::from_str_radix(s, 10) {
+                n.into()
+            } else {
+                s.into()
+            }
+        }
+        Value::OsString(s) => {
+            if let Some(s) = s.to_str() {
                 if let Ok(n) = isize::from_str_radix(s, 10) {
                     n.into()
                 } else {
                     s.into()
                 }
+            } else {
+                Value::OsString(s.to_os_string())
             }
-            Value::OsString(s) => {
-                if let Some(s) = s.to_str() {
-                    if let Ok(n) = isize::from_str_radix(s, 10) {
-                        n.into()
-                    } else {
-                        s.into()
-                    }
-                } else {
-                    Value::OsString(s.to_os_string())
-                }
-            }
-            Value::WaitableStatus(s) => match s.wait() {
-                Some(Status::Complete(n)) => n,
-                _ => bail!("last wait status is not complete!?"),
-            },
-            value => value.clone(),
-        };
-        Ok(Status::Complete(value))
+        }
+        Value::WaitableStatus(s) => match s.wait() {
+            Some(Status::Complete(n)) => n,
+            _ => bail!("last wait status is not complete!?"),
+        },
+        value => value.clone(),
+    })
+}
+
+impl Dispatch for Exit {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        Ok(Status::Complete(exit_value(machine, &self.value)?))
     }
 }
 
 impl Dispatch for Error {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
-        let mut stderr = machine.io_env_mut()?.stderr();
         match machine.operand(&self.message)? {
-            Value::String(s) => write!(stderr, "{}", s),
-            Value::OsString(s) => write!(stderr, "{:?}", s),
-            value => write!(stderr, "{:?}", value),
-        }?;
-        Ok(Status::Complete(1.into()))
+            Value::String(s) => bail!("{}", s),
+            Value::OsString(s) => bail!("{}", s.to_string_lossy()),
+            value => bail!("{:?}", value),
+        }
     }
 }
 
@@ -432,6 +730,7 @@ impl Dispatch for StringLength {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let len = match machine.operand(&self.string)? {
             Value::String(s) => s.len(),
+            Value::List(list) => list.len(),
             Value::None => 0,
             Value::OsString(s) => s.len(),
             value => bail!(
@@ -559,6 +858,54 @@ impl Dispatch for DupFd {
     }
 }
 
+impl Dispatch for MoveFd {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        machine.io_env_mut()?.move_to(self.src_fd, self.dest_fd)?;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for CloseFd {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        machine.io_env_mut()?.close_fd(self.fd_number);
+        Ok(Status::Running)
+    }
+}
+
+fn operand_as_fd_number(machine: &Machine, operand: &Operand) -> anyhow::Result<usize> {
+    let value = machine.operand_as_str(operand)?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{:?}: invalid file descriptor number", value))
+}
+
+impl Dispatch for DupFdFrom {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let src_fd = operand_as_fd_number(machine, &self.src_fd)?;
+        machine.io_env_mut()?.duplicate_to(src_fd, self.dest_fd)?;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for CloseFdFrom {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let fd_number = operand_as_fd_number(machine, &self.fd_number)?;
+        machine.io_env_mut()?.close_fd(fd_number);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for AllocateFd {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let io_env = machine.io_env_mut()?;
+        let fd_number = io_env.next_unused_fd(FIRST_SPARE_FD);
+        io_env.move_to(self.src_fd, fd_number)?;
+        *machine.operand_mut(&self.fd_number)? = fd_number.to_string().into();
+        Ok(Status::Running)
+    }
+}
+
 impl Dispatch for OpenFile {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let file_name = match machine.operand(&self.name)? {
@@ -576,6 +923,10 @@ impl Dispatch for OpenFile {
             invalid => bail!("invalid redirection to {:?}", invalid),
         };
         let file_name = PathBuf::from(file_name);
+        if let Some(src_fd) = machine.io_env()?.fd_number_for_path(&file_name) {
+            machine.io_env_mut()?.duplicate_to(src_fd, self.fd_number)?;
+            return Ok(Status::Running);
+        }
         let file_name = if file_name.is_absolute() {
             file_name
         } else {
@@ -750,6 +1101,51 @@ impl Dispatch for Wait {
     }
 }
 
+impl Dispatch for SpawnConcurrentProgram {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let host = machine.host.as_ref().ok_or_else(|| {
+            anyhow!("unable to SpawnConcurrentProgram because no shell host has been configured")
+        })?;
+        let env = machine
+            .environment
+            .back()
+            .ok_or_else(|| anyhow!("SpawnConcurrentProgram: no current environment"))?;
+        let io_env = machine
+            .io_env
+            .back()
+            .ok_or_else(|| anyhow!("SpawnConcurrentProgram: no current io_env"))?;
+        let status = host.spawn_concurrent_program(&self.program, env, &machine.cwd, io_env)?;
+        machine.concurrent.push(status);
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for WaitConcurrent {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let start = machine
+            .concurrent
+            .len()
+            .checked_sub(self.count)
+            .ok_or_else(|| anyhow!("WaitConcurrent: only {} commands", machine.concurrent.len()))?;
+        for status in &machine.concurrent[start..] {
+            match status.wait() {
+                None | Some(Status::Running) => {
+                    // Spurious wakeup: Ensure that we don't advance the
+                    // program counter yet
+                    machine.program_counter -= 1;
+                    return Ok(Status::Running);
+                }
+                Some(Status::Stopped) => return Ok(Status::Stopped),
+                Some(Status::Complete(_)) => {}
+            }
+        }
+        if let Some(status) = machine.concurrent.split_off(start).pop() {
+            machine.last_wait_status = Some(Value::WaitableStatus(status));
+        }
+        Ok(Status::Running)
+    }
+}
+
 impl Dispatch for InvertLastWait {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let inverted_status = match machine.last_wait_status.take() {
@@ -772,6 +1168,56 @@ impl Dispatch for InvertLastWait {
     }
 }
 
+impl Dispatch for ErrExit {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let failed = match &machine.last_wait_status {
+            Some(status) => !status.truthy(),
+            None => false,
+        };
+        if failed
+            && machine.condition_depth == 0
+            && machine.environment()?.is_option_set(ShellOption::ErrExit)
+        {
+            return Exit {
+                value: Operand::LastWaitStatus,
+            }
+            .dispatch(machine);
+        }
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for EnterCondition {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        machine.condition_depth += 1;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for LeaveCondition {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        machine.condition_depth = machine
+            .condition_depth
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("LeaveCondition without EnterCondition"))?;
+        Ok(Status::Running)
+    }
+}
+
+/// Quote an argument for display in an xtrace line, so that
+/// it could be pasted back into the shell
+fn xtrace_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
 impl Dispatch for SpawnCommand {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let argv = match machine.operand(&self.argv)? {
@@ -779,10 +1225,45 @@ impl Dispatch for SpawnCommand {
             argv => bail!("SpawnCommand argv must be a list, got {:?}", argv),
         };
 
+        if !argv.is_empty() && machine.environment()?.is_option_set(ShellOption::XTrace) {
+            let trace: Vec<String> = argv
+                .iter()
+                .map(|arg| {
+                    xtrace_quote(
+                        &arg.as_os_str()
+                            .map(|s| s.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| format!("{:?}", arg)),
+                    )
+                })
+                .collect();
+            // The trace goes to the stderr of the shell itself, rather
+            // than any redirection that applies to the command
+            let io_env = machine
+                .io_env
+                .front()
+                .ok_or_else(|| anyhow!("SpawnCommand: no io_env"))?;
+            writeln!(io_env.stderr(), "+ {}", trace.join(" "))?;
+        }
+
         let host = machine.host.as_mut().ok_or_else(|| {
             anyhow!("unable to SpawnCommand because no shell host has been configured")
         })?;
 
+        if !self.concurrent {
+            let function = argv
+                .first()
+                .and_then(Value::as_str)
+                .and_then(|name| host.lookup_function(name));
+            if let Some(program) = function {
+                return Call {
+                    program,
+                    argv: self.argv.clone(),
+                    status: self.status.clone(),
+                }
+                .dispatch(machine);
+            }
+        }
+
         let env = machine
             .environment
             .back_mut()
@@ -792,20 +1273,135 @@ impl Dispatch for SpawnCommand {
             .back_mut()
             .ok_or_else(|| anyhow!("SpawnCommand: no current io_env"))?;
 
+        if self.concurrent {
+            let status = host.spawn_concurrent_command(&argv, env, &machine.cwd, io_env)?;
+            machine.concurrent.push(status.clone());
+            *machine.operand_mut(&self.status)? = Value::WaitableStatus(status);
+            return Ok(Status::Running);
+        }
+
         let status = host.spawn_command(&argv, env, &mut machine.cwd, io_env)?;
 
+        if let Some(request) = env.exit_request() {
+            // The `exit` builtin, possibly within a function, has asked
+            // for the shell to exit: complete the program now
+            let status = match request {
+                ExitRequest::Status(n) => n,
+                ExitRequest::LastStatus => match &machine.last_wait_status {
+                    Some(Value::WaitableStatus(status)) => match status.poll() {
+                        Some(Status::Complete(Value::Integer(n))) => n,
+                        _ => 0,
+                    },
+                    Some(Value::Integer(n)) => *n,
+                    _ => env.last_status(),
+                },
+            };
+            // Record the resolved status in the outermost environment,
+            // where it survives PopEnvironment and is seen by our caller
+            machine
+                .environment
+                .front_mut()
+                .ok_or_else(|| anyhow!("SpawnCommand: no environment"))?
+                .request_exit(ExitRequest::Status(status));
+            return Ok(Status::Complete(status.into()));
+        }
+
         *machine.operand_mut(&self.status)? = Value::WaitableStatus(status);
 
         Ok(Status::Running)
     }
 }
 
+/// The maximum depth of function calls when FUNCNEST is not set
+const DEFAULT_FUNCNEST: usize = 1000;
+
+/// Returns the maximum depth of function calls, or 0 if there is no limit
+fn funcnest(machine: &Machine) -> anyhow::Result<usize> {
+    Ok(match machine.environment()?.get_str("FUNCNEST")? {
+        Some(limit) => limit.trim().parse().unwrap_or(DEFAULT_FUNCNEST),
+        None => DEFAULT_FUNCNEST,
+    })
+}
+
+impl Dispatch for Call {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let argv = match machine.operand(&self.argv)? {
+            Value::List(argv) => argv.clone(),
+            argv => bail!("Call argv must be a list, got {:?}", argv),
+        };
+        let limit = funcnest(machine)?;
+        if limit > 0 && machine.calls.len() >= limit {
+            bail!(
+                "{}: maximum function nesting level exceeded ({})",
+                argv.first().and_then(Value::as_str).unwrap_or("function"),
+                limit
+            );
+        }
+
+        // The SpawnCommand that dispatched us is the call site
+        let call_site = CallSite {
+            function: argv
+                .first()
+                .and_then(Value::as_str)
+                .unwrap_or("function")
+                .to_owned(),
+            span: machine.program.span(machine.program_counter - 1),
+            source_name: machine.program.source_name().map(str::to_owned),
+        };
+
+        let env = machine.environment_mut()?;
+        let positional = env.positional().to_vec();
+        env.set_positional(argv);
+        let call_stack = env.call_stack().len();
+        env.push_call(call_site);
+
+        machine.calls.push_back(CallFrame {
+            program: Arc::clone(&machine.program),
+            return_address: machine.program_counter,
+            status: self.status.clone(),
+            positional,
+            frames: machine.frames.len(),
+            environment: machine.environment.len(),
+            io_env: machine.io_env.len(),
+            call_stack,
+            condition_depth: machine.condition_depth,
+        });
+        machine.program = Arc::clone(&self.program);
+        machine.program_counter = 0;
+        Ok(Status::Running)
+    }
+}
+
+impl Dispatch for Return {
+    fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
+        let value = exit_value(machine, &self.value)?;
+        let call = match machine.calls.pop_back() {
+            Some(call) => call,
+            None => return Ok(Status::Complete(value)),
+        };
+
+        machine.truncate_frames(call.frames);
+        machine.environment.truncate(call.environment);
+        machine.io_env.truncate(call.io_env);
+        // The positional parameters are local to the function call
+        let env = machine.environment_mut()?;
+        env.set_positional(call.positional);
+        env.truncate_call_stack(call.call_stack);
+        machine.condition_depth = call.condition_depth;
+        machine.program = call.program;
+        machine.program_counter = call.return_address;
+
+        *machine.operand_mut(&call.status)? = Value::WaitableStatus(Status::Complete(value).into());
+        Ok(Status::Running)
+    }
+}
+
 impl Dispatch for DefineFunction {
     fn dispatch(&self, machine: &mut Machine) -> anyhow::Result<Status> {
         let host = machine.host.as_mut().ok_or_else(|| {
             anyhow!("unable to DefineFunction because no shell host has been configured")
         })?;
-        host.define_function(&self.name, &self.program)?;
+        host.define_function(&self.name, &self.program, &self.location)?;
         machine.last_wait_status.replace(Value::WaitableStatus(
             Status::Complete(Value::Integer(0)).into(),
         ));
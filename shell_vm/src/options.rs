@@ -0,0 +1,52 @@
+//! Options that alter the behavior of the shell, as managed
+//! by `set -o name` and `set +o name`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShellOption {
+    /// A command name that isn't a function, builtin or executable
+    /// but that names a directory changes to that directory
+    AutoCd,
+    /// Exit as soon as a command fails, unless it is being tested
+    /// by a condition or its status is inverted by `!`
+    ErrExit,
+    /// Print each command and its expanded arguments to stderr
+    /// before running it
+    XTrace,
+}
+
+impl ShellOption {
+    pub const ALL: &'static [ShellOption] = &[
+        ShellOption::AutoCd,
+        ShellOption::ErrExit,
+        ShellOption::XTrace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShellOption::AutoCd => "autocd",
+            ShellOption::ErrExit => "errexit",
+            ShellOption::XTrace => "xtrace",
+        }
+    }
+
+    /// The single letter form of the option, as used by
+    /// `set -x` and on the command line
+    pub fn letter(self) -> Option<char> {
+        match self {
+            ShellOption::AutoCd => None,
+            ShellOption::ErrExit => Some('e'),
+            ShellOption::XTrace => Some('x'),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|opt| opt.name() == name)
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|opt| opt.letter() == Some(letter))
+    }
+}
@@ -0,0 +1,81 @@
+//! Formatting of the report produced by the `time` reserved word,
+//! following the TIMEFORMAT conventions described in
+//! https://www.gnu.org/software/bash/manual/html_node/Bash-Variables.html
+use std::time::Duration;
+
+/// Used when TIMEFORMAT is not set
+pub const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+/// Used by `time -p`
+pub const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+/// Expand the `%` sequences in format:
+/// * `%%` - a literal `%`
+/// * `%[p][l]R` - the elapsed real time in seconds
+/// * `%[p][l]U` - the user CPU time in seconds
+/// * `%[p][l]S` - the system CPU time in seconds
+/// * `%P` - the CPU percentage, computed as (U + S) / R
+///
+/// `p` is the number of fractional digits, from 0 to 3, defaulting
+/// to 3.  `l` selects a longer format that includes minutes, of the
+/// form MMmSS.FFFs.  Unrecognized sequences are copied verbatim.
+pub fn format_times(format: &str, real: Duration, user: Duration, system: Duration) -> String {
+    let mut result = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        let mut sequence = String::from("%");
+        let mut precision = 3;
+        let mut long = false;
+
+        if let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            sequence.push(chars.next().unwrap());
+            precision = digit.min(3) as usize;
+        }
+        if chars.peek() == Some(&'l') {
+            sequence.push(chars.next().unwrap());
+            long = true;
+        }
+
+        let duration = match chars.next() {
+            Some('%') if sequence == "%" => {
+                result.push('%');
+                continue;
+            }
+            Some('P') if sequence == "%" => {
+                let real = real.as_secs_f64();
+                let cpu = user.as_secs_f64() + system.as_secs_f64();
+                let percent = if real > 0.0 { cpu * 100.0 / real } else { 0.0 };
+                result.push_str(&format!("{:.2}", percent));
+                continue;
+            }
+            Some('R') => real,
+            Some('U') => user,
+            Some('S') => system,
+            Some(other) => {
+                sequence.push(other);
+                result.push_str(&sequence);
+                continue;
+            }
+            None => {
+                result.push_str(&sequence);
+                continue;
+            }
+        };
+
+        let seconds = duration.as_secs_f64();
+        if long {
+            let minutes = duration.as_secs() / 60;
+            let seconds = seconds - (minutes * 60) as f64;
+            result.push_str(&format!("{}m{:.*}s", minutes, precision, seconds));
+        } else {
+            result.push_str(&format!("{:.*}", precision, seconds));
+        }
+    }
+
+    result
+}
@@ -0,0 +1,88 @@
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use shell_vm::{CallSite, Environment, IoEnvironment, Status, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+/// Print where the current function was called from.  Without n,
+/// print the line number and file of that call.  With n, print the
+/// line number, the name of the calling function and the file for
+/// the nth caller, where 0 is the current call; the top level of the
+/// shell is named `main`, and a file of `-` means that the call came
+/// from input that wasn't a file.  The status is 1 when there is no
+/// such call.
+pub struct CallerCommand {
+    n: Option<usize>,
+}
+
+/// Describes the call that is n frames away from the innermost
+fn describe_call(stack: &[CallSite], n: Option<usize>) -> Option<String> {
+    let idx = stack.len().checked_sub(n.unwrap_or(0) + 1)?;
+    let call = &stack[idx];
+    let line = call.span.map(|span| span.start.line + 1).unwrap_or(0);
+    let file = call.source_name.as_deref().unwrap_or("-");
+    Some(match n {
+        None => format!("{} {}", line, file),
+        Some(_) => {
+            let function = match idx.checked_sub(1) {
+                Some(parent) => stack[parent].function.as_str(),
+                None => "main",
+            };
+            format!("{} {} {}", line, function, file)
+        }
+    })
+}
+
+impl Builtin for CallerCommand {
+    fn name() -> &'static str {
+        "caller"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        match describe_call(environment.call_stack(), self.n) {
+            Some(description) => {
+                writeln!(io_env.stdout(), "{}", description)?;
+                Ok(Status::Complete(0.into()).into())
+            }
+            None => Ok(Status::Complete(1.into()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shell_lexer::Span;
+
+    fn call(function: &str, line: usize, source_name: Option<&str>) -> CallSite {
+        CallSite {
+            function: function.to_owned(),
+            span: Some(Span::new_to(line, 0, 4)),
+            source_name: source_name.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn describe() {
+        let stack = vec![call("outer", 9, Some("script")), call("inner", 2, None)];
+        assert_eq!(describe_call(&stack, None), Some("3 -".to_owned()));
+        assert_eq!(describe_call(&stack, Some(0)), Some("3 outer -".to_owned()));
+        assert_eq!(
+            describe_call(&stack, Some(1)),
+            Some("10 main script".to_owned())
+        );
+        assert_eq!(describe_call(&stack, Some(2)), None);
+        assert_eq!(describe_call(&[], None), None);
+    }
+}
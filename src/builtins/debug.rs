@@ -0,0 +1,89 @@
+use crate::builtins::Builtin;
+use crate::script::{compile_parsed_script, parse_script};
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use shell_vm::{Environment, IoEnvironment, Status, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+/// Show how the shell understands some commands, for the purpose of
+/// reporting bugs in the shell.  The commands in the string are
+/// parsed and compiled but not run, and their syntax tree and a
+/// disassembly of their bytecode are printed.  With -f, the string
+/// instead names a function, whose bytecode is printed; the syntax
+/// tree of a function is not retained once it has been defined.
+pub struct DebugCommand {
+    /// Print only the syntax tree
+    #[structopt(short = "a", long = "ast")]
+    ast: bool,
+    /// Print only the bytecode
+    #[structopt(short = "b", long = "bytecode")]
+    bytecode: bool,
+    /// The string is the name of a function
+    #[structopt(short = "f")]
+    function: bool,
+    string: String,
+}
+
+impl Builtin for DebugCommand {
+    fn name() -> &'static str {
+        "debug"
+    }
+
+    fn run(
+        &mut self,
+        _environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let both = !self.ast && !self.bytecode;
+        let mut stdout = io_env.stdout();
+
+        if self.function {
+            if self.ast {
+                writeln!(
+                    io_env.stderr(),
+                    "debug: {}: the syntax tree of a function is not retained",
+                    self.string
+                )?;
+                return Ok(Status::Complete(1.into()).into());
+            }
+            return match functions.lookup_function(&self.string) {
+                Some(program) => {
+                    let listing = program
+                        .disassemble()
+                        .with_title(format!("function {}", self.string));
+                    write!(stdout, "{}", listing)?;
+                    Ok(Status::Complete(0.into()).into())
+                }
+                None => {
+                    writeln!(io_env.stderr(), "debug: {}: not a function", self.string)?;
+                    Ok(Status::Complete(1.into()).into())
+                }
+            };
+        }
+
+        let result = parse_script(self.string.as_bytes()).and_then(|command| {
+            if self.ast || both {
+                writeln!(stdout, "{:#?}", command)?;
+            }
+            if self.bytecode || both {
+                let program = compile_parsed_script(&command, "debug")?;
+                write!(stdout, "{}", program.disassemble())?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(Status::Complete(0.into()).into()),
+            Err(err) => {
+                writeln!(io_env.stderr(), "debug: {:#}", err)?;
+                Ok(Status::Complete(1.into()).into())
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use shell_vm::{Attributes, Environment, IoEnvironment, Status, Value, WaitableStatus};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+/// Declare variables and set their attributes.  Each name may be of
+/// the form `name=value` to also assign a value, or `name=(...)` to
+/// assign the elements of an array.  Without any names,
+/// or with -p, print variables in a syntax that can be read back
+/// by the shell.
+pub struct DeclareCommand {
+    /// Make each name an indexed array
+    #[structopt(short = "a")]
+    indexed: bool,
+    /// Make each name an associative array
+    #[structopt(short = "A", conflicts_with = "indexed")]
+    associative: bool,
+    /// Evaluate assignments to each name arithmetically
+    #[structopt(short = "i")]
+    integer: bool,
+    /// Convert values assigned to each name to lower case
+    #[structopt(short = "l")]
+    lowercase: bool,
+    /// Convert values assigned to each name to upper case
+    #[structopt(short = "u", conflicts_with = "lowercase")]
+    uppercase: bool,
+    /// Make each name readonly
+    #[structopt(short = "r")]
+    readonly: bool,
+    /// Export each name to the environment of spawned commands
+    #[structopt(short = "x")]
+    export: bool,
+    /// Print the named variables, or all variables that have the
+    /// specified attributes if no names are given
+    #[structopt(short = "p")]
+    print: bool,
+    names: Vec<String>,
+}
+
+/// The elements of the compound array assignments among the arguments,
+/// keyed by the name of the array
+type Arrays = HashMap<String, Vec<(Option<String>, OsString)>>;
+
+/// The compiler passes each compound array assignment argument, as in
+/// `declare -a name=(...)`, as a list holding the name and the elements.
+/// Returns argv with those replaced by just the name, along with
+/// the elements to assign to each of them.
+fn split_arrays(argv: &[Value]) -> anyhow::Result<(Vec<Value>, Arrays)> {
+    let mut args = vec![];
+    let mut arrays = Arrays::new();
+    for arg in argv {
+        match arg {
+            Value::List(pair) if pair.len() == 2 => {
+                let name = pair[0]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("array name is not a string"))?;
+                arrays.insert(name.to_owned(), pair[1].array_elements()?);
+                args.push(name.into());
+            }
+            arg => args.push(arg.clone()),
+        }
+    }
+    Ok((args, arrays))
+}
+
+impl Builtin for DeclareCommand {
+    fn eval(
+        argv: &[Value],
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let (argv, arrays) = split_arrays(argv)?;
+        Self::parse(&argv)?.run_as(Self::name(), &arrays, environment, io_env, cancel)
+    }
+
+    fn name() -> &'static str {
+        "declare"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        self.run_as(Self::name(), &Arrays::new(), environment, io_env, cancel)
+    }
+}
+
+impl DeclareCommand {
+    fn run_as(
+        &self,
+        command: &str,
+        arrays: &Arrays,
+        environment: &mut Environment,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mut status = 0;
+        if self.print || self.names.is_empty() {
+            let names: Vec<String> = if self.names.is_empty() {
+                environment
+                    .iter_attributes()
+                    .filter(|(_, attributes)| self.matches(attributes))
+                    .filter_map(|(name, _)| name.to_str().map(str::to_owned))
+                    .collect()
+            } else {
+                self.names.clone()
+            };
+            for name in &names {
+                cancel.check_cancel()?;
+                if !print_variable(&mut io_env.stdout(), environment, name)? {
+                    writeln!(io_env.stderr(), "{}: {}: not found", command, name)?;
+                    status = 1;
+                }
+            }
+        } else {
+            for name in &self.names {
+                if let Err(err) = self.declare(environment, name, arrays) {
+                    writeln!(io_env.stderr(), "{}: {}", command, err)?;
+                    status = 1;
+                }
+            }
+        }
+        Ok(Status::Complete(status.into()).into())
+    }
+
+    /// Returns true if attributes has all of the attributes
+    /// requested by the options
+    fn matches(&self, attributes: &Attributes) -> bool {
+        (!self.integer || attributes.integer)
+            && (!self.lowercase || attributes.lowercase)
+            && (!self.uppercase || attributes.uppercase)
+            && (!self.readonly || attributes.readonly)
+            && (!self.export || attributes.exported)
+    }
+
+    fn declare(
+        &self,
+        environment: &mut Environment,
+        name: &str,
+        arrays: &Arrays,
+    ) -> anyhow::Result<()> {
+        let split: Vec<&str> = name.splitn(2, '=').collect();
+        let name = split[0];
+        let value = split.get(1);
+        if let Some(value) = value {
+            if value.starts_with('(') {
+                anyhow::bail!("{}: a compound assignment must not be quoted", name);
+            }
+        }
+
+        if self.indexed || self.associative {
+            environment.declare_array(name, self.associative)?;
+        }
+
+        let mut attributes = environment.attributes(name);
+        attributes.exported |= self.export;
+        attributes.integer |= self.integer;
+        if self.lowercase {
+            attributes.lowercase = true;
+            attributes.uppercase = false;
+        }
+        if self.uppercase {
+            attributes.uppercase = true;
+            attributes.lowercase = false;
+        }
+        environment.set_attributes(name, attributes);
+
+        if let Some(value) = value {
+            environment.assign(name, *value)?;
+        } else if let Some(elements) = arrays.get(name) {
+            environment.assign_array(name, elements.clone())?;
+        }
+
+        // Made readonly after the assignment, so that
+        // `declare -r NAME=value` can assign the value
+        if self.readonly {
+            attributes.readonly = true;
+            environment.set_attributes(name, attributes);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+/// Declare variables and set their attributes; this is a synonym
+/// for `declare`
+pub struct TypesetCommand {
+    #[structopt(flatten)]
+    declare: DeclareCommand,
+}
+
+impl Builtin for TypesetCommand {
+    fn eval(
+        argv: &[Value],
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let (argv, arrays) = split_arrays(argv)?;
+        Self::parse(&argv)?
+            .declare
+            .run_as(Self::name(), &arrays, environment, io_env, cancel)
+    }
+
+    fn name() -> &'static str {
+        "typeset"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        self.declare
+            .run_as(Self::name(), &Arrays::new(), environment, io_env, cancel)
+    }
+}
+
+#[derive(StructOpt)]
+/// Make variables readonly, so that they cannot be assigned or unset.
+/// Each name may be of the form `name=value` to also assign a value.
+pub struct ReadonlyCommand {
+    /// Print the readonly variables in a syntax that can be read
+    /// back by the shell
+    #[structopt(short = "p", conflicts_with = "names")]
+    print: bool,
+    names: Vec<String>,
+}
+
+impl Builtin for ReadonlyCommand {
+    fn eval(
+        argv: &[Value],
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let (argv, arrays) = split_arrays(argv)?;
+        Self::parse(&argv)?.run_with(&arrays, environment, io_env, cancel)
+    }
+
+    fn name() -> &'static str {
+        "readonly"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        self.run_with(&Arrays::new(), environment, io_env, cancel)
+    }
+}
+
+impl ReadonlyCommand {
+    fn run_with(
+        &self,
+        arrays: &Arrays,
+        environment: &mut Environment,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let declare = DeclareCommand {
+            indexed: false,
+            associative: false,
+            integer: false,
+            lowercase: false,
+            uppercase: false,
+            readonly: true,
+            export: false,
+            print: self.print,
+            names: self.names.clone(),
+        };
+        declare.run_as(Self::name(), arrays, environment, io_env, cancel)
+    }
+}
+
+fn flags(attributes: &Attributes, array: Option<bool>) -> String {
+    let mut flags = String::new();
+    for (set, flag) in &[
+        (array == Some(false), 'a'),
+        (array == Some(true), 'A'),
+        (attributes.integer, 'i'),
+        (attributes.lowercase, 'l'),
+        (attributes.uppercase, 'u'),
+        (attributes.readonly, 'r'),
+        (attributes.exported, 'x'),
+    ] {
+        if *set {
+            flags.push(*flag);
+        }
+    }
+    if flags.is_empty() {
+        "--".to_owned()
+    } else {
+        format!("-{}", flags)
+    }
+}
+
+/// Quote s so that the shell reads it back as the same string
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' || c == '$' || c == '`' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Print the declaration of the named variable.
+/// Returns false if there is no such variable.
+fn print_variable(
+    out: &mut dyn Write,
+    environment: &Environment,
+    name: &str,
+) -> anyhow::Result<bool> {
+    let attributes = environment.attributes(name);
+    if let Some(array) = environment.get_array(name) {
+        // The elements are assigned separately from the declaration,
+        // and so the readonly attribute has to be applied last
+        let mut declared = attributes;
+        declared.readonly = false;
+        writeln!(
+            out,
+            "declare {} {}",
+            flags(&declared, Some(array.is_associative())),
+            name
+        )?;
+        let elements: Vec<String> = array
+            .keys()
+            .iter()
+            .zip(array.values())
+            .map(|(key, value)| format!("[{}]={}", key, quote(&value.to_string_lossy())))
+            .collect();
+        writeln!(out, "{}=({})", name, elements.join(" "))?;
+        if attributes.readonly {
+            writeln!(out, "declare -r {}", name)?;
+        }
+        return Ok(true);
+    }
+
+    match environment.get(name) {
+        Some(value) => writeln!(
+            out,
+            "declare {} {}={}",
+            flags(&attributes, None),
+            name,
+            quote(&value.to_string_lossy())
+        )?,
+        None if attributes != Attributes::default() => {
+            writeln!(out, "declare {} {}", flags(&attributes, None), name)?
+        }
+        None => return Ok(false),
+    }
+    Ok(true)
+}
@@ -3,19 +3,24 @@ use crate::shellhost::FunctionRegistry;
 use cancel::Token;
 use shell_vm::{Environment, IoEnvironment, Status, WaitableStatus};
 use std::collections::HashSet;
+use std::ffi::OsString;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 use structopt::*;
 
 #[derive(StructOpt)]
-/// Set the export attribute for variables (note that this is always
-/// set in the current version of wzsh)
+/// Set the export attribute for variables, so that they are passed
+/// to the commands that the shell spawns.  Each name may be of the
+/// form `name=value` to also assign a value.
 pub struct ExportCommand {
     names: Vec<String>,
     /// Print exported variables in a syntax compatible with the shell
     #[structopt(short = "p", conflicts_with = "names")]
     print: bool,
+    /// Remove the export attribute instead of setting it
+    #[structopt(short = "n")]
+    unexport: bool,
 }
 
 impl Builtin for ExportCommand {
@@ -31,8 +36,9 @@ impl Builtin for ExportCommand {
         cancel: Arc<Token>,
         _functions: &Arc<FunctionRegistry>,
     ) -> anyhow::Result<WaitableStatus> {
+        let mut status = 0;
         if self.print {
-            for (k, v) in environment.iter() {
+            for (k, v) in environment.iter_exported() {
                 cancel.check_cancel()?;
                 match (k.to_str(), v.to_str()) {
                     (Some(k), Some(v)) => writeln!(io_env.stdout(), "export {}={}", k, v)?,
@@ -49,11 +55,20 @@ impl Builtin for ExportCommand {
                 // parse `name=value` and assign
                 let split: Vec<&str> = name.splitn(2, '=').collect();
                 if split.len() == 2 {
-                    environment.set(split[0], split[1]);
+                    if let Err(err) = environment.assign(split[0], split[1]) {
+                        writeln!(io_env.stderr(), "export: {}", err)?;
+                        status = 1;
+                        continue;
+                    }
+                }
+                if self.unexport {
+                    environment.unexport(split[0]);
+                } else {
+                    environment.export(split[0]);
                 }
             }
         }
-        Ok(Status::Complete(0.into()).into())
+        Ok(Status::Complete(status.into()).into())
     }
 }
 
@@ -72,14 +87,18 @@ impl Builtin for UnsetCommand {
         &mut self,
         environment: &mut Environment,
         _current_directory: &mut PathBuf,
-        _io_env: &IoEnvironment,
+        io_env: &IoEnvironment,
         _cancel: Arc<Token>,
         _functions: &Arc<FunctionRegistry>,
     ) -> anyhow::Result<WaitableStatus> {
+        let mut status = 0;
         for name in &self.names {
-            environment.unset(name);
+            if let Err(err) = environment.unset(name) {
+                writeln!(io_env.stderr(), "unset: {}", err)?;
+                status = 1;
+            }
         }
-        Ok(Status::Complete(0.into()).into())
+        Ok(Status::Complete(status.into()).into())
     }
 }
 
@@ -104,7 +123,8 @@ enum PathOp {
 }
 
 impl PathSpec {
-    fn apply(&self, env: &mut Environment, op: PathOp) -> anyhow::Result<()> {
+    /// Returns the value of PATH after applying op to it
+    fn apply(&self, env: &Environment, op: PathOp) -> anyhow::Result<OsString> {
         let mut set = HashSet::new();
         let mut pathvec = Vec::new();
 
@@ -150,9 +170,7 @@ impl PathSpec {
             }
         }
 
-        let new_path = std::env::join_paths(pathvec.into_iter())?;
-        env.set("PATH", new_path);
-        Ok(())
+        Ok(std::env::join_paths(pathvec.into_iter())?)
     }
 }
 
@@ -188,12 +206,13 @@ impl Builtin for PathCommand {
         _cancel: Arc<Token>,
         _functions: &Arc<FunctionRegistry>,
     ) -> anyhow::Result<WaitableStatus> {
-        match self {
+        let new_path = match self {
             PathCommand::Show => {
                 if let Some(path) = env.get("PATH") {
                     for entry in std::env::split_paths(path) {
                         writeln!(io_env.stdout(), "{}", entry.display())?;
                     }
+                    return Ok(Status::Complete(0.into()).into());
                 } else {
                     writeln!(io_env.stderr(), "PATH environment is not set!")?;
                     return Ok(Status::Complete(1.into()).into());
@@ -217,10 +236,55 @@ impl Builtin for PathCommand {
                         }
                     }
                 }
-                let new_path = std::env::join_paths(pathvec.into_iter())?;
-                env.set("PATH", new_path);
+                std::env::join_paths(pathvec.into_iter())?
             }
+        };
+        if let Err(err) = env.assign("PATH", new_path) {
+            writeln!(io_env.stderr(), "path: {}", err)?;
+            return Ok(Status::Complete(1.into()).into());
         }
         Ok(Status::Complete(0.into()).into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::run_builtin;
+    use shell_vm::Attributes;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn path_respects_readonly() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+        env.set("PATH", "/bin");
+
+        run_builtin::<PathCommand>(&["path", "add", "/usr/bin"], &mut env, &mut cwd)?;
+        assert_eq!(env.get("PATH"), Some(OsStr::new("/bin:/usr/bin")));
+
+        env.set_attributes(
+            "PATH",
+            Attributes {
+                readonly: true,
+                ..env.attributes("PATH")
+            },
+        );
+        for argv in &[
+            &["path", "prepend", "/sbin"][..],
+            &["path", "remove", "/bin"],
+            &["path", "fixup"],
+        ] {
+            assert_eq!(
+                run_builtin::<PathCommand>(argv, &mut env, &mut cwd)?,
+                (
+                    1,
+                    String::new(),
+                    "path: PATH: readonly variable\n".to_owned()
+                )
+            );
+        }
+        assert_eq!(env.get("PATH"), Some(OsStr::new("/bin:/usr/bin")));
+        Ok(())
+    }
+}
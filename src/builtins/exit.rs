@@ -0,0 +1,189 @@
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use shell_vm::{Environment, ExitRequest, IoEnvironment, Status, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+#[structopt(raw(setting = "structopt::clap::AppSettings::AllowNegativeNumbers"))]
+/// Exit the shell with status n, unwinding any functions that are
+/// running.  If n is omitted, the status is that of the last command
+/// that was run.  If n is not a number, the status is 2.  The EXIT
+/// trap, if any, runs before the shell exits.
+pub struct ExitCommand {
+    n: Option<String>,
+}
+
+impl Builtin for ExitCommand {
+    fn name() -> &'static str {
+        "exit"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let (request, status) = match self.n.as_deref().map(str::parse::<isize>) {
+            Some(Ok(n)) => (ExitRequest::Status(n), n),
+            Some(Err(_)) => {
+                writeln!(
+                    io_env.stderr(),
+                    "exit: {}: numeric argument required",
+                    self.n.as_deref().unwrap_or_default()
+                )?;
+                (ExitRequest::Status(2), 2)
+            }
+            None => (ExitRequest::LastStatus, 0),
+        };
+        environment.request_exit(request);
+        Ok(Status::Complete(status.into()).into())
+    }
+}
+
+#[derive(StructOpt)]
+/// Run action when one of the specified conditions occurs.  An action
+/// of `-` resets the conditions to their default, and an empty action
+/// ignores them.  With no arguments, or with -p, print the actions
+/// as `trap` commands.  Only the EXIT condition, which may also be
+/// written as `0`, is currently supported.
+pub struct TrapCommand {
+    /// Print the trap actions
+    #[structopt(short = "p")]
+    print: bool,
+    action: Option<String>,
+    conditions: Vec<String>,
+}
+
+fn is_exit_condition(condition: &str) -> bool {
+    condition == "EXIT" || condition == "0"
+}
+
+impl Builtin for TrapCommand {
+    fn name() -> &'static str {
+        "trap"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let action = match self.action.take() {
+            Some(action) if !self.print => action,
+            _ => {
+                if let Some(action) = environment.exit_trap() {
+                    writeln!(
+                        io_env.stdout(),
+                        "trap -- '{}' EXIT",
+                        action.replace('\'', "'\\''")
+                    )?;
+                }
+                return Ok(Status::Complete(0.into()).into());
+            }
+        };
+
+        // A lone condition resets that condition
+        let (action, conditions) = if self.conditions.is_empty() {
+            (None, vec![action])
+        } else if action == "-" {
+            (None, self.conditions.clone())
+        } else {
+            (Some(action), self.conditions.clone())
+        };
+
+        for condition in &conditions {
+            if !is_exit_condition(condition) {
+                writeln!(
+                    io_env.stderr(),
+                    "trap: {}: only the EXIT condition is supported",
+                    condition
+                )?;
+                return Ok(Status::Complete(1.into()).into());
+            }
+        }
+        environment.set_exit_trap(action);
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::run_builtin;
+
+    #[test]
+    fn exit_status() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+
+        assert_eq!(
+            run_builtin::<ExitCommand>(&["exit", "3"], &mut env, &mut cwd)?,
+            (3, String::new(), String::new())
+        );
+        assert_eq!(env.take_exit_request(), Some(ExitRequest::Status(3)));
+
+        run_builtin::<ExitCommand>(&["exit"], &mut env, &mut cwd)?;
+        assert_eq!(env.take_exit_request(), Some(ExitRequest::LastStatus));
+
+        assert_eq!(
+            run_builtin::<ExitCommand>(&["exit", "abc"], &mut env, &mut cwd)?,
+            (
+                2,
+                String::new(),
+                "exit: abc: numeric argument required\n".to_owned()
+            )
+        );
+        assert_eq!(env.take_exit_request(), Some(ExitRequest::Status(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn trap() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+        let mut trap = |argv: &[&str]| run_builtin::<TrapCommand>(argv, &mut env, &mut cwd);
+
+        assert_eq!(trap(&["trap", "-p"])?, (0, String::new(), String::new()));
+        assert_eq!(
+            trap(&["trap", "echo 'bye'", "EXIT"])?,
+            (0, String::new(), String::new())
+        );
+        let printed = (
+            0,
+            "trap -- 'echo '\\''bye'\\''' EXIT\n".to_owned(),
+            String::new(),
+        );
+        assert_eq!(trap(&["trap", "-p"])?, printed);
+        assert_eq!(trap(&["trap"])?, printed);
+
+        assert_eq!(
+            trap(&["trap", "-", "EXIT"])?,
+            (0, String::new(), String::new())
+        );
+        assert_eq!(trap(&["trap", "-p"])?, (0, String::new(), String::new()));
+
+        trap(&["trap", "true", "0"])?;
+        trap(&["trap", "EXIT"])?;
+        assert_eq!(trap(&["trap", "-p"])?, (0, String::new(), String::new()));
+
+        assert_eq!(
+            trap(&["trap", "true", "INT"])?,
+            (
+                1,
+                String::new(),
+                "trap: INT: only the EXIT condition is supported\n".to_owned()
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use anyhow::bail;
+use cancel::Token;
+use shell_vm::{Environment, IoEnvironment, OptionCursor, Status, WaitableStatus};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+// The args are options destined for the script, and so must not be
+// interpreted as options to getopts itself
+#[structopt(raw(
+    setting = "structopt::clap::AppSettings::TrailingVarArg",
+    setting = "structopt::clap::AppSettings::AllowLeadingHyphen",
+    setting = "structopt::clap::AppSettings::DisableHelpFlags"
+))]
+/// Parse the options from the positional parameters, or from args if
+/// any are specified.  Each invocation stores the next option
+/// character into the variable name and its argument, if any, into
+/// OPTARG, and advances OPTIND to the index of the next argument
+/// to process.  The status is non-zero once the options have been
+/// exhausted.
+///
+/// Unrecognized options and missing option arguments set name to `?`
+/// and print a diagnostic.  If optstring begins with `:` the diagnostic
+/// is suppressed and OPTARG is set to the option character instead;
+/// in that mode a missing option argument sets name to `:`.
+pub struct GetoptsCommand {
+    /// The option characters to recognize; a character followed
+    /// by `:` requires an argument
+    optstring: String,
+    /// The variable in which to store each option character
+    name: String,
+    /// Parse these instead of the positional parameters
+    #[structopt(parse(from_os_str), raw(allow_hyphen_values = "true"))]
+    args: Vec<OsString>,
+}
+
+impl Builtin for GetoptsCommand {
+    fn name() -> &'static str {
+        "getopts"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let args: Vec<String> = if self.args.is_empty() {
+            environment
+                .positional()
+                .iter()
+                .skip(1)
+                .map(|arg| {
+                    arg.as_os_str()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                })
+                .collect()
+        } else {
+            self.args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let optind = environment
+            .get_str("OPTIND")?
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+        let arg = match args.get(optind - 1) {
+            Some(arg) if arg == "--" => return self.finish(environment, optind + 1),
+            Some(arg) if arg.len() > 1 && arg.starts_with('-') => arg,
+            _ => return self.finish(environment, optind),
+        };
+
+        let mut cursor = environment.option_cursor();
+        if cursor.optind != optind || cursor.offset == 0 || cursor.arg != *arg {
+            cursor = OptionCursor {
+                optind,
+                offset: 1,
+                arg: arg.clone(),
+            };
+        }
+
+        let c = match arg.get(cursor.offset..).and_then(|s| s.chars().next()) {
+            Some(c) => c,
+            None => bail!(
+                "getopts: option position {} is not within {:?}",
+                cursor.offset,
+                arg
+            ),
+        };
+        let rest = &arg[cursor.offset + c.len_utf8()..];
+        let silent = self.optstring.starts_with(':');
+        let spec = if c == ':' {
+            None
+        } else {
+            self.optstring
+                .find(c)
+                .map(|idx| self.optstring[idx + c.len_utf8()..].starts_with(':'))
+        };
+
+        // Advance past this option character, moving to the next
+        // argument when this one has been consumed
+        let mut next_optind = optind;
+        if rest.is_empty() {
+            next_optind += 1;
+            cursor.offset = 1;
+        } else {
+            cursor.offset += c.len_utf8();
+        }
+
+        let (value, optarg) = match spec {
+            None => {
+                if !silent {
+                    writeln!(io_env.stderr(), "getopts: illegal option -- {}", c)?;
+                }
+                ('?', if silent { Some(c.to_string()) } else { None })
+            }
+            Some(false) => (c, None),
+            Some(true) if !rest.is_empty() => {
+                next_optind = optind + 1;
+                cursor.offset = 1;
+                (c, Some(rest.to_owned()))
+            }
+            Some(true) => match args.get(next_optind - 1) {
+                Some(optarg) => {
+                    next_optind += 1;
+                    (c, Some(optarg.clone()))
+                }
+                None if silent => (':', Some(c.to_string())),
+                None => {
+                    writeln!(
+                        io_env.stderr(),
+                        "getopts: option requires an argument -- {}",
+                        c
+                    )?;
+                    ('?', None)
+                }
+            },
+        };
+
+        environment.assign(&self.name, value.to_string())?;
+        match optarg {
+            Some(optarg) => environment.assign("OPTARG", optarg)?,
+            None => environment.unset("OPTARG")?,
+        }
+        environment.assign("OPTIND", next_optind.to_string())?;
+        cursor.optind = next_optind;
+        environment.set_option_cursor(cursor);
+
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+impl GetoptsCommand {
+    /// Called when the options have been exhausted; optind is the
+    /// index of the first operand
+    fn finish(
+        &self,
+        environment: &mut Environment,
+        optind: usize,
+    ) -> anyhow::Result<WaitableStatus> {
+        environment.assign(&self.name, "?")?;
+        environment.unset("OPTARG")?;
+        environment.assign("OPTIND", optind.to_string())?;
+        environment.set_option_cursor(OptionCursor::default());
+        Ok(Status::Complete(1.into()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::run_builtin;
+
+    /// Run getopts repeatedly until it reports the end of the options,
+    /// returning each `name` and OPTARG, and finally OPTIND
+    fn parse_all(env: &mut Environment, argv: &[&str]) -> anyhow::Result<(Vec<String>, String)> {
+        let mut cwd = std::env::current_dir()?;
+        let mut seen = vec![];
+        for _ in 0..20 {
+            let (status, _, _) = run_builtin::<GetoptsCommand>(argv, env, &mut cwd)?;
+            if status != 0 {
+                let optind = env.get_str("OPTIND")?.unwrap_or("").to_owned();
+                return Ok((seen, optind));
+            }
+            let name = env.get_str("opt")?.unwrap_or("").to_owned();
+            match env.get_str("OPTARG")? {
+                Some(optarg) => seen.push(format!("{}={}", name, optarg)),
+                None => seen.push(name),
+            }
+        }
+        anyhow::bail!("getopts did not finish");
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn clustered() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let (seen, optind) = parse_all(&mut env, &["getopts", "abc", "opt", "-abc", "-b", "file"])?;
+        assert_eq!(seen, strings(&["a", "b", "c", "b"]));
+        assert_eq!(optind, "3");
+        Ok(())
+    }
+
+    #[test]
+    fn option_arguments() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let (seen, optind) = parse_all(
+            &mut env,
+            &[
+                "getopts", "o:v", "opt", "-ofile", "-v", "-o", "other", "rest",
+            ],
+        )?;
+        assert_eq!(seen, strings(&["o=file", "v", "o=other"]));
+        assert_eq!(optind, "5");
+        Ok(())
+    }
+
+    #[test]
+    fn double_dash() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let (seen, optind) = parse_all(&mut env, &["getopts", "a", "opt", "-a", "--", "-a"])?;
+        assert_eq!(seen, strings(&["a"]));
+        assert_eq!(optind, "3");
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+        let (status, _, err) =
+            run_builtin::<GetoptsCommand>(&["getopts", "a:", "opt", "-x"], &mut env, &mut cwd)?;
+        assert_eq!(status, 0);
+        assert_eq!(env.get_str("opt")?, Some("?"));
+        assert_eq!(env.get_str("OPTARG")?, None);
+        assert_eq!(err, "getopts: illegal option -- x\n");
+
+        env.assign("OPTIND", "1")?;
+        let (status, _, err) =
+            run_builtin::<GetoptsCommand>(&["getopts", "a:", "opt", "-a"], &mut env, &mut cwd)?;
+        assert_eq!(status, 0);
+        assert_eq!(env.get_str("opt")?, Some("?"));
+        assert_eq!(err, "getopts: option requires an argument -- a\n");
+        Ok(())
+    }
+
+    #[test]
+    fn silent() -> anyhow::Result<()> {
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+        let (_, _, err) =
+            run_builtin::<GetoptsCommand>(&["getopts", ":a:", "opt", "-x"], &mut env, &mut cwd)?;
+        assert_eq!(env.get_str("opt")?, Some("?"));
+        assert_eq!(env.get_str("OPTARG")?, Some("x"));
+        assert_eq!(err, "");
+
+        env.assign("OPTIND", "1")?;
+        let (_, _, err) =
+            run_builtin::<GetoptsCommand>(&["getopts", ":a:", "opt", "-a"], &mut env, &mut cwd)?;
+        assert_eq!(env.get_str("opt")?, Some(":"));
+        assert_eq!(env.get_str("OPTARG")?, Some("a"));
+        assert_eq!(err, "");
+        Ok(())
+    }
+
+    #[test]
+    fn changed_arguments() -> anyhow::Result<()> {
+        // The cursor left within `-abc` must not be applied to `-x`
+        let mut env = Environment::new_empty();
+        let mut cwd = std::env::current_dir()?;
+        run_builtin::<GetoptsCommand>(&["getopts", "abc", "opt", "-abc"], &mut env, &mut cwd)?;
+        let (status, _, _) =
+            run_builtin::<GetoptsCommand>(&["getopts", "x", "opt", "-x"], &mut env, &mut cwd)?;
+        assert_eq!(status, 0);
+        assert_eq!(env.get_str("opt")?, Some("x"));
+
+        // Assigning OPTIND restarts the parse
+        run_builtin::<GetoptsCommand>(&["getopts", "abc", "opt", "-abc"], &mut env, &mut cwd)?;
+        env.assign("OPTIND", "1")?;
+        run_builtin::<GetoptsCommand>(&["getopts", "abc", "opt", "-abc"], &mut env, &mut cwd)?;
+        assert_eq!(env.get_str("opt")?, Some("a"));
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use pathsearch::PathSearcher;
+use shell_vm::{Environment, IoEnvironment, Status, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+/// Manage the table of remembered command locations.  The shell
+/// remembers where it found each command that it runs from PATH
+/// and forgets them all when PATH is changed.  Each name is searched
+/// for in PATH and remembered.  Without any names or options, print
+/// the table.
+pub struct HashCommand {
+    /// Forget all remembered locations
+    #[structopt(short = "r")]
+    reset: bool,
+    /// Forget the remembered location of each name
+    #[structopt(short = "d", conflicts_with = "reset")]
+    delete: bool,
+    /// Remember path as the location of the single name, without
+    /// searching PATH
+    #[structopt(short = "p", parse(from_os_str), conflicts_with = "delete")]
+    path: Option<PathBuf>,
+    names: Vec<String>,
+}
+
+impl Builtin for HashCommand {
+    fn name() -> &'static str {
+        "hash"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mut status = 0;
+
+        if self.reset {
+            environment.command_hash().clear();
+        }
+
+        if let Some(path) = &self.path {
+            if self.names.len() != 1 {
+                writeln!(io_env.stderr(), "hash: -p requires exactly one name")?;
+                return Ok(Status::Complete(2.into()).into());
+            }
+            environment
+                .command_hash()
+                .insert(&self.names[0], path.clone());
+        } else if self.delete {
+            for name in &self.names {
+                if !environment.command_hash().remove(name) {
+                    writeln!(io_env.stderr(), "hash: {}: not found", name)?;
+                    status = 1;
+                }
+            }
+        } else if !self.names.is_empty() {
+            for name in &self.names {
+                cancel.check_cancel()?;
+                match PathSearcher::new(name, environment.get("PATH"), environment.get("PATHEXT"))
+                    .next()
+                {
+                    Some(path) => environment.command_hash().insert(name, path),
+                    None => {
+                        writeln!(io_env.stderr(), "hash: {}: not found", name)?;
+                        status = 1;
+                    }
+                }
+            }
+        } else if !self.reset {
+            let hash = environment.command_hash();
+            if hash.is_empty() {
+                writeln!(io_env.stderr(), "hash: hash table empty")?;
+            } else {
+                let mut stdout = io_env.stdout();
+                writeln!(stdout, "hits\tcommand")?;
+                for (_name, command) in hash.iter() {
+                    writeln!(stdout, "{:4}\t{}", command.hits, command.path.display())?;
+                }
+            }
+        }
+
+        Ok(Status::Complete(status.into()).into())
+    }
+}
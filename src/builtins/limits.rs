@@ -0,0 +1,471 @@
+//! Builtins that manipulate process state that is inherited by
+//! the commands that the shell spawns
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use anyhow::{anyhow, bail};
+use cancel::Token;
+use shell_vm::{Environment, IoEnvironment, Status, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+use tabout::{tabulate_output, Alignment, Column};
+
+#[derive(StructOpt)]
+/// Set the file mode creation mask of the shell, which is inherited
+/// by the commands that it spawns, or print it if no mode is
+/// specified.  The mode is either an octal number, or a symbolic mode
+/// such as `u=rwx,g=rx,o=` that specifies the permissions that are to
+/// be allowed, as for chmod.
+pub struct UmaskCommand {
+    /// Print the mask in symbolic form
+    #[structopt(short = "S")]
+    symbolic: bool,
+    mode: Option<String>,
+}
+
+/// Returns the current umask; there is no way to query it without
+/// also setting it, so it is set back to the value that was read
+fn current_umask() -> libc::mode_t {
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask
+    }
+}
+
+/// Applies a symbolic mode to the permission bits in allowed,
+/// returning the modified bits
+fn apply_symbolic_mode(mode: &str, mut allowed: u32) -> anyhow::Result<u32> {
+    for clause in mode.split(',') {
+        let op_idx = clause
+            .find(&['=', '+', '-'][..])
+            .ok_or_else(|| anyhow!("{}: invalid symbolic mode", mode))?;
+        let (who, rest) = clause.split_at(op_idx);
+
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => bail!("{}: invalid symbolic mode", mode),
+            };
+        }
+        if who_bits == 0 {
+            who_bits = 0o777;
+        }
+
+        let op = rest.chars().next().unwrap();
+        let mut perm_bits = 0;
+        for c in rest[1..].chars() {
+            perm_bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => bail!("{}: invalid symbolic mode", mode),
+            };
+        }
+        perm_bits &= who_bits;
+
+        match op {
+            '=' => allowed = (allowed & !who_bits) | perm_bits,
+            '+' => allowed |= perm_bits,
+            _ => allowed &= !perm_bits,
+        }
+    }
+    Ok(allowed)
+}
+
+/// Formats the permissions allowed by mask, eg: `u=rwx,g=rx,o=rx`
+fn symbolic_mask(mask: u32) -> String {
+    let allowed = !mask & 0o777;
+    let mut clauses = vec![];
+    for (who, shift) in &[("u", 6), ("g", 3), ("o", 0)] {
+        let bits = (allowed >> shift) & 0o7;
+        let mut clause = format!("{}=", who);
+        for (bit, c) in &[(0o4, 'r'), (0o2, 'w'), (0o1, 'x')] {
+            if bits & bit != 0 {
+                clause.push(*c);
+            }
+        }
+        clauses.push(clause);
+    }
+    clauses.join(",")
+}
+
+impl Builtin for UmaskCommand {
+    fn name() -> &'static str {
+        "umask"
+    }
+
+    fn run(
+        &mut self,
+        _environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mask = current_umask() as u32;
+        let mode = match &self.mode {
+            Some(mode) => mode,
+            None => {
+                if self.symbolic {
+                    writeln!(io_env.stdout(), "{}", symbolic_mask(mask))?;
+                } else {
+                    writeln!(io_env.stdout(), "{:04o}", mask)?;
+                }
+                return Ok(Status::Complete(0.into()).into());
+            }
+        };
+
+        let new_mask = if mode.chars().all(|c| c.is_digit(8)) {
+            u32::from_str_radix(mode, 8)
+                .ok()
+                .filter(|mask| *mask <= 0o777)
+                .ok_or_else(|| anyhow!("{}: octal number out of range", mode))
+        } else {
+            apply_symbolic_mode(mode, !mask & 0o777).map(|allowed| !allowed & 0o777)
+        };
+
+        match new_mask {
+            Ok(new_mask) => {
+                unsafe {
+                    libc::umask(new_mask as libc::mode_t);
+                }
+                Ok(Status::Complete(0.into()).into())
+            }
+            Err(err) => {
+                writeln!(io_env.stderr(), "umask: {}", err)?;
+                Ok(Status::Complete(1.into()).into())
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Describes a resource limit that ulimit can manipulate
+struct Limit {
+    flag: char,
+    description: &'static str,
+    resource: Resource,
+    /// Values are reported and specified in multiples of this
+    /// many of the underlying units
+    scale: libc::rlim_t,
+    unit: &'static str,
+}
+
+const LIMITS: &[Limit] = &[
+    Limit {
+        flag: 'c',
+        description: "core file size",
+        resource: libc::RLIMIT_CORE,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 'd',
+        description: "data seg size",
+        resource: libc::RLIMIT_DATA,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 'f',
+        description: "file size",
+        resource: libc::RLIMIT_FSIZE,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 'l',
+        description: "max locked memory",
+        resource: libc::RLIMIT_MEMLOCK,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 'm',
+        description: "max memory size",
+        resource: libc::RLIMIT_RSS,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 'n',
+        description: "open files",
+        resource: libc::RLIMIT_NOFILE,
+        scale: 1,
+        unit: "",
+    },
+    Limit {
+        flag: 's',
+        description: "stack size",
+        resource: libc::RLIMIT_STACK,
+        scale: 1024,
+        unit: "kbytes",
+    },
+    Limit {
+        flag: 't',
+        description: "cpu time",
+        resource: libc::RLIMIT_CPU,
+        scale: 1,
+        unit: "seconds",
+    },
+    Limit {
+        flag: 'u',
+        description: "max user processes",
+        resource: libc::RLIMIT_NPROC,
+        scale: 1,
+        unit: "",
+    },
+    Limit {
+        flag: 'v',
+        description: "virtual memory",
+        resource: libc::RLIMIT_AS,
+        scale: 1024,
+        unit: "kbytes",
+    },
+];
+
+impl Limit {
+    fn get(&self) -> anyhow::Result<libc::rlimit> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(self.resource, &mut limit) } != 0 {
+            bail!(
+                "{}: getrlimit failed: {}",
+                self.description,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(limit)
+    }
+
+    fn set(&self, limit: &libc::rlimit) -> anyhow::Result<()> {
+        if unsafe { libc::setrlimit(self.resource, limit) } != 0 {
+            bail!(
+                "{}: cannot modify limit: {}",
+                self.description,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    fn format(&self, value: libc::rlim_t) -> String {
+        if value == libc::RLIM_INFINITY {
+            "unlimited".to_owned()
+        } else {
+            (value / self.scale).to_string()
+        }
+    }
+
+    fn parse(&self, value: &str) -> anyhow::Result<libc::rlim_t> {
+        if value == "unlimited" {
+            return Ok(libc::RLIM_INFINITY);
+        }
+        let value: libc::rlim_t = value
+            .parse()
+            .map_err(|_| anyhow!("{}: invalid number", value))?;
+        value
+            .checked_mul(self.scale)
+            .ok_or_else(|| anyhow!("{}: limit out of range", value))
+    }
+}
+
+#[derive(StructOpt)]
+/// Set or print the resource limits of the shell, which are inherited
+/// by the commands that it spawns.  Without any resource options, the
+/// file size limit is used.  Setting a limit changes both the soft
+/// and hard limits unless -S or -H is used.  The value may be a number
+/// or `unlimited`.
+pub struct UlimitCommand {
+    /// Print all of the limits
+    #[structopt(short = "a")]
+    all: bool,
+    /// Use the hard limit
+    #[structopt(short = "H")]
+    hard: bool,
+    /// Use the soft limit
+    #[structopt(short = "S")]
+    soft: bool,
+    /// The maximum size of core files, in kbytes
+    #[structopt(short = "c")]
+    core: bool,
+    /// The maximum size of the data segment, in kbytes
+    #[structopt(short = "d")]
+    data: bool,
+    /// The maximum size of files created, in kbytes
+    #[structopt(short = "f")]
+    file_size: bool,
+    /// The maximum amount of memory that may be locked, in kbytes
+    #[structopt(short = "l")]
+    locked_memory: bool,
+    /// The maximum resident set size, in kbytes
+    #[structopt(short = "m")]
+    memory: bool,
+    /// The maximum number of open file descriptors
+    #[structopt(short = "n")]
+    open_files: bool,
+    /// The maximum stack size, in kbytes
+    #[structopt(short = "s")]
+    stack: bool,
+    /// The maximum amount of cpu time, in seconds
+    #[structopt(short = "t")]
+    cpu_time: bool,
+    /// The maximum number of processes for the user
+    #[structopt(short = "u")]
+    processes: bool,
+    /// The maximum amount of virtual memory, in kbytes
+    #[structopt(short = "v")]
+    virtual_memory: bool,
+    value: Option<String>,
+}
+
+impl UlimitCommand {
+    fn selected_limits(&self) -> Vec<&'static Limit> {
+        if self.all {
+            return LIMITS.iter().collect();
+        }
+        let flags = [
+            (self.core, 'c'),
+            (self.data, 'd'),
+            (self.file_size, 'f'),
+            (self.locked_memory, 'l'),
+            (self.memory, 'm'),
+            (self.open_files, 'n'),
+            (self.stack, 's'),
+            (self.cpu_time, 't'),
+            (self.processes, 'u'),
+            (self.virtual_memory, 'v'),
+        ];
+        let selected: Vec<_> = LIMITS
+            .iter()
+            .filter(|limit| flags.contains(&(true, limit.flag)))
+            .collect();
+        if selected.is_empty() {
+            LIMITS.iter().filter(|limit| limit.flag == 'f').collect()
+        } else {
+            selected
+        }
+    }
+
+    fn print(&self, limits: &[&Limit], io_env: &IoEnvironment) -> anyhow::Result<()> {
+        if let [limit] = limits {
+            let current = limit.get()?;
+            let value = if self.hard {
+                current.rlim_max
+            } else {
+                current.rlim_cur
+            };
+            writeln!(io_env.stdout(), "{}", limit.format(value))?;
+            return Ok(());
+        }
+
+        let columns = [
+            Column {
+                name: "RESOURCE".to_string(),
+                alignment: Alignment::Left,
+            },
+            Column {
+                name: "OPTION".to_string(),
+                alignment: Alignment::Left,
+            },
+            Column {
+                name: "SOFT".to_string(),
+                alignment: Alignment::Right,
+            },
+            Column {
+                name: "HARD".to_string(),
+                alignment: Alignment::Right,
+            },
+            Column {
+                name: "UNIT".to_string(),
+                alignment: Alignment::Left,
+            },
+        ];
+        let mut rows = vec![];
+        for limit in limits {
+            let current = limit.get()?;
+            rows.push(vec![
+                limit.description.to_string(),
+                format!("-{}", limit.flag),
+                limit.format(current.rlim_cur),
+                limit.format(current.rlim_max),
+                limit.unit.to_string(),
+            ]);
+        }
+        tabulate_output(&columns, &rows, &mut io_env.stdout())?;
+        Ok(())
+    }
+}
+
+impl Builtin for UlimitCommand {
+    fn name() -> &'static str {
+        "ulimit"
+    }
+
+    fn run(
+        &mut self,
+        _environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let limits = self.selected_limits();
+        let value = match &self.value {
+            Some(value) => value,
+            None => {
+                self.print(&limits, io_env)?;
+                return Ok(Status::Complete(0.into()).into());
+            }
+        };
+
+        let result = match limits.as_slice() {
+            [limit] => limit.parse(value).and_then(|value| {
+                let mut current = limit.get()?;
+                // Neither -H nor -S means that both are set
+                if self.hard || !self.soft {
+                    current.rlim_max = value;
+                }
+                if self.soft || !self.hard {
+                    current.rlim_cur = value;
+                }
+                limit.set(&current)
+            }),
+            _ => Err(anyhow!("only one limit may be set at a time")),
+        };
+        if let Err(err) = result {
+            writeln!(io_env.stderr(), "ulimit: {}", err)?;
+            return Ok(Status::Complete(1.into()).into());
+        }
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symbolic_modes() {
+        assert_eq!(symbolic_mask(0o022), "u=rwx,g=rx,o=rx");
+        assert_eq!(symbolic_mask(0o777), "u=,g=,o=");
+        assert_eq!(apply_symbolic_mode("u=rwx,g=rx,o=", 0).unwrap(), 0o750);
+        assert_eq!(apply_symbolic_mode("go-w", 0o777).unwrap(), 0o755);
+        assert_eq!(apply_symbolic_mode("a+x", 0o644).unwrap(), 0o755);
+        assert_eq!(apply_symbolic_mode("=r", 0o777).unwrap(), 0o444);
+        assert!(apply_symbolic_mode("u=q", 0).is_err());
+        assert!(apply_symbolic_mode("rw", 0).is_err());
+    }
+}
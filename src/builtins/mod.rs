@@ -9,11 +9,21 @@ use std::sync::Arc;
 use structopt::*;
 
 mod builtins;
+mod caller;
 mod colon;
+mod debug;
+mod declare;
 mod echo;
 mod env;
+mod exit;
+mod getopts;
+mod hash;
 pub mod history;
 mod jobcontrol;
+#[cfg(unix)]
+mod limits;
+mod positional;
+mod resolve;
 mod truefalse;
 mod which;
 mod workingdir;
@@ -29,6 +39,15 @@ pub trait Builtin: StructOpt {
         cancel: Arc<Token>,
         functions: &Arc<FunctionRegistry>,
     ) -> anyhow::Result<WaitableStatus>
+    where
+        Self: Sized,
+    {
+        let mut args = Self::parse(argv)?;
+        args.run(environment, current_directory, io_env, cancel, functions)
+    }
+
+    /// Parse argv, including the command name in argv[0], into Self
+    fn parse(argv: &[Value]) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
@@ -43,8 +62,7 @@ pub trait Builtin: StructOpt {
                     .ok_or_else(|| anyhow!("argument is not representable as osstr"))?,
             );
         }
-        let mut args = Self::from_clap(&app.get_matches_from_safe(os_args.iter())?);
-        args.run(environment, current_directory, io_env, cancel, functions)
+        Ok(Self::from_clap(&app.get_matches_from_safe(os_args.iter())?))
     }
 
     fn name() -> &'static str;
@@ -95,21 +113,91 @@ lazy_static! {
 
         builtins!(
             builtins::BuiltinsCommand,
+            caller::CallerCommand,
             colon::ColonCommand,
+            debug::DebugCommand,
             echo::EchoCommand,
+            declare::DeclareCommand,
+            declare::ReadonlyCommand,
+            declare::TypesetCommand,
             env::ExportCommand,
             env::UnsetCommand,
             env::PathCommand,
+            exit::ExitCommand,
+            exit::TrapCommand,
+            getopts::GetoptsCommand,
+            hash::HashCommand,
             history::HistoryCommand,
             jobcontrol::FgCommand,
             jobcontrol::JobsCommand,
+            positional::SetCommand,
+            positional::ShiftCommand,
+            resolve::CommandCommand,
+            resolve::TypeCommand,
             truefalse::FalseCommand,
             truefalse::TrueCommand,
             which::WhichCommand,
             workingdir::CdCommand,
+            workingdir::DirsCommand,
+            workingdir::PopdCommand,
+            workingdir::PushdCommand,
             workingdir::PwdCommand,
         );
+        #[cfg(unix)]
+        builtins!(limits::UlimitCommand, limits::UmaskCommand);
 
         builtins
     };
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use filedescriptor::Pipe;
+    use shell_vm::Status;
+    use std::io::Read;
+
+    /// Returns an empty directory for the test named name to use
+    pub fn scratch_dir(name: &str) -> anyhow::Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("wzsh-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Run the builtin B with argv, which includes its name, returning
+    /// its status and what it wrote to stdout and stderr
+    pub fn run_builtin<B: Builtin>(
+        argv: &[&str],
+        environment: &mut Environment,
+        current_directory: &mut PathBuf,
+    ) -> anyhow::Result<(isize, String, String)> {
+        let mut stdout = Pipe::new()?;
+        let mut stderr = Pipe::new()?;
+        let mut io_env = IoEnvironment::new()?;
+        io_env.assign_fd(1, stdout.write);
+        io_env.assign_fd(2, stderr.write);
+        let argv: Vec<Value> = argv.iter().map(Value::from).collect();
+        let status = B::eval(
+            &argv,
+            environment,
+            current_directory,
+            &io_env,
+            Arc::new(Token::new()),
+            &Arc::new(FunctionRegistry::new()),
+        )?;
+        drop(io_env);
+
+        let status = match status.poll() {
+            Some(Status::Complete(Value::Integer(n))) => n,
+            status => anyhow::bail!("unexpected status {:?}", status),
+        };
+        let mut out = String::new();
+        stdout.read.read_to_string(&mut out)?;
+        let mut err = String::new();
+        stderr.read.read_to_string(&mut err)?;
+        Ok((status, out, err))
+    }
+}
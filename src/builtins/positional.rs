@@ -0,0 +1,189 @@
+use crate::builtins::declare::quote;
+use crate::builtins::Builtin;
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use shell_vm::{Environment, IoEnvironment, ShellOption, Status, Value, WaitableStatus};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+#[derive(StructOpt)]
+/// Replace the positional parameters `$1`..`$N` with the specified
+/// arguments.  `set -- args...` replaces them even when no arguments
+/// follow the `--`, clearing them.  Without any arguments, print the
+/// shell variables in a syntax that can be read back by the shell.
+///
+/// `set -o name` enables the named shell option and `set +o name`
+/// disables it.  `set -o` lists the options and their states, while
+/// `set +o` prints them as commands that restore those states.
+/// Options that have a single letter form may also be specified
+/// that way, as in `set -e` or `set +x`.
+pub struct SetCommand {
+    #[structopt(parse(from_os_str))]
+    args: Vec<OsString>,
+}
+
+impl Builtin for SetCommand {
+    fn name() -> &'static str {
+        "set"
+    }
+
+    fn eval(
+        argv: &[Value],
+        environment: &mut Environment,
+        current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        // The options are parsed here rather than by clap, because
+        // clap has no notion of `+o`, and because it consumes the `--`,
+        // which would make a bare `set --` indistinguishable from `set`
+        let mut idx = 1;
+        while let Some(arg) = argv.get(idx).and_then(Value::as_str) {
+            match arg {
+                "--" => {
+                    environment.set_positional_params(argv[idx + 1..].to_vec());
+                    return Ok(Status::Complete(0.into()).into());
+                }
+                "-o" | "+o" => {
+                    let enable = arg == "-o";
+                    match argv.get(idx + 1).and_then(Value::as_str) {
+                        Some(name) => match ShellOption::from_name(name) {
+                            Some(option) => environment.set_option(option, enable),
+                            None => {
+                                writeln!(io_env.stderr(), "set: {}: invalid option name", name)?;
+                                return Ok(Status::Complete(2.into()).into());
+                            }
+                        },
+                        None => {
+                            print_options(environment, io_env, enable)?;
+                            return Ok(Status::Complete(0.into()).into());
+                        }
+                    }
+                    idx += 2;
+                }
+                _ => match letter_options(arg) {
+                    Some((enable, options)) => {
+                        for option in options {
+                            environment.set_option(option, enable);
+                        }
+                        idx += 1;
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        if idx > 1 && idx == argv.len() {
+            // Only options were specified
+            return Ok(Status::Complete(0.into()).into());
+        }
+        let mut args = Self::parse(&[&argv[0..1], &argv[idx..]].concat())?;
+        args.run(environment, current_directory, io_env, cancel, functions)
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        if self.args.is_empty() {
+            for (name, value, _) in environment.iter_variables() {
+                cancel.check_cancel()?;
+                writeln!(
+                    io_env.stdout(),
+                    "{}={}",
+                    name.to_string_lossy(),
+                    quote(&value.to_string_lossy())
+                )?;
+            }
+        } else {
+            environment.set_positional_params(self.args.drain(..).map(Value::OsString).collect());
+        }
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+/// Parses an argument such as `-ex` or `+x` into the options that it
+/// names, and whether they are to be enabled
+fn letter_options(arg: &str) -> Option<(bool, Vec<ShellOption>)> {
+    let enable = match arg.chars().next()? {
+        '-' => true,
+        '+' => false,
+        _ => return None,
+    };
+    let options = arg[1..]
+        .chars()
+        .map(ShellOption::from_letter)
+        .collect::<Option<Vec<_>>>()?;
+    if options.is_empty() {
+        None
+    } else {
+        Some((enable, options))
+    }
+}
+
+/// Print the state of each shell option.  Unless verbose, print
+/// them as `set` commands that would restore that state.
+fn print_options(
+    environment: &Environment,
+    io_env: &IoEnvironment,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let mut stdout = io_env.stdout();
+    for option in ShellOption::ALL {
+        let enabled = environment.is_option_set(*option);
+        if verbose {
+            writeln!(
+                stdout,
+                "{:15}\t{}",
+                option.name(),
+                if enabled { "on" } else { "off" }
+            )?;
+        } else {
+            writeln!(
+                stdout,
+                "set {}o {}",
+                if enabled { "-" } else { "+" },
+                option.name()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(StructOpt)]
+/// Shift the positional parameters to the left by n, so that `$n+1`
+/// becomes `$1`.  n defaults to 1.  It is an error for n to be larger
+/// than the number of positional parameters.
+pub struct ShiftCommand {
+    #[structopt(default_value = "1")]
+    n: usize,
+}
+
+impl Builtin for ShiftCommand {
+    fn name() -> &'static str {
+        "shift"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        if let Err(err) = environment.shift_positional(self.n) {
+            writeln!(io_env.stderr(), "shift: {}", err)?;
+            return Ok(Status::Complete(1.into()).into());
+        }
+        Ok(Status::Complete(0.into()).into())
+    }
+}
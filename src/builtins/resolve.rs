@@ -0,0 +1,191 @@
+use crate::builtins::{lookup_builtin, Builtin};
+use crate::shellhost::FunctionRegistry;
+use cancel::Token;
+use pathsearch::PathSearcher;
+use shell_lexer::ReservedWord;
+use shell_vm::{Environment, IoEnvironment, SourceLocation, Status, Value, WaitableStatus};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::*;
+
+/// Something that a command name resolves to
+enum Resolution {
+    Keyword,
+    Function(Option<SourceLocation>),
+    Builtin,
+    File(PathBuf),
+}
+
+impl Resolution {
+    /// The single word description used by `type -t`
+    fn kind(&self) -> &'static str {
+        match self {
+            Resolution::Keyword => "keyword",
+            Resolution::Function(_) => "function",
+            Resolution::Builtin => "builtin",
+            Resolution::File(_) => "file",
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Resolution::Keyword => format!("{} is a shell keyword", name),
+            Resolution::Function(Some(location)) => {
+                format!("{} is a function, defined at {}", name, location)
+            }
+            Resolution::Function(None) => format!("{} is a function", name),
+            Resolution::Builtin => format!("{} is a shell builtin", name),
+            Resolution::File(path) => format!("{} is {}", name, path.display()),
+        }
+    }
+}
+
+/// Resolve name in the same order that the shell uses when running
+/// a command: reserved words, then functions, then builtins and then
+/// the PATH.  Unless all is true, only the first match is returned.
+fn resolve(
+    name: &str,
+    environment: &Environment,
+    functions: &Arc<FunctionRegistry>,
+    cancel: &Token,
+    all: bool,
+) -> anyhow::Result<Vec<Resolution>> {
+    let mut result = vec![];
+    if ReservedWord::lookup(name).is_some() {
+        result.push(Resolution::Keyword);
+    }
+    if functions.lookup_function(name).is_some() {
+        result.push(Resolution::Function(functions.function_location(name)));
+    }
+    if lookup_builtin(&Value::String(name.to_owned())).is_some() {
+        result.push(Resolution::Builtin);
+    }
+    if all || result.is_empty() {
+        for path in PathSearcher::new(name, environment.get("PATH"), environment.get("PATHEXT")) {
+            cancel.check_cancel()?;
+            result.push(Resolution::File(path));
+            if !all {
+                break;
+            }
+        }
+    }
+    if !all {
+        result.truncate(1);
+    }
+    Ok(result)
+}
+
+#[derive(StructOpt)]
+/// Describe how each name would be interpreted if it were used
+/// as a command.
+pub struct TypeCommand {
+    /// Report all of the places that contain a command with the
+    /// specified name, rather than just the first
+    #[structopt(short = "a")]
+    all: bool,
+    /// Print a single word that describes the kind of each match;
+    /// one of `keyword`, `function`, `builtin` or `file`
+    #[structopt(short = "t", conflicts_with = "path")]
+    type_only: bool,
+    /// Print the path of the file that would be executed, or nothing
+    /// if the name doesn't resolve to a file
+    #[structopt(short = "p")]
+    path: bool,
+    names: Vec<String>,
+}
+
+impl Builtin for TypeCommand {
+    fn name() -> &'static str {
+        "type"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mut status = 0;
+        for name in &self.names {
+            let resolutions = resolve(name, environment, functions, &cancel, self.all)?;
+            if resolutions.is_empty() {
+                if !self.type_only && !self.path {
+                    writeln!(io_env.stderr(), "type: {}: not found", name)?;
+                }
+                status = 1;
+            }
+            for resolution in &resolutions {
+                if self.type_only {
+                    writeln!(io_env.stdout(), "{}", resolution.kind())?;
+                } else if self.path {
+                    if let Resolution::File(path) = resolution {
+                        writeln!(io_env.stdout(), "{}", path.display())?;
+                    }
+                } else {
+                    writeln!(io_env.stdout(), "{}", resolution.describe(name))?;
+                }
+            }
+        }
+        Ok(Status::Complete(status.into()).into())
+    }
+}
+
+#[derive(StructOpt)]
+/// `command -v` and `command -V` describe how each name would be
+/// interpreted if it were used as a command.  `command name args...`
+/// runs name as a command, bypassing any shell function of the same
+/// name; that form is handled by the shell itself.
+pub struct CommandCommand {
+    /// Print the path of each name that resolves to a file, or just
+    /// the name if it resolves to a function, builtin or keyword
+    #[structopt(short = "v")]
+    concise: bool,
+    /// Print a description of how each name would be interpreted
+    #[structopt(short = "V", conflicts_with = "concise")]
+    verbose: bool,
+    names: Vec<String>,
+}
+
+impl Builtin for CommandCommand {
+    fn name() -> &'static str {
+        "command"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        _current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        cancel: Arc<Token>,
+        functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        if !self.concise && !self.verbose {
+            writeln!(
+                io_env.stderr(),
+                "command: only the -v and -V forms are available as a builtin"
+            )?;
+            return Ok(Status::Complete(2.into()).into());
+        }
+
+        let mut status = 0;
+        for name in &self.names {
+            match resolve(name, environment, functions, &cancel, false)?.first() {
+                None => {
+                    if self.verbose {
+                        writeln!(io_env.stderr(), "command: {}: not found", name)?;
+                    }
+                    status = 1;
+                }
+                Some(resolution) if self.verbose => {
+                    writeln!(io_env.stdout(), "{}", resolution.describe(name))?
+                }
+                Some(Resolution::File(path)) => writeln!(io_env.stdout(), "{}", path.display())?,
+                Some(_) => writeln!(io_env.stdout(), "{}", name)?,
+            }
+        }
+        Ok(Status::Complete(status.into()).into())
+    }
+}
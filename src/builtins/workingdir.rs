@@ -1,7 +1,8 @@
 use crate::builtins::Builtin;
 use crate::shellhost::FunctionRegistry;
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use cancel::Token;
+use pathsearch::SimplePathSearcher;
 use shell_vm::{Environment, IoEnvironment, Status, WaitableStatus};
 use std::io::Write;
 use std::path::{Component, Path, PathBuf};
@@ -124,33 +125,442 @@ impl Builtin for CdCommand {
 
         let mut print = false;
 
-        let curpath = if directory.is_absolute() {
-            directory
-        } else if directory == Path::new("-") {
+        let directory = if directory == Path::new("-") {
             print = true;
-            PathBuf::from(
-                environment
-                    .get("OLDPWD")
-                    .ok_or_else(|| anyhow!("OLDPWD is not set"))?,
-            )
+            match environment.get("OLDPWD") {
+                Some(oldpwd) => PathBuf::from(oldpwd),
+                None => {
+                    writeln!(io_env.stderr(), "wzsh: cd: OLDPWD is not set")?;
+                    return Ok(Status::Complete(1.into()).into());
+                }
+            }
+        } else if let Some(found) = search_cdpath(environment, current_directory, &directory) {
+            // POSIX requires that the result be printed when
+            // a non-empty CDPATH entry was used
+            print = found != current_directory.join(&directory);
+            found
         } else {
-            current_directory.join(directory)
+            directory
         };
 
-        let cwd = canonicalize_path(curpath, self.physical)?;
-        if !cwd.is_dir() {
+        let cwd = match resolve_directory(current_directory, &directory, self.physical) {
+            Ok(cwd) => cwd,
+            Err(err) => {
+                writeln!(io_env.stderr(), "wzsh: cd: {}", err)?;
+                return Ok(Status::Complete(1.into()).into());
+            }
+        };
+
+        change_directory(environment, current_directory, cwd.clone())?;
+        if print {
+            writeln!(io_env.stdout(), "{}", cwd.display())?;
+        }
+        return Ok(Status::Complete(0.into()).into());
+    }
+}
+
+/// Search the colon separated CDPATH for a relative directory.
+/// Directories that start with `.` or `..` are not searched for.
+/// Relative CDPATH entries, including the empty entry, are relative
+/// to current_directory.
+fn search_cdpath(
+    environment: &Environment,
+    current_directory: &Path,
+    directory: &Path,
+) -> Option<PathBuf> {
+    match directory.components().next()? {
+        Component::Normal(_) => {}
+        _ => return None,
+    }
+    let cdpath = environment.get("CDPATH")?;
+    let cdpath =
+        std::env::join_paths(std::env::split_paths(cdpath).map(|e| current_directory.join(e)))
+            .ok()?;
+    SimplePathSearcher::new(directory, Some(&cdpath))
+        .directories()
+        .next()
+}
+
+/// Resolve directory relative to current_directory, verifying that
+/// the result is a directory
+fn resolve_directory(
+    current_directory: &Path,
+    directory: &Path,
+    physical: bool,
+) -> anyhow::Result<PathBuf> {
+    let cwd = canonicalize_path(current_directory.join(directory), physical)?;
+    if !cwd.is_dir() {
+        return Err(anyhow!("{} is not a directory", cwd.display()));
+    }
+    Ok(cwd)
+}
+
+/// Make directory the current directory, updating PWD and OLDPWD
+/// to match
+fn change_directory(
+    environment: &mut Environment,
+    current_directory: &mut PathBuf,
+    directory: PathBuf,
+) -> anyhow::Result<()> {
+    environment.assign("OLDPWD", current_directory.as_os_str())?;
+    environment.assign("PWD", directory.as_os_str())?;
+    *current_directory = directory;
+    Ok(())
+}
+
+/// The directories saved by pushd are held in the DIRSTACK array,
+/// most recently pushed first.  Together with the current directory,
+/// which is implicitly at the top, they form the directory stack.
+const DIRSTACK: &str = "DIRSTACK";
+
+/// Returns the directory stack, including the current directory
+fn directory_stack(environment: &Environment, current_directory: &Path) -> Vec<PathBuf> {
+    let mut stack = vec![current_directory.to_path_buf()];
+    if let Some(array) = environment.get_array(DIRSTACK) {
+        stack.extend(array.values().into_iter().map(PathBuf::from));
+    }
+    stack
+}
+
+/// Changes to the directory at the top of stack and saves the rest
+fn set_directory_stack(
+    environment: &mut Environment,
+    current_directory: &mut PathBuf,
+    mut stack: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let top = stack.remove(0);
+    if top != *current_directory {
+        if !top.is_dir() {
+            bail!("{} is not a directory", top.display());
+        }
+        change_directory(environment, current_directory, top)?;
+    }
+    environment.assign_array(
+        DIRSTACK,
+        stack
+            .into_iter()
+            .map(|dir| (None, dir.into_os_string()))
+            .collect(),
+    )
+}
+
+/// Parses a `+N` or `-N` stack position into an index into a stack
+/// of len entries.  `+N` counts from the top, starting with zero,
+/// and `-N` counts from the bottom.
+fn stack_index(position: &str, len: usize) -> Option<usize> {
+    let from_top = position.starts_with('+');
+    if !from_top && !position.starts_with('-') {
+        return None;
+    }
+    let n: usize = position[1..].parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    Some(if from_top { n } else { len - 1 - n })
+}
+
+/// Formats a directory for display, abbreviating the home
+/// directory as `~` unless long is true
+fn display_directory(dir: &Path, environment: &Environment, long: bool) -> String {
+    if !long {
+        if let Some(home) = environment.get("HOME") {
+            if let Ok(rest) = dir.strip_prefix(home) {
+                if rest.as_os_str().is_empty() {
+                    return "~".to_owned();
+                }
+                return format!("~/{}", rest.display());
+            }
+        }
+    }
+    dir.display().to_string()
+}
+
+fn print_stack(
+    stack: &[PathBuf],
+    environment: &Environment,
+    io_env: &IoEnvironment,
+    long: bool,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let mut stdout = io_env.stdout();
+    if verbose {
+        for (idx, dir) in stack.iter().enumerate() {
             writeln!(
-                io_env.stderr(),
-                "wzsh: cd: {} is not a directory",
-                cwd.display()
+                stdout,
+                "{:2}  {}",
+                idx,
+                display_directory(dir, environment, long)
             )?;
+        }
+    } else {
+        let dirs: Vec<String> = stack
+            .iter()
+            .map(|dir| display_directory(dir, environment, long))
+            .collect();
+        writeln!(stdout, "{}", dirs.join(" "))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(raw(setting = "structopt::clap::AppSettings::AllowNegativeNumbers"))]
+/// Save the current directory on the directory stack and change to
+/// the specified directory.  `+N` or `-N` instead rotates the stack
+/// so that the Nth directory, counting from the top or bottom
+/// respectively, becomes the current directory.  Without arguments,
+/// exchange the top two directories.
+pub struct PushdCommand {
+    /// The destination directory, or stack position
+    target: Option<String>,
+}
+
+impl Builtin for PushdCommand {
+    fn name() -> &'static str {
+        "pushd"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mut stack = directory_stack(environment, current_directory);
+
+        match self.target.as_ref() {
+            None => {
+                if stack.len() < 2 {
+                    writeln!(io_env.stderr(), "wzsh: pushd: no other directory")?;
+                    return Ok(Status::Complete(1.into()).into());
+                }
+                stack.swap(0, 1);
+            }
+            Some(position) if position.starts_with(&['+', '-'][..]) => {
+                match stack_index(position, stack.len()) {
+                    Some(idx) => stack.rotate_left(idx),
+                    None => {
+                        writeln!(
+                            io_env.stderr(),
+                            "wzsh: pushd: {}: directory stack index out of range",
+                            position
+                        )?;
+                        return Ok(Status::Complete(1.into()).into());
+                    }
+                }
+            }
+            Some(directory) => {
+                match resolve_directory(current_directory, Path::new(directory), false) {
+                    Ok(dir) => stack.insert(0, dir),
+                    Err(err) => {
+                        writeln!(io_env.stderr(), "wzsh: pushd: {}", err)?;
+                        return Ok(Status::Complete(1.into()).into());
+                    }
+                }
+            }
+        }
+
+        if let Err(err) = set_directory_stack(environment, current_directory, stack) {
+            writeln!(io_env.stderr(), "wzsh: pushd: {}", err)?;
             return Ok(Status::Complete(1.into()).into());
         }
+        let stack = directory_stack(environment, current_directory);
+        print_stack(&stack, environment, io_env, false, false)?;
+        Ok(Status::Complete(0.into()).into())
+    }
+}
 
-        *current_directory = cwd.clone();
-        if print {
-            writeln!(io_env.stdout(), "{}", cwd.display())?;
+#[derive(Debug, StructOpt)]
+#[structopt(raw(setting = "structopt::clap::AppSettings::AllowNegativeNumbers"))]
+/// Remove the top directory from the directory stack and change to
+/// the new top directory.  `+N` or `-N` instead removes the Nth
+/// directory, counting from the top or bottom respectively.
+pub struct PopdCommand {
+    /// The stack position to remove
+    position: Option<String>,
+}
+
+impl Builtin for PopdCommand {
+    fn name() -> &'static str {
+        "popd"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        let mut stack = directory_stack(environment, current_directory);
+        if stack.len() < 2 {
+            writeln!(io_env.stderr(), "wzsh: popd: directory stack empty")?;
+            return Ok(Status::Complete(1.into()).into());
         }
-        return Ok(Status::Complete(0.into()).into());
+
+        let idx = match self.position.as_ref() {
+            None => 0,
+            Some(position) => match stack_index(position, stack.len()) {
+                Some(idx) => idx,
+                None => {
+                    writeln!(
+                        io_env.stderr(),
+                        "wzsh: popd: {}: directory stack index out of range",
+                        position
+                    )?;
+                    return Ok(Status::Complete(1.into()).into());
+                }
+            },
+        };
+        stack.remove(idx);
+
+        if let Err(err) = set_directory_stack(environment, current_directory, stack) {
+            writeln!(io_env.stderr(), "wzsh: popd: {}", err)?;
+            return Ok(Status::Complete(1.into()).into());
+        }
+        let stack = directory_stack(environment, current_directory);
+        print_stack(&stack, environment, io_env, false, false)?;
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+/// Display the directory stack, starting with the current directory.
+pub struct DirsCommand {
+    /// Clear the directory stack, leaving only the current directory
+    #[structopt(short = "c")]
+    clear: bool,
+    /// Show the full path of each directory rather than abbreviating
+    /// the home directory as `~`
+    #[structopt(short = "l")]
+    long: bool,
+    /// Print one directory per line, prefixed by its stack position
+    #[structopt(short = "v")]
+    verbose: bool,
+}
+
+impl Builtin for DirsCommand {
+    fn name() -> &'static str {
+        "dirs"
+    }
+
+    fn run(
+        &mut self,
+        environment: &mut Environment,
+        current_directory: &mut PathBuf,
+        io_env: &IoEnvironment,
+        _cancel: Arc<Token>,
+        _functions: &Arc<FunctionRegistry>,
+    ) -> anyhow::Result<WaitableStatus> {
+        if self.clear {
+            environment.assign_array(DIRSTACK, vec![])?;
+        } else {
+            let stack = directory_stack(environment, current_directory);
+            print_stack(&stack, environment, io_env, self.long, self.verbose)?;
+        }
+        Ok(Status::Complete(0.into()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::{run_builtin, scratch_dir};
+
+    #[test]
+    fn stack_positions() {
+        assert_eq!(stack_index("+0", 3), Some(0));
+        assert_eq!(stack_index("+2", 3), Some(2));
+        assert_eq!(stack_index("-0", 3), Some(2));
+        assert_eq!(stack_index("-2", 3), Some(0));
+        assert_eq!(stack_index("+3", 3), None);
+        assert_eq!(stack_index("-3", 3), None);
+        assert_eq!(stack_index("2", 3), None);
+        assert_eq!(stack_index("+x", 3), None);
+    }
+
+    #[test]
+    fn cdpath() -> anyhow::Result<()> {
+        let root = scratch_dir("cdpath")?;
+        for dir in &[
+            "base/proj",
+            "base/only",
+            "here/proj",
+            "here/local",
+            "here/rel/sub",
+        ] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        let here = root.join("here");
+        let base = root.join("base");
+
+        let cd = |cdpath: &str, dir: &str| -> anyhow::Result<(isize, String, PathBuf)> {
+            let mut env = Environment::new_empty();
+            env.set(
+                "CDPATH",
+                cdpath.replace("ROOT", &root.display().to_string()),
+            );
+            let mut cwd = here.clone();
+            let (status, stdout, _stderr) =
+                run_builtin::<CdCommand>(&["cd", dir], &mut env, &mut cwd)?;
+            if status == 0 {
+                assert_eq!(env.get("PWD"), Some(cwd.as_os_str()));
+            }
+            Ok((status, stdout, cwd))
+        };
+        let printed = |dir: &Path| format!("{}\n", dir.display());
+
+        // Found through a CDPATH entry, so the new directory is printed
+        assert_eq!(
+            cd("ROOT/base", "proj")?,
+            (0, printed(&base.join("proj")), base.join("proj"))
+        );
+        assert_eq!(
+            cd("ROOT/base", "only")?,
+            (0, printed(&base.join("only")), base.join("only"))
+        );
+        // Relative entries are relative to the current directory
+        assert_eq!(
+            cd("rel", "sub")?,
+            (0, printed(&here.join("rel/sub")), here.join("rel/sub"))
+        );
+
+        // The empty and `.` entries are the current directory, and
+        // using them doesn't print anything
+        for cdpath in &[":ROOT/base", "ROOT/nowhere::ROOT/base", ".:ROOT/base"] {
+            assert_eq!(
+                cd(cdpath, "proj")?,
+                (0, String::new(), here.join("proj")),
+                "CDPATH={}",
+                cdpath
+            );
+        }
+        assert_eq!(
+            cd("ROOT/base:", "local")?,
+            (0, String::new(), here.join("local"))
+        );
+        // Without an empty or `.` entry, the current directory is
+        // only used once the search fails
+        assert_eq!(
+            cd("ROOT/base", "local")?,
+            (0, String::new(), here.join("local"))
+        );
+
+        // Directories that start with `.`, `..` or `/` aren't searched for
+        assert_eq!(
+            cd("ROOT/base", "./proj")?,
+            (0, String::new(), here.join("proj"))
+        );
+        assert_eq!(cd("ROOT/base", "./only")?.0, 1);
+        assert_eq!(cd("ROOT", "../base")?, (0, String::new(), base.clone()));
+        assert_eq!(
+            cd("ROOT/base", &here.join("proj").display().to_string())?,
+            (0, String::new(), here.join("proj"))
+        );
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
     }
 }
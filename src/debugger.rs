@@ -0,0 +1,438 @@
+use crate::errorprint::print_span;
+use anyhow::Context;
+use shell_vm::{Environment, Machine, Program, RuntimeError, SourceLocation, Status, Value};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+
+const HELP: &str = "\
+step, s            run to the next command, entering functions
+next, n            run to the next command, stepping over functions
+finish             run until the current function returns
+continue, c        run until a breakpoint is reached
+break, b [WHERE]   pause at LINE, FILE:LINE or a function name;
+                   the current line if WHERE is omitted
+delete, d [N]      delete breakpoint N, or all of them
+breakpoints        list the breakpoints
+print, p [NAME..]  print variables, including $1..$N, $@ and $#;
+                   all of the variables if no name is given
+args               print the positional parameters
+backtrace, bt      print the function call stack
+where, l           show the command that is about to run
+quit, q            stop running the script
+Entering an empty line repeats the previous command.";
+
+/// A place at which the debugger pauses the program
+#[derive(Debug, PartialEq, Eq)]
+enum Breakpoint {
+    /// The first command on a (one-based) line of a file
+    Line { file: String, line: usize },
+    /// The first command in a function, each time that it is called
+    Function(String),
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line { file, line } => write!(fmt, "{}:{}", file, line),
+            Breakpoint::Function(name) => write!(fmt, "function {}", name),
+        }
+    }
+}
+
+/// Parses `LINE`, `FILE:LINE` or a function name into a breakpoint.
+/// file is that which a bare line number refers to.
+fn parse_breakpoint(spec: &str, file: String) -> Breakpoint {
+    if let Some((file, line)) = spec.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return Breakpoint::Line {
+                file: file.to_owned(),
+                line,
+            };
+        }
+    }
+    match spec.parse() {
+        Ok(line) => Breakpoint::Line { file, line },
+        Err(_) => Breakpoint::Function(spec.to_owned()),
+    }
+}
+
+/// How far the program runs before the debugger pauses it again
+#[derive(Clone, Copy)]
+enum Resume {
+    /// Pause before the next command, including those in called functions
+    Step,
+    /// Pause before the next command at or above this call depth
+    Next(usize),
+    /// Pause before the next command above this call depth
+    Finish(usize),
+    /// Pause only at a breakpoint
+    Continue,
+}
+
+/// The debugger drives a Machine one step at a time, pausing before
+/// simple commands and function definitions to accept commands from
+/// the user.  It pauses before the first command of the program.
+/// Functions that run outside of the machine, such as those in a
+/// pipeline, run without pausing.
+pub struct Debugger {
+    /// The text of the source files, which is used to show the
+    /// command that is about to run
+    sources: HashMap<String, Option<String>>,
+    /// The file that line breakpoints refer to when none is named
+    file_name: String,
+    breakpoints: Vec<Breakpoint>,
+    resume: Resume,
+    /// The call depth, program and line of the previous command,
+    /// which are used to recognize entry into a function and to
+    /// pause only at the first command on a line
+    previous: Option<(usize, Arc<Program>, Option<usize>)>,
+    last_command: String,
+    /// The terminal that commands are read from, so that they don't
+    /// compete with the program for its stdin.  When there is no
+    /// terminal, commands are read from stdin instead.
+    terminal: Option<BufReader<File>>,
+}
+
+/// The path through which the controlling terminal is opened
+#[cfg(unix)]
+const TERMINAL: &str = "/dev/tty";
+#[cfg(windows)]
+const TERMINAL: &str = "CONIN$";
+
+impl Debugger {
+    pub fn new(file_name: &str, input: &str) -> Self {
+        let mut sources = HashMap::new();
+        sources.insert(file_name.to_owned(), Some(input.to_owned()));
+        Self {
+            sources,
+            file_name: file_name.to_owned(),
+            breakpoints: vec![],
+            resume: Resume::Step,
+            previous: None,
+            last_command: String::new(),
+            terminal: File::open(TERMINAL).ok().map(BufReader::new),
+        }
+    }
+
+    /// Run the program to completion, pausing as directed by the user
+    pub fn run(&mut self, machine: &mut Machine) -> anyhow::Result<Status> {
+        loop {
+            let program = Arc::clone(machine.program());
+            let pc = machine.program_counter();
+            if program.is_command_start(pc) && self.should_pause(machine, &program, pc)? {
+                self.show_location(machine)?;
+                if let Some(status) = self.prompt(machine)? {
+                    return Ok(status);
+                }
+            }
+            match machine.step() {
+                Ok(Status::Running) => continue,
+                Ok(status) => return Ok(status),
+                Err(err) => {
+                    // The address of the failing opcode is of interest
+                    // only when debugging, so it isn't part of the error
+                    if let Some(err) = err.downcast_ref::<RuntimeError>() {
+                        eprintln!("Error at PC={}", err.pc);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Decide whether to pause before the command at pc, reporting
+    /// any breakpoint that caused it
+    fn should_pause(
+        &mut self,
+        machine: &Machine,
+        program: &Arc<Program>,
+        pc: usize,
+    ) -> anyhow::Result<bool> {
+        let depth = machine.call_depth();
+        let line = program.span(pc).map(|span| span.start.line);
+        let (entered, new_line) = match &self.previous {
+            Some((prev_depth, prev_program, prev_line)) => (
+                depth > *prev_depth,
+                !Arc::ptr_eq(prev_program, program) || *prev_line != line,
+            ),
+            None => (depth > 0, true),
+        };
+        self.previous = Some((depth, Arc::clone(program), line));
+
+        let function = current_function(machine.environment()?);
+        let hit = self.breakpoints.iter().position(|bp| match bp {
+            Breakpoint::Line {
+                file,
+                line: bp_line,
+            } => {
+                new_line
+                    && line.map(|line| line + 1) == Some(*bp_line)
+                    && program.source_name() == Some(file.as_str())
+            }
+            Breakpoint::Function(name) => entered && function == Some(name.as_str()),
+        });
+        if let Some(idx) = hit {
+            eprintln!("Breakpoint {}, {}", idx + 1, self.breakpoints[idx]);
+            return Ok(true);
+        }
+
+        Ok(match self.resume {
+            Resume::Step => true,
+            Resume::Next(at) => depth <= at,
+            Resume::Finish(at) => depth < at,
+            Resume::Continue => false,
+        })
+    }
+
+    /// Accept commands until one of them resumes the program.
+    /// Returns the status to complete with if the user quits.
+    fn prompt(&mut self, machine: &Machine) -> anyhow::Result<Option<Status>> {
+        loop {
+            eprint!("(wzsh-debug) ");
+            std::io::stderr().flush()?;
+            let mut line = String::new();
+            let len = match self.terminal.as_mut() {
+                Some(terminal) => terminal.read_line(&mut line),
+                None => std::io::stdin().read_line(&mut line),
+            };
+            if len.context("reading debugger command")? == 0 {
+                eprintln!();
+                return Ok(Some(Status::Complete(1.into())));
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => {
+                    self.last_command = line.to_owned();
+                    line.to_owned()
+                }
+            };
+            let mut words = line.split_whitespace();
+            let command = match words.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+            let env = machine.environment()?;
+            let depth = machine.call_depth();
+
+            match command {
+                "step" | "s" => {
+                    self.resume = Resume::Step;
+                    return Ok(None);
+                }
+                "next" | "n" => {
+                    self.resume = Resume::Next(depth);
+                    return Ok(None);
+                }
+                "finish" => {
+                    if depth == 0 {
+                        eprintln!("not within a function");
+                        continue;
+                    }
+                    self.resume = Resume::Finish(depth);
+                    return Ok(None);
+                }
+                "continue" | "c" => {
+                    self.resume = Resume::Continue;
+                    return Ok(None);
+                }
+                "break" | "b" => self.add_breakpoint(machine, args.first().copied()),
+                "delete" | "d" => self.delete_breakpoint(args.first().copied()),
+                "breakpoints" => {
+                    for (idx, bp) in self.breakpoints.iter().enumerate() {
+                        eprintln!("{:3}  {}", idx + 1, bp);
+                    }
+                }
+                "print" | "p" if args.is_empty() => {
+                    for (name, value, _) in env.iter_variables() {
+                        eprintln!("{}={:?}", name.to_string_lossy(), value.to_string_lossy());
+                    }
+                }
+                "print" | "p" => {
+                    for name in args {
+                        print_variable(env, name);
+                    }
+                }
+                "args" => {
+                    for (idx, value) in env.positional().iter().enumerate() {
+                        eprintln!("${}={:?}", idx, display_value(value));
+                    }
+                }
+                "backtrace" | "bt" => print_backtrace(machine)?,
+                "where" | "l" => self.show_location(machine)?,
+                "quit" | "q" => return Ok(Some(Status::Complete(1.into()))),
+                "help" | "h" => eprintln!("{}", HELP),
+                _ => eprintln!("unknown command {}; try help", command),
+            }
+        }
+    }
+
+    fn add_breakpoint(&mut self, machine: &Machine, spec: Option<&str>) {
+        let program = machine.program();
+        let file = program.source_name().unwrap_or(&self.file_name).to_owned();
+        let bp = match spec {
+            None => match program.span(machine.program_counter()) {
+                Some(span) => Breakpoint::Line {
+                    file,
+                    line: span.start.line + 1,
+                },
+                None => {
+                    eprintln!("the current line is not known");
+                    return;
+                }
+            },
+            Some(spec) => parse_breakpoint(spec, file),
+        };
+        eprintln!("Breakpoint {} at {}", self.breakpoints.len() + 1, bp);
+        self.breakpoints.push(bp);
+    }
+
+    fn delete_breakpoint(&mut self, spec: Option<&str>) {
+        match spec.map(str::parse::<usize>) {
+            None => self.breakpoints.clear(),
+            Some(Ok(n)) if n >= 1 && n <= self.breakpoints.len() => {
+                self.breakpoints.remove(n - 1);
+            }
+            Some(_) => eprintln!("no breakpoint {}", spec.unwrap_or_default()),
+        }
+    }
+
+    /// Show the function and source of the command that is about to run
+    fn show_location(&mut self, machine: &Machine) -> anyhow::Result<()> {
+        let program = machine.program();
+        let function = current_function(machine.environment()?).unwrap_or("main");
+        let span = match program.span(machine.program_counter()) {
+            Some(span) => span,
+            None => {
+                eprintln!("in {}", function);
+                return Ok(());
+            }
+        };
+        let location = SourceLocation {
+            file: program.source_name().map(str::to_owned),
+            line: span.start.line,
+        };
+        eprintln!("in {} at {}", function, location);
+        if let Some(source) = self.source(program.source_name()) {
+            print_span(span, source);
+        }
+        Ok(())
+    }
+
+    /// Returns the text of the named source file, reading it if
+    /// it hasn't been seen before
+    fn source(&mut self, file_name: Option<&str>) -> Option<&str> {
+        let file_name = file_name?;
+        self.sources
+            .entry(file_name.to_owned())
+            .or_insert_with(|| std::fs::read_to_string(file_name).ok())
+            .as_deref()
+    }
+}
+
+/// Returns the name of the innermost function that is running
+fn current_function(env: &Environment) -> Option<&str> {
+    env.call_stack().last().map(|call| call.function.as_str())
+}
+
+fn display_value(value: &Value) -> String {
+    value
+        .as_os_str()
+        .map(OsStr::to_string_lossy)
+        .map(Into::into)
+        .unwrap_or_else(|| format!("{:?}", value))
+}
+
+/// Print a variable, a positional parameter, `$@` or `$#`
+fn print_variable(env: &Environment, name: &str) {
+    let name = name.trim_start_matches('$');
+    let params = env.positional().get(1..).unwrap_or(&[]);
+    match name {
+        "@" | "*" => {
+            let params: Vec<String> = params.iter().map(display_value).collect();
+            eprintln!("${}={:?}", name, params.join(" "));
+        }
+        "#" => eprintln!("$#={}", params.len()),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => {
+            match name
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| env.positional().get(n))
+            {
+                Some(value) => eprintln!("${}={:?}", name, display_value(value)),
+                None => eprintln!("${} is unset", name),
+            }
+        }
+        _ => {
+            if let Some(array) = env.get_array(name) {
+                let elements: Vec<String> = array
+                    .keys()
+                    .into_iter()
+                    .zip(array.values())
+                    .map(|(key, value)| format!("[{}]={:?}", key, value.to_string_lossy()))
+                    .collect();
+                eprintln!("{}=({})", name, elements.join(" "));
+            } else {
+                match env.get(name) {
+                    Some(value) => eprintln!("{}={:?}", name, value.to_string_lossy()),
+                    None => eprintln!("{} is unset", name),
+                }
+            }
+        }
+    }
+}
+
+/// Print the function calls that are in progress, innermost first,
+/// along with the location that each of them has reached
+fn print_backtrace(machine: &Machine) -> anyhow::Result<()> {
+    let program = machine.program();
+    let stack = machine.environment()?.call_stack();
+    let mut location = program
+        .span(machine.program_counter())
+        .map(|span| SourceLocation {
+            file: program.source_name().map(str::to_owned),
+            line: span.start.line,
+        });
+    for (idx, call) in stack.iter().rev().enumerate() {
+        print_frame(idx, &call.function, location.as_ref());
+        location = call.location();
+    }
+    print_frame(stack.len(), "main", location.as_ref());
+    Ok(())
+}
+
+fn print_frame(idx: usize, function: &str, location: Option<&SourceLocation>) {
+    match location {
+        Some(location) => eprintln!("#{:<3} {} at {}", idx, function, location),
+        None => eprintln!("#{:<3} {}", idx, function),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn breakpoints() {
+        let line = |file: &str, line| Breakpoint::Line {
+            file: file.to_owned(),
+            line,
+        };
+        assert_eq!(parse_breakpoint("12", "a.wzsh".into()), line("a.wzsh", 12));
+        assert_eq!(
+            parse_breakpoint("b.wzsh:3", "a.wzsh".into()),
+            line("b.wzsh", 3)
+        );
+        assert_eq!(
+            parse_breakpoint("greet", "a.wzsh".into()),
+            Breakpoint::Function("greet".to_owned())
+        );
+        assert_eq!(
+            parse_breakpoint("ns:greet", "a.wzsh".into()),
+            Breakpoint::Function("ns:greet".to_owned())
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::Error;
 use shell_lexer::{LexError, Span};
 use shell_parser::ParseErrorKind;
+use shell_vm::{CallSite, RuntimeError};
+use std::borrow::Cow;
 use std::io::Read;
 use std::path::Path;
 
@@ -11,11 +13,27 @@ fn extract_error_range(e: &Error) -> Option<Span> {
         match parse_err {
             ParseErrorKind::UnexpectedToken(token, ..) => Some(token.span()),
         }
+    } else if let Some(runtime_err) = e.downcast_ref::<RuntimeError>() {
+        runtime_err.span
     } else {
         None
     }
 }
 
+/// Returns the source text that the span of the error refers to.
+/// That is usually input, but a runtime error may have occurred in
+/// a function that was defined by some other file.
+fn error_source<'a>(e: &Error, input: &'a str) -> Option<Cow<'a, str>> {
+    let file = match e.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError {
+            source_name: Some(file),
+            ..
+        }) if Path::new(file).is_file() => file,
+        _ => return Some(Cow::Borrowed(input)),
+    };
+    std::fs::read_to_string(file).ok().map(Cow::Owned)
+}
+
 pub fn print_error_path(e: &Error, path: &Path) {
     let mut file = match std::fs::File::open(path) {
         Ok(file) => file,
@@ -38,41 +56,151 @@ pub fn print_error(e: &Error, input: &str) {
     for item in e.chain() {
         eprintln!("wzsh: {}", item);
     }
-    if let Some(span) = extract_error_range(e) {
-        let lines: Vec<&str> = input.split('\n').collect();
+    print_source(e, input);
+    print_backtrace(e);
+}
 
-        let start_line = &lines[span.start.line];
-        let end_line = &lines[span.end.line];
+/// The most lines that a backtrace may occupy; deep recursion can
+/// otherwise bury the error under thousands of lines
+const MAX_BACKTRACE_LINES: usize = 20;
 
-        let mut indicator = String::new();
-        let end_col = if span.start.line == span.end.line {
-            span.end.col
-        } else {
-            start_line.len()
-        };
+/// Print the function calls that were in progress when a runtime
+/// error occurred, innermost first
+fn print_backtrace(e: &Error) {
+    if let Some(runtime_err) = e.downcast_ref::<RuntimeError>() {
+        for line in backtrace_lines(&runtime_err.backtrace) {
+            eprintln!("wzsh:   {}", line);
+        }
+    }
+}
 
-        for _ in 0..span.start.col {
-            indicator.push(' ');
+/// Describe the calls in backtrace, innermost first.  Repeated calls
+/// from the same place, as made by a recursive function, are collapsed
+/// into a single line, and the description is cut short after
+/// MAX_BACKTRACE_LINES lines.
+fn backtrace_lines(backtrace: &[CallSite]) -> Vec<String> {
+    let mut lines = vec![];
+    let mut remaining = backtrace.len();
+    let mut calls = backtrace.iter().rev().peekable();
+    while let Some(call) = calls.next() {
+        if lines.len() >= MAX_BACKTRACE_LINES {
+            lines.push(format!("... {} more calls", remaining));
+            break;
+        }
+        lines.push(match call.location() {
+            Some(location) => format!("in {}, called from {}", call.function, location),
+            None => format!("in {}", call.function),
+        });
+        let mut repeats = 0;
+        while calls.peek() == Some(&call) {
+            calls.next();
+            repeats += 1;
+        }
+        if repeats > 0 {
+            lines.push(format!(
+                "... {} more calls to {} from the same place",
+                repeats, call.function
+            ));
         }
+        remaining -= 1 + repeats;
+    }
+    lines
+}
+
+/// Print the line(s) of source that the error refers to, with the
+/// erroneous portion underlined
+fn print_source(e: &Error, input: &str) {
+    if let (Some(span), Some(input)) = (extract_error_range(e), error_source(e, input)) {
+        print_span(span, &input);
+    }
+}
+
+/// Print the line(s) of input that span covers, underlining the
+/// portion that it refers to
+pub fn print_span(span: Span, input: &str) {
+    let lines: Vec<&str> = input.split('\n').collect();
+
+    let (start_line, end_line) = match (lines.get(span.start.line), lines.get(span.end.line)) {
+        (Some(start_line), Some(end_line)) => (start_line, end_line),
+        _ => return,
+    };
 
+    let mut indicator = String::new();
+    let end_col = if span.start.line == span.end.line {
+        span.end.col
+    } else {
+        start_line.len()
+    };
+
+    for _ in 0..span.start.col {
+        indicator.push(' ');
+    }
+
+    indicator.push_str("\x1b[1m");
+    for _ in span.start.col..=end_col {
+        indicator.push('^');
+    }
+    indicator.push_str("\x1b[0m");
+
+    eprintln!("{}", start_line);
+    eprintln!("{}", indicator);
+
+    if span.end.line != span.start.line {
+        indicator.clear();
         indicator.push_str("\x1b[1m");
-        for _ in span.start.col..=end_col {
+        for _ in 0..=span.end.col {
             indicator.push('^');
         }
         indicator.push_str("\x1b[0m");
-
-        eprintln!("{}", start_line);
+        eprintln!("{}", end_line);
         eprintln!("{}", indicator);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        if span.end.line != span.start.line {
-            indicator.clear();
-            indicator.push_str("\x1b[1m");
-            for _ in 0..=span.end.col {
-                indicator.push('^');
-            }
-            indicator.push_str("\x1b[0m");
-            eprintln!("{}", end_line);
-            eprintln!("{}", indicator);
+    fn call(function: &str, line: usize) -> CallSite {
+        CallSite {
+            function: function.to_owned(),
+            span: Some(Span::new_to(line, 0, 1)),
+            source_name: None,
         }
     }
+
+    #[test]
+    fn collapse_recursion() {
+        let mut backtrace = vec![call("main_loop", 9)];
+        backtrace.extend(vec![call("recurse", 2); 1000]);
+        backtrace.push(call("fail", 4));
+        assert_eq!(
+            backtrace_lines(&backtrace),
+            vec![
+                "in fail, called from line 5",
+                "in recurse, called from line 3",
+                "... 999 more calls to recurse from the same place",
+                "in main_loop, called from line 10",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncate_long_backtraces() {
+        // Mutual recursion doesn't repeat a call immediately
+        let backtrace: Vec<CallSite> = (0..100)
+            .map(|i| {
+                if i % 2 == 0 {
+                    call("even", 1)
+                } else {
+                    call("odd", 2)
+                }
+            })
+            .collect();
+        let lines = backtrace_lines(&backtrace);
+        assert_eq!(lines.len(), MAX_BACKTRACE_LINES + 1);
+        assert_eq!(lines[0], "in odd, called from line 3");
+        assert_eq!(lines[1], "in even, called from line 2");
+        assert_eq!(lines[MAX_BACKTRACE_LINES], "... 80 more calls");
+    }
 }
@@ -174,6 +174,12 @@ impl JobList {
         jobs.iter().map(|(_, v)| v.clone()).collect()
     }
 
+    pub fn has_stopped_jobs(&self) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.values_mut()
+            .any(|job| job.poll() == Some(Status::Stopped))
+    }
+
     pub fn check_and_print_status(&self) {
         let mut jobs = self.jobs.lock().unwrap();
         let mut terminated = vec![];
@@ -1,12 +1,14 @@
 use crate::errorprint::{print_error, print_error_path};
 use crate::shellhost::FunctionRegistry;
-use shell_vm::Environment;
+use shell_vm::{Environment, ShellOption, Status, Value};
+use std::ffi::OsString;
 use std::io::Read;
 use std::path::PathBuf;
 use std::sync::Arc;
 use structopt::StructOpt;
 
 mod builtins;
+mod debugger;
 mod errorprint;
 mod exitstatus;
 mod job;
@@ -18,23 +20,291 @@ mod shellhost;
 #[structopt(about = "Wez's Shell\nhttp://github.com/wez/wzsh")]
 #[structopt(raw(
     global_setting = "structopt::clap::AppSettings::ColoredHelp",
+    setting = "structopt::clap::AppSettings::TrailingVarArg",
     version = r#"env!("VERGEN_SEMVER_LIGHTWEIGHT")"#,
 ))]
 struct Opt {
-    /// Skip loading startup.wzsh
+    /// Skip loading startup.wzsh, and the profile and logout
+    /// files of a login shell
     #[structopt(long = "no-startup")]
     skip_startup: bool,
 
+    /// Act as a login shell, loading profile.wzsh before startup.wzsh,
+    /// and logout.wzsh when the shell exits.  This is implied when the
+    /// shell is invoked with a name that starts with `-`
+    #[structopt(short = "l", long = "login")]
+    login: bool,
+
+    /// Run the commands in the string instead of a file.  The first
+    /// of the remaining arguments sets `$0`, and the rest of them
+    /// become the positional parameters
+    #[structopt(short = "c")]
+    command: Option<String>,
+
+    /// Parse and compile the commands without running them, in
+    /// order to check their syntax
+    #[structopt(short = "n")]
+    no_exec: bool,
+
+    /// Print the syntax tree of the commands instead of running them
+    #[structopt(long = "dump-ast")]
+    dump_ast: bool,
+
+    /// Print a disassembly of the compiled commands, and of the
+    /// functions that they define, instead of running them
+    #[structopt(long = "dump-bytecode")]
+    dump_bytecode: bool,
+
+    /// Run the script file or `-c` commands under an interactive
+    /// debugger, which pauses before the first command.  Enter `help`
+    /// at its prompt for the commands that it accepts
+    #[structopt(long = "debug")]
+    debug: bool,
+
+    /// Enable the errexit option, exiting if a command fails
+    #[structopt(short = "e")]
+    errexit: bool,
+
+    /// Enable the xtrace option, printing commands before they run
+    #[structopt(short = "x")]
+    xtrace: bool,
+
+    /// Read commands from stdin; all of the remaining arguments
+    /// become the positional parameters
+    #[structopt(short = "s")]
+    stdin: bool,
+
+    /// Start the interactive REPL even if stdin is not a terminal.
+    /// With `-c` or a script file, load startup.wzsh before running
+    /// them as an interactive shell would
+    #[structopt(short = "i")]
+    interactive: bool,
+
     /// Instead of starting the interactive REPL, load script
     /// from file and execute it
     file: Option<PathBuf>,
+
+    /// Arguments passed to the script as the positional
+    /// parameters `$1`..`$N`
+    #[structopt(parse(from_os_str))]
+    args: Vec<OsString>,
+}
+
+/// The directories that hold the startup, profile and logout scripts
+struct ScriptRoots {
+    /// The directory of the system-wide scripts: `/etc/wzsh`, or on
+    /// Windows the directory that contains the shell executable
+    system: Option<PathBuf>,
+    /// The home directory of the user, if known
+    home: Option<PathBuf>,
+}
+
+impl ScriptRoots {
+    fn new(exe_dir: Option<&PathBuf>) -> Self {
+        let system = if cfg!(windows) {
+            exe_dir.cloned()
+        } else {
+            Some(PathBuf::from("/etc/wzsh"))
+        };
+        Self {
+            system,
+            home: dirs::home_dir(),
+        }
+    }
+}
+
+/// Returns the layers of scripts with the specified name, such as
+/// `startup`, in the order that they are to run.  The system-wide
+/// layer comes first, followed by the per-user layer, except for
+/// `logout` where that order is reversed.  Each layer lists
+/// candidate paths in order of preference.
+fn script_layers(name: &str, roots: &ScriptRoots) -> Vec<Vec<PathBuf>> {
+    let file_name = format!("{}.wzsh", name);
+    // Traditional dotfile names for the per-user scripts
+    let dotfile = match name {
+        "startup" => ".wzshrc".to_string(),
+        name => format!(".wzsh_{}", name),
+    };
+
+    let system_name = if cfg!(windows) { &dotfile } else { &file_name };
+    let system: Vec<PathBuf> = roots
+        .system
+        .iter()
+        .map(|dir| dir.join(system_name))
+        .collect();
+
+    let user = match &roots.home {
+        Some(home) => vec![
+            home.join(".config").join("wzsh").join(&file_name),
+            home.join(&dotfile),
+        ],
+        None => vec![],
+    };
+
+    if name == "logout" {
+        vec![user, system]
+    } else {
+        vec![system, user]
+    }
+}
+
+/// Run the first existing script from each of the layers of
+/// scripts with the specified name.  Errors are reported, but
+/// don't prevent the shell from continuing.  If one of the scripts
+/// uses `exit`, the remaining layers are skipped and the status that
+/// the shell should exit with is returned.
+fn run_script_layers(
+    name: &str,
+    roots: &ScriptRoots,
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+) -> Option<i32> {
+    for layer in script_layers(name, roots) {
+        if let Some(script) = layer.iter().find(|path| path.exists()) {
+            match script::compile_and_run_script_file(script, cwd, env, funcs) {
+                Ok(status) => {
+                    if env.take_exit_request().is_some() {
+                        return Some(exit_code(&status));
+                    }
+                }
+                Err(err) => {
+                    print_error_path(&err, script);
+                    eprintln!("wzsh: ignoring error during {} processing.", name);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Convert the final status of a program into a process exit code
+fn exit_code(status: &Status) -> i32 {
+    match status {
+        Status::Complete(Value::Integer(n)) => *n as i32,
+        _ => 0,
+    }
 }
 
-fn config_dir() -> PathBuf {
-    dirs::home_dir()
-        .expect("can't find HOME dir")
-        .join(".config")
-        .join("wzsh")
+/// Run the commands given by `-c`, returning the exit code
+fn run_command_string(
+    command: &str,
+    debug: bool,
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+) -> i32 {
+    let status = if debug {
+        script::compile_and_debug_script(command, "-c", cwd, env, funcs)
+    } else {
+        script::compile_and_run_script(command.as_bytes(), "-c", cwd, env, funcs)
+    };
+    match status {
+        Ok(status) => exit_code(&status),
+        Err(err) => {
+            print_error(&err, command);
+            1
+        }
+    }
+}
+
+/// Run the EXIT trap, if one has been set.  It is removed first,
+/// so that it runs only once
+fn run_exit_trap(cwd: &mut PathBuf, env: &mut Environment, funcs: &Arc<FunctionRegistry>) {
+    // Any request to exit has been honored by now; clear it so
+    // that it doesn't cut short the trap and the logout scripts
+    env.take_exit_request();
+    if let Some(action) = env.exit_trap().map(str::to_owned) {
+        env.set_exit_trap(None);
+        if let Err(err) = script::compile_and_run_script(action.as_bytes(), "trap", cwd, env, funcs)
+        {
+            print_error(&err, &action);
+        }
+        env.take_exit_request();
+    }
+}
+
+impl Opt {
+    fn is_login_shell(&self) -> bool {
+        self.login
+            || std::env::args_os()
+                .next()
+                .map(|argv0| argv0.to_string_lossy().starts_with('-'))
+                .unwrap_or(false)
+    }
+
+    /// Returns true if the shell reads commands from the REPL,
+    /// or -i was given
+    fn is_interactive(&self) -> bool {
+        self.interactive
+            || (self.command.is_none()
+                && self.script_file().is_none()
+                && !atty::isnt(atty::Stream::Stdin))
+    }
+
+    /// Returns true if startup.wzsh should be loaded.  As with bash,
+    /// that is skipped when running `-c` commands, a script file or
+    /// commands piped to stdin, unless -i is given or the shell is
+    /// a login shell
+    fn loads_startup(&self) -> bool {
+        !self.skip_startup && (self.is_interactive() || self.is_login_shell())
+    }
+
+    /// The script file to run, if any
+    fn script_file(&self) -> Option<&PathBuf> {
+        if self.command.is_some() || self.stdin {
+            None
+        } else {
+            self.file.as_ref()
+        }
+    }
+
+    /// The remaining arguments, including the file, which become
+    /// `$0` and the positional parameters
+    fn script_args(&self) -> Vec<Value> {
+        self.file
+            .iter()
+            .map(|file| file.as_os_str().to_os_string())
+            .chain(self.args.iter().cloned())
+            .map(Value::OsString)
+            .collect()
+    }
+}
+
+/// Parse and compile the selected commands without running them,
+/// exiting with a failure status if they are not valid.  The syntax
+/// tree and bytecode are printed if they were requested.
+fn check_syntax(opts: &Opt) -> anyhow::Result<()> {
+    let (input, file_name) = if let Some(command) = opts.command.as_ref() {
+        (command.clone(), "-c".to_string())
+    } else if let Some(file) = opts.script_file() {
+        let mut input = String::new();
+        if let Err(err) = std::fs::File::open(file).and_then(|mut f| f.read_to_string(&mut input)) {
+            eprintln!("wzsh: {}: {}", file.display(), err);
+            std::process::exit(1);
+        }
+        (input, file.to_string_lossy().into_owned())
+    } else {
+        let mut input = String::new();
+        std::io::stdin().lock().read_to_string(&mut input)?;
+        (input, "stdin".to_string())
+    };
+
+    let result = script::parse_script(input.as_bytes()).and_then(|command| {
+        if opts.dump_ast {
+            println!("{:#?}", command);
+        }
+        let program = script::compile_parsed_script(&command, &file_name)?;
+        if opts.dump_bytecode {
+            print!("{}", program.disassemble());
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        print_error(&err, &input);
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -42,6 +312,12 @@ fn main() -> anyhow::Result<()> {
     let mut env = Environment::new();
     let mut exe_dir = None;
 
+    // Keep PWD accurate from the outset, so that `cd -` and
+    // children that consult PWD see the correct values
+    env.set("PWD", &cwd);
+    env.export("PWD");
+    env.export("OLDPWD");
+
     // We want to pick up our shell utility executables.
     // In the source tree they are emitted alongside the wzsh
     // executable. In a deployed package they will also be
@@ -54,6 +330,7 @@ fn main() -> anyhow::Result<()> {
             // prefer to use these utilities over others that
             // might be in their path
             env.set("WZSH_BIN_DIR", bindir);
+            env.export("WZSH_BIN_DIR");
             env.append_path("PATH", bindir)?;
 
             exe_dir.replace(bindir.to_path_buf());
@@ -63,45 +340,338 @@ fn main() -> anyhow::Result<()> {
     let funcs = Arc::new(FunctionRegistry::new());
 
     let opts = Opt::from_args();
-    if !opts.skip_startup {
-        let startup_paths = &[
-            #[cfg(windows)]
-            exe_dir.as_ref().unwrap().join(".wzshrc"),
-            config_dir().join("startup.wzsh"),
-            dirs::home_dir().unwrap().join(".wzshrc"),
-        ];
-
-        for startup_script in startup_paths {
-            if startup_script.exists() {
-                if let Err(err) =
-                    script::compile_and_run_script_file(&startup_script, &mut cwd, &mut env, &funcs)
-                {
-                    print_error_path(&err, &startup_script);
-                    eprintln!("wzsh: ignoring error during startup processing.");
-                }
-                break;
-            }
-        }
+    if opts.no_exec || opts.dump_ast || opts.dump_bytecode {
+        return check_syntax(&opts);
+    }
+    if opts.debug && opts.command.is_none() && opts.script_file().is_none() {
+        eprintln!("wzsh: --debug requires a script file or -c");
+        std::process::exit(2);
     }
 
-    if let Some(file) = opts.file.as_ref() {
-        if let Err(err) = script::compile_and_run_script_file(file, &mut cwd, &mut env, &funcs) {
-            print_error_path(&err, file);
-            std::process::exit(1);
+    let roots = ScriptRoots::new(exe_dir.as_ref());
+    let login = opts.is_login_shell() && !opts.skip_startup;
+    let mut startup_exit = None;
+    if login {
+        startup_exit = run_script_layers("profile", &roots, &mut cwd, &mut env, &funcs);
+    }
+    if opts.loads_startup() && startup_exit.is_none() {
+        startup_exit = run_script_layers("startup", &roots, &mut cwd, &mut env, &funcs);
+    }
+
+    if opts.errexit {
+        env.set_option(ShellOption::ErrExit, true);
+    }
+    if opts.xtrace {
+        env.set_option(ShellOption::XTrace, true);
+    }
+
+    let code = if let Some(code) = startup_exit {
+        code
+    } else if let Some(command) = opts.command.as_ref() {
+        let args = opts.script_args();
+        if !args.is_empty() {
+            env.set_positional(args);
         }
-        Ok(())
-    } else if atty::isnt(atty::Stream::Stdin) {
+        run_command_string(command, opts.debug, &mut cwd, &mut env, &funcs)
+    } else if let Some(file) = opts.script_file() {
+        env.set_positional(opts.script_args());
+        let status = if opts.debug {
+            script::compile_and_debug_script_file(file, &mut cwd, &mut env, &funcs)
+        } else {
+            script::compile_and_run_script_file(file, &mut cwd, &mut env, &funcs)
+        };
+        match status {
+            Ok(status) => exit_code(&status),
+            Err(err) => {
+                print_error_path(&err, file);
+                1
+            }
+        }
+    } else if atty::isnt(atty::Stream::Stdin) && !opts.interactive {
+        env.set_positional_params(opts.script_args());
+
         let mut stdin = String::new();
         std::io::stdin().lock().read_to_string(&mut stdin)?;
 
-        if let Err(err) =
-            script::compile_and_run_script(stdin.as_bytes(), "stdin", &mut cwd, &mut env, &funcs)
+        match script::compile_and_run_script(stdin.as_bytes(), "stdin", &mut cwd, &mut env, &funcs)
         {
-            print_error(&err, &stdin);
-            std::process::exit(1);
+            Ok(status) => exit_code(&status),
+            Err(err) => {
+                print_error(&err, &stdin);
+                1
+            }
         }
-        Ok(())
     } else {
-        repl::repl(cwd, env, &funcs)
+        env.set_positional_params(opts.script_args());
+        exit_code(&repl::repl(&mut cwd, &mut env, &funcs)?)
+    };
+
+    run_exit_trap(&mut cwd, &mut env, &funcs);
+    if login {
+        run_script_layers("logout", &roots, &mut cwd, &mut env, &funcs);
+    }
+
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::scratch_dir;
+    use std::path::Path;
+
+    /// Run command as `wzsh -c` does, including the EXIT trap, and
+    /// return the exit code along with the resulting environment
+    fn run_c(command: &str) -> (i32, Environment) {
+        let mut cwd = std::env::current_dir().unwrap();
+        let mut env = Environment::new_empty();
+        let funcs = Arc::new(FunctionRegistry::new());
+        let code = run_command_string(command, false, &mut cwd, &mut env, &funcs);
+        run_exit_trap(&mut cwd, &mut env, &funcs);
+        (code, env)
+    }
+
+    fn is_set(env: &Environment, name: &str) -> bool {
+        env.get(name).is_some()
+    }
+
+    #[test]
+    fn command_string_exit_code() {
+        assert_eq!(run_c("true").0, 0);
+        assert_eq!(run_c("false").0, 1);
+        assert_eq!(run_c("false; exit").0, 1);
+        assert_eq!(run_c("exit -1").0, -1);
+        assert_eq!(run_c("exit abc").0, 2);
+
+        let (code, env) = run_c("exit 3; AFTER=1");
+        assert_eq!(code, 3);
+        assert!(!is_set(&env, "AFTER"));
+    }
+
+    #[test]
+    fn exit_from_nested_functions() {
+        let (code, env) = run_c(
+            "outer() { inner; OUTER=1; }\n\
+             inner() { exit 4; INNER=1; }\n\
+             outer\n\
+             AFTER=1",
+        );
+        assert_eq!(code, 4);
+        for name in &["OUTER", "INNER", "AFTER"] {
+            assert!(!is_set(&env, name), "{} is set", name);
+        }
+
+        let (code, _env) = run_c("fails() { false; exit; }\nfails");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn exit_with_prefix_assignment() {
+        let (code, env) = run_c("VAR=1 exit 5; AFTER=1");
+        assert_eq!(code, 5);
+        assert!(!is_set(&env, "AFTER"));
+
+        let (code, _env) = run_c("false; VAR=1 exit");
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn exit_trap() {
+        let (code, env) = run_c("trap 'TRAPPED=1' EXIT; exit 2; AFTER=1");
+        assert_eq!(code, 2);
+        assert!(is_set(&env, "TRAPPED"));
+        assert!(!is_set(&env, "AFTER"));
+
+        let (code, env) = run_c("trap 'TRAPPED=1' EXIT; false");
+        assert_eq!(code, 1);
+        assert!(is_set(&env, "TRAPPED"));
+
+        let (code, env) = run_c("trap 'TRAPPED=1' EXIT; trap - EXIT");
+        assert_eq!(code, 0);
+        assert!(!is_set(&env, "TRAPPED"));
+
+        // The trap runs only once, even if it exits itself
+        let (code, env) = run_c("trap 'TRAPPED=1; exit 7' 0");
+        assert_eq!(code, 0);
+        assert!(is_set(&env, "TRAPPED"));
+        assert_eq!(env.exit_trap(), None);
+    }
+
+    fn opts(args: &[&str]) -> Opt {
+        Opt::from_iter(std::iter::once("wzsh").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn startup_only_when_interactive() {
+        assert!(opts(&["-i"]).loads_startup());
+        assert!(!opts(&["-i", "--no-startup"]).loads_startup());
+        // Neither -c nor a script file loads startup.wzsh by default
+        assert!(!opts(&["-c", "true"]).loads_startup());
+        assert!(!opts(&["script.wzsh", "arg"]).loads_startup());
+        assert!(opts(&["-i", "-c", "true"]).loads_startup());
+        assert!(opts(&["-l", "script.wzsh"]).loads_startup());
+        assert!(!opts(&["-l", "--no-startup", "-c", "true"]).loads_startup());
+    }
+
+    /// Returns the keys and values of the array name
+    fn array(env: &Environment, name: &str) -> Vec<(String, String)> {
+        let array = env.get_array(name).expect("array is set");
+        array
+            .keys()
+            .into_iter()
+            .zip(array.values())
+            .map(|(key, value)| (key, value.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn declare_compound_assignment() {
+        let (code, env) = run_c("declare -A mm=([k]=v [j]=\"x y\")");
+        assert_eq!(code, 0);
+        assert!(env.get_array("mm").unwrap().is_associative());
+        assert_eq!(array(&env, "mm"), pairs(&[("j", "x y"), ("k", "v")]));
+
+        let (code, env) = run_c("declare -a a=(1 \"2 3\")");
+        assert_eq!(code, 0);
+        assert!(!env.get_array("a").unwrap().is_associative());
+        assert_eq!(array(&env, "a"), pairs(&[("0", "1"), ("1", "2 3")]));
+
+        // The elements are assigned before the array becomes readonly
+        let (code, env) = run_c("typeset -i n=(1+1) && readonly r=(x)");
+        assert_eq!(code, 0);
+        assert_eq!(array(&env, "n"), pairs(&[("0", "2")]));
+        assert_eq!(array(&env, "r"), pairs(&[("0", "x")]));
+        assert!(env.attributes("r").readonly);
+
+        let (code, env) = run_c("declare -A m=(v)");
+        assert_eq!(code, 1);
+        assert!(env.get_array("m").unwrap().values().is_empty());
+    }
+
+    /// Create scripts under root that each append their name to ORDER,
+    /// returning the roots that they belong to
+    fn make_scripts(root: &Path, scripts: &[&str]) -> anyhow::Result<ScriptRoots> {
+        for script in scripts {
+            let path = root.join(script);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, format!("ORDER=\"${{ORDER}}{} \"\n", script))?;
+        }
+        Ok(ScriptRoots {
+            system: Some(root.join("etc")),
+            home: Some(root.join("home")),
+        })
+    }
+
+    /// Run the named layers, returning the scripts that ran, in order
+    fn run_layers(name: &str, roots: &ScriptRoots) -> String {
+        let mut cwd = std::env::current_dir().unwrap();
+        let mut env = Environment::new_empty();
+        let funcs = Arc::new(FunctionRegistry::new());
+        assert_eq!(
+            run_script_layers(name, roots, &mut cwd, &mut env, &funcs),
+            None
+        );
+        env.get("ORDER")
+            .map(|order| order.to_string_lossy().trim_end().to_owned())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn script_layer_paths() {
+        let roots = ScriptRoots {
+            system: Some(PathBuf::from("/sys")),
+            home: Some(PathBuf::from("/home/me")),
+        };
+        let layers = |name| -> Vec<Vec<String>> {
+            script_layers(name, &roots)
+                .iter()
+                .map(|layer| layer.iter().map(|p| p.display().to_string()).collect())
+                .collect()
+        };
+        assert_eq!(
+            layers("startup"),
+            vec![
+                vec!["/sys/startup.wzsh"],
+                vec!["/home/me/.config/wzsh/startup.wzsh", "/home/me/.wzshrc"],
+            ]
+        );
+        assert_eq!(
+            layers("logout"),
+            vec![
+                vec!["/home/me/.config/wzsh/logout.wzsh", "/home/me/.wzsh_logout"],
+                vec!["/sys/logout.wzsh"],
+            ]
+        );
+
+        let roots = ScriptRoots {
+            system: None,
+            home: None,
+        };
+        assert_eq!(
+            script_layers("profile", &roots),
+            vec![vec![], vec![]] as Vec<Vec<PathBuf>>
+        );
+    }
+
+    #[test]
+    fn script_layers_stack() -> anyhow::Result<()> {
+        let root = scratch_dir("layers-stack")?;
+        let roots = make_scripts(
+            &root,
+            &[
+                "etc/profile.wzsh",
+                "home/.wzsh_profile",
+                "etc/logout.wzsh",
+                "home/.wzsh_logout",
+            ],
+        )?;
+        assert_eq!(
+            run_layers("profile", &roots),
+            "etc/profile.wzsh home/.wzsh_profile"
+        );
+        // The user layer is unwound before the system layer
+        assert_eq!(
+            run_layers("logout", &roots),
+            "home/.wzsh_logout etc/logout.wzsh"
+        );
+        // Either layer may be missing
+        assert_eq!(run_layers("startup", &roots), "");
+        std::fs::remove_file(root.join("etc/profile.wzsh"))?;
+        assert_eq!(run_layers("profile", &roots), "home/.wzsh_profile");
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn script_layers_first_existing() -> anyhow::Result<()> {
+        let root = scratch_dir("layers-first")?;
+        let roots = make_scripts(
+            &root,
+            &[
+                "etc/startup.wzsh",
+                "home/.config/wzsh/startup.wzsh",
+                "home/.wzshrc",
+            ],
+        )?;
+        assert_eq!(
+            run_layers("startup", &roots),
+            "etc/startup.wzsh home/.config/wzsh/startup.wzsh"
+        );
+        std::fs::remove_file(root.join("home/.config/wzsh/startup.wzsh"))?;
+        assert_eq!(
+            run_layers("startup", &roots),
+            "etc/startup.wzsh home/.wzshrc"
+        );
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
     }
 }
@@ -7,7 +7,7 @@ use filenamegen::Glob;
 use shell_compiler::Compiler;
 use shell_lexer::{LexError, LexErrorKind};
 use shell_parser::{ParseErrorKind, Parser};
-use shell_vm::{Environment, Machine, Program, Status};
+use shell_vm::{Environment, Machine, Status, Value};
 use std::path::PathBuf;
 use std::sync::Arc;
 use termwiz::cell::AttributeChange;
@@ -26,6 +26,7 @@ fn is_recoverable_parse_error(e: &Error) -> bool {
             | LexErrorKind::EofDuringDoubleQuotedString
             | LexErrorKind::EofDuringAssignmentWord
             | LexErrorKind::EofDuringCommandSubstitution
+            | LexErrorKind::EofDuringCompoundAssignment
             | LexErrorKind::EofDuringParameterExpansion => true,
             LexErrorKind::IoError => false,
         }
@@ -93,17 +94,20 @@ fn compile_and_run(prog: &str, env_bits: &mut EnvBits) -> anyhow::Result<Status>
     let mut compiler = Compiler::new();
     compiler.compile_command(&command)?;
     let prog = compiler.finish()?;
-    let mut machine = Machine::new(
-        &Program::new(prog),
-        Some(env_bits.env.clone()),
-        &env_bits.cwd,
-    )?;
+    let mut machine = Machine::new(&prog, Some(env_bits.env.clone()), &env_bits.cwd)?;
     machine.set_host(Arc::new(Host::with_job_control(job, &env_bits.funcs)));
     let status = machine.run();
 
     let (cwd, env) = machine.top_environment();
     env_bits.cwd = cwd;
     env_bits.env = env;
+    // The next line runs in a new machine, which needs to be told
+    // the status for `exit` to use
+    match &status {
+        Ok(Status::Complete(Value::Integer(n))) => env_bits.env.set_last_status(*n),
+        Err(_) => env_bits.env.set_last_status(1),
+        _ => {}
+    }
 
     status
 }
@@ -206,21 +210,42 @@ fn word_at_cursor(line: &str, cursor_position: usize) -> Option<(std::ops::Range
     }
 }
 
-pub fn repl(cwd: PathBuf, env: Environment, funcs: &Arc<FunctionRegistry>) -> anyhow::Result<()> {
-    let mut env = EnvBits {
-        cwd,
-        env,
+/// Run the interactive REPL until `exit` is used, returning the status
+/// that the shell should exit with.  When it finishes, cwd and env hold
+/// the final state of the shell, for use by the logout processing.
+pub fn repl(
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+) -> anyhow::Result<Status> {
+    let mut env_bits = EnvBits {
+        cwd: cwd.clone(),
+        env: env.clone(),
         funcs: Arc::clone(funcs),
     };
+    let result = run_repl(&mut env_bits);
+    *cwd = env_bits.cwd;
+    *env = env_bits.env;
+    result
+}
 
+fn run_repl(env: &mut EnvBits) -> anyhow::Result<Status> {
+    // When forced to be interactive by `-i`, stdin may not be a
+    // terminal that we can take control of
     #[cfg(unix)]
-    init_job_control()?;
+    {
+        if atty::is(atty::Stream::Stdin) {
+            init_job_control()?;
+        }
+    }
 
     let mut terminal = line_editor_terminal()?;
     let mut editor = LineEditor::new(&mut terminal);
     let mut host = EditHost::new();
 
     let mut input = String::new();
+    let mut last_status = Status::Complete(0.into());
+    let mut warned_stopped_jobs = false;
 
     loop {
         // We handle all the prompt rendering in render_prompt.
@@ -237,7 +262,7 @@ pub fn repl(cwd: PathBuf, env: Environment, funcs: &Arc<FunctionRegistry>) -> an
 
                 input.push_str(&line);
 
-                let _status = match compile_and_run(&input, &mut env) {
+                let status = match compile_and_run(&input, env) {
                     Err(e) => {
                         if !is_recoverable_parse_error(&e) {
                             print_error(&e, &input);
@@ -254,6 +279,20 @@ pub fn repl(cwd: PathBuf, env: Environment, funcs: &Arc<FunctionRegistry>) -> an
                 };
 
                 put_shell_in_foreground();
+
+                if env.env.exit_request().is_some() {
+                    // As in bash, exiting with stopped jobs requires
+                    // asking twice in a row
+                    if !warned_stopped_jobs && JOB_LIST.has_stopped_jobs() {
+                        eprintln!("wzsh: there are stopped jobs.");
+                        env.env.take_exit_request();
+                        warned_stopped_jobs = true;
+                        continue;
+                    }
+                    return Ok(status);
+                }
+                warned_stopped_jobs = false;
+                last_status = status;
             }
             Ok(None) => {
                 input.clear();
@@ -266,5 +305,32 @@ pub fn repl(cwd: PathBuf, env: Environment, funcs: &Arc<FunctionRegistry>) -> an
         }
     }
 
-    Ok(())
+    Ok(last_status)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exit_uses_status_of_previous_line() -> anyhow::Result<()> {
+        let mut env_bits = EnvBits {
+            cwd: std::env::current_dir()?,
+            env: Environment::new_empty(),
+            funcs: Arc::new(FunctionRegistry::new()),
+        };
+        compile_and_run("false", &mut env_bits)?;
+        assert_eq!(
+            compile_and_run("exit", &mut env_bits)?,
+            Status::Complete(1.into())
+        );
+
+        env_bits.env.take_exit_request();
+        compile_and_run("true", &mut env_bits)?;
+        assert_eq!(
+            compile_and_run("exit", &mut env_bits)?,
+            Status::Complete(0.into())
+        );
+        Ok(())
+    }
 }
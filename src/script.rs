@@ -1,11 +1,31 @@
+use crate::debugger::Debugger;
 use crate::job::Job;
 use crate::shellhost::{FunctionRegistry, Host};
 use shell_compiler::Compiler;
-use shell_parser::Parser;
+use shell_parser::{Command, Parser};
 use shell_vm::{Environment, Machine, Program, Status};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// Parse a script into its syntax tree
+pub fn parse_script<R: std::io::Read>(file: R) -> anyhow::Result<Command> {
+    let mut parser = Parser::new(file);
+    parser.parse()
+}
+
+/// Compile the syntax tree of a script that was read from file_name
+pub fn compile_parsed_script(command: &Command, file_name: &str) -> anyhow::Result<Arc<Program>> {
+    let mut compiler = Compiler::new();
+    compiler.set_source_name(file_name);
+    compiler.compile_command(command)?;
+    compiler.finish()
+}
+
+/// Parse and compile a script without running it
+pub fn compile_script<R: std::io::Read>(file: R, file_name: &str) -> anyhow::Result<Arc<Program>> {
+    compile_parsed_script(&parse_script(file)?, file_name)
+}
+
 pub fn compile_and_run_script<R: std::io::Read>(
     file: R,
     file_name: &str,
@@ -13,17 +33,44 @@ pub fn compile_and_run_script<R: std::io::Read>(
     env: &mut Environment,
     funcs: &Arc<FunctionRegistry>,
 ) -> anyhow::Result<Status> {
-    let job = Job::new_empty(file_name.to_string());
-    let mut parser = Parser::new(file);
+    let prog = compile_script(file, file_name)?;
+    run_program(&prog, file_name, cwd, env, funcs, Machine::run)
+}
 
-    let command = parser.parse()?;
-    let mut compiler = Compiler::new();
-    compiler.compile_command(&command)?;
-    let prog = compiler.finish()?;
+/// Like compile_and_run_script, but the program is run under the
+/// control of the interactive debugger
+pub fn compile_and_debug_script(
+    input: &str,
+    file_name: &str,
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+) -> anyhow::Result<Status> {
+    let prog = compile_script(input.as_bytes(), file_name)?;
+    let mut debugger = Debugger::new(file_name, input);
+    run_program(&prog, file_name, cwd, env, funcs, |machine| {
+        debugger.run(machine)
+    })
+}
 
-    let mut machine = Machine::new(&Program::new(prog), Some(env.clone()), &cwd)?;
+/// Run a compiled program, with run driving the machine to completion.
+/// The environment and working directory that the program leaves
+/// behind are copied back into env and cwd.
+fn run_program<F>(
+    prog: &Arc<Program>,
+    file_name: &str,
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+    run: F,
+) -> anyhow::Result<Status>
+where
+    F: FnOnce(&mut Machine) -> anyhow::Result<Status>,
+{
+    let job = Job::new_empty(file_name.to_string());
+    let mut machine = Machine::new(prog, Some(env.clone()), &cwd)?;
     machine.set_host(Arc::new(Host::new(job, funcs)));
-    let status = machine.run();
+    let status = run(&mut machine);
 
     let (new_cwd, new_env) = machine.top_environment();
     *cwd = new_cwd;
@@ -42,3 +89,14 @@ pub fn compile_and_run_script_file(
     let file = std::fs::File::open(path)?;
     compile_and_run_script(file, &file_name, cwd, env, funcs)
 }
+
+pub fn compile_and_debug_script_file(
+    path: &Path,
+    cwd: &mut PathBuf,
+    env: &mut Environment,
+    funcs: &Arc<FunctionRegistry>,
+) -> anyhow::Result<Status> {
+    let file_name = path.to_string_lossy();
+    let input = std::fs::read_to_string(path)?;
+    compile_and_debug_script(&input, &file_name, cwd, env, funcs)
+}
@@ -7,17 +7,20 @@ use anyhow::{anyhow, bail, Context};
 use cancel::Token;
 use pathsearch::PathSearcher;
 use shell_vm::{
-    Environment, IoEnvironment, Machine, Program, ShellHost, Status, Value, WaitableStatus,
+    CpuTimes, Environment, IoEnvironment, Machine, Program, RuntimeError, ShellHost, ShellOption,
+    SourceLocation, Status, Value, WaitForStatus, WaitableStatus,
 };
 use std::collections::HashMap;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct FunctionRegistry {
-    functions: Mutex<HashMap<String, Arc<Program>>>,
+    functions: Mutex<HashMap<String, (Arc<Program>, SourceLocation)>>,
 }
 
 impl FunctionRegistry {
@@ -27,14 +30,20 @@ impl FunctionRegistry {
         }
     }
 
-    pub fn define_function(&self, name: &str, program: &Arc<Program>) {
+    pub fn define_function(&self, name: &str, program: &Arc<Program>, location: &SourceLocation) {
         let mut funcs = self.functions.lock().unwrap();
-        funcs.insert(name.to_owned(), Arc::clone(program));
+        funcs.insert(name.to_owned(), (Arc::clone(program), location.clone()));
     }
 
     pub fn lookup_function(&self, name: &str) -> Option<Arc<Program>> {
         let funcs = self.functions.lock().unwrap();
-        funcs.get(name).map(Arc::clone)
+        funcs.get(name).map(|(program, _)| Arc::clone(program))
+    }
+
+    /// Returns the location of the definition of the function name
+    pub fn function_location(&self, name: &str) -> Option<SourceLocation> {
+        let funcs = self.functions.lock().unwrap();
+        funcs.get(name).map(|(_, location)| location.clone())
     }
 }
 
@@ -63,6 +72,96 @@ impl Host {
     }
 }
 
+/// The status of a command that is running on a worker thread
+#[derive(Debug)]
+struct ThreadStatus {
+    handle: Mutex<Option<JoinHandle<Status>>>,
+    status: Mutex<Option<Status>>,
+}
+
+impl ThreadStatus {
+    fn new(handle: JoinHandle<Status>) -> Self {
+        Self {
+            handle: Mutex::new(Some(handle)),
+            status: Mutex::new(None),
+        }
+    }
+}
+
+impl WaitForStatus for ThreadStatus {
+    fn poll(&self) -> Option<Status> {
+        let finished = match self.handle.lock().unwrap().as_ref() {
+            Some(handle) => handle.is_finished(),
+            None => true,
+        };
+        if finished {
+            self.wait()
+        } else {
+            Some(Status::Running)
+        }
+    }
+
+    fn wait(&self) -> Option<Status> {
+        // The handle lock is held while joining, so that concurrent
+        // waiters don't observe the status before it has been set
+        let mut handle = self.handle.lock().unwrap();
+        if let Some(handle) = handle.take() {
+            let status = handle.join().unwrap_or_else(|_| Status::Complete(1.into()));
+            self.status.lock().unwrap().replace(status);
+        }
+        self.status.lock().unwrap().clone()
+    }
+}
+
+impl ThreadStatus {
+    /// Run func on a worker thread as a stage of a pipeline.  An error
+    /// is reported on the stderr of io_env, prefixed by name if there
+    /// is one, except for a broken pipe: that only means that a later
+    /// stage has stopped reading, so the stage fails quietly.
+    fn spawn_stage<F>(name: Option<String>, io_env: IoEnvironment, func: F) -> WaitableStatus
+    where
+        F: FnOnce(&IoEnvironment) -> anyhow::Result<Status> + Send + 'static,
+    {
+        let handle = std::thread::spawn(move || match func(&io_env) {
+            Ok(status) => status,
+            Err(err) => {
+                if !is_broken_pipe(&err) {
+                    let mut stderr = io_env.stderr();
+                    match name {
+                        Some(name) => writeln!(stderr, "wzsh: {}: {:#}", name, err).ok(),
+                        None => writeln!(stderr, "wzsh: {:#}", err).ok(),
+                    };
+                }
+                Status::Complete(1.into())
+            }
+        });
+        WaitableStatus::new(Arc::new(Self::new(handle)))
+    }
+}
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<RuntimeError>() {
+        return is_broken_pipe(&err.error);
+    }
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .map(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+            .unwrap_or(false)
+    })
+}
+
+impl Host {
+    /// A host for a pipeline stage that runs on a worker thread
+    fn worker_host(&self) -> Self {
+        Self {
+            job: Mutex::new(self.job.lock().unwrap().clone()),
+            job_control_enabled: self.job_control_enabled,
+            funcs: Arc::clone(&self.funcs),
+        }
+    }
+}
+
 impl ShellHost for Host {
     fn lookup_homedir(&self, user: Option<&str>) -> anyhow::Result<OsString> {
         if user.is_none() {
@@ -92,7 +191,14 @@ impl ShellHost for Host {
             return Ok(Status::Complete(0.into()).into());
         }
 
-        let (search_builtin, search_path, argv) = if argv[0].as_str() == Some("command") {
+        // `command -v` and `command -V` are queries that are answered
+        // by the command builtin, rather than requests to run a command
+        let command_query = argv[0].as_str() == Some("command")
+            && matches!(argv.get(1).and_then(Value::as_str), Some("-v") | Some("-V"));
+
+        let (search_builtin, search_path, argv) = if command_query {
+            (true, false, &argv[..])
+        } else if argv[0].as_str() == Some("command") {
             (false, true, &argv[1..])
         } else if argv[0].as_str() == Some("builtin") {
             (true, false, &argv[1..])
@@ -102,17 +208,22 @@ impl ShellHost for Host {
 
         if let Some(name) = argv[0].as_str() {
             if let Some(prog) = self.funcs.lookup_function(name) {
-                // Execute the function.
+                // Functions are usually called by the machine itself;
+                // we get here for `command name` and for pipeline
+                // stages, which run the function in a machine of its own.
                 let job = Job::new_empty(name.to_string());
                 let mut machine =
                     Machine::new(&prog, Some(environment.clone()), &current_directory)?;
                 machine.set_host(Arc::new(Host::with_job_control(job, &self.funcs)));
+                machine.set_io_env(io_env.clone());
 
                 machine.set_positional(argv.to_vec());
 
                 let status = machine.run();
 
-                let (new_cwd, new_env) = machine.top_environment();
+                let (new_cwd, mut new_env) = machine.top_environment();
+                // The positional parameters are local to the function call
+                new_env.set_positional(environment.positional().to_vec());
                 *current_directory = new_cwd;
                 *environment = new_env;
 
@@ -140,15 +251,12 @@ impl ShellHost for Host {
         }
 
         if search_path {
-            if let Some(exe) = PathSearcher::new(
+            if let Some(exe) = find_executable(
                 argv[0]
                     .as_os_str()
                     .ok_or_else(|| anyhow!("argv0 is not convertible to OsStr"))?,
-                environment.get("PATH"),
-                environment.get("PATHEXT"),
-            )
-            .next()
-            {
+                environment,
+            ) {
                 let mut child_cmd = std::process::Command::new(&exe);
                 for (i, arg) in argv.iter().enumerate().skip(1) {
                     child_cmd.arg(
@@ -157,12 +265,21 @@ impl ShellHost for Host {
                     );
                 }
                 child_cmd.env_clear();
-                child_cmd.envs(environment.iter());
+                child_cmd.envs(environment.iter_exported());
                 child_cmd.current_dir(&current_directory);
 
-                child_cmd.stdin(io_env.fd_as_stdio(0)?);
-                child_cmd.stdout(io_env.fd_as_stdio(1)?);
-                child_cmd.stderr(io_env.fd_as_stdio(2)?);
+                // A closed stdio descriptor is closed again in the
+                // child, after std has set it up as /dev/null
+                let stdio = |fd_number| {
+                    if io_env.is_open(fd_number) {
+                        io_env.fd_as_stdio(fd_number)
+                    } else {
+                        Ok(std::process::Stdio::null())
+                    }
+                };
+                child_cmd.stdin(stdio(0)?);
+                child_cmd.stdout(stdio(1)?);
+                child_cmd.stderr(stdio(2)?);
 
                 let process_group_id = self.job.lock().unwrap().process_group_id();
 
@@ -170,7 +287,16 @@ impl ShellHost for Host {
                 unsafe {
                     use std::os::unix::process::CommandExt;
                     let job_control = self.job_control_enabled;
+                    let extra_fds = io_env.extra_fds();
+                    let closed_stdio: Vec<libc::c_int> =
+                        (0..=2).filter(|fd| !io_env.is_open(*fd as usize)).collect();
+                    // Allocated up front; we must not allocate in the child
+                    let mut temp_fds = Vec::with_capacity(extra_fds.len());
                     child_cmd.pre_exec(move || {
+                        map_extra_fds(&extra_fds, &mut temp_fds)?;
+                        for fd in &closed_stdio {
+                            libc::close(*fd);
+                        }
                         let pid = libc::getpid();
                         if job_control {
                             if process_group_id == 0 {
@@ -236,6 +362,23 @@ impl ShellHost for Host {
             }
         }
 
+        if environment.is_option_set(ShellOption::AutoCd) {
+            if let Some(dir) = argv[0].as_os_str() {
+                if current_directory.join(dir).is_dir() {
+                    let cd = lookup_builtin(&Value::String("cd".to_owned()))
+                        .ok_or_else(|| anyhow!("cd builtin is missing"))?;
+                    return cd(
+                        &[Value::String("cd".to_owned()), argv[0].clone()],
+                        environment,
+                        current_directory,
+                        io_env,
+                        Arc::new(Token::new()),
+                        &self.funcs,
+                    );
+                }
+            }
+        }
+
         if let Some(s) = argv[0].as_str() {
             writeln!(io_env.stderr(), "wzsh: {} not found", s)?;
         } else {
@@ -244,8 +387,201 @@ impl ShellHost for Host {
         Ok(Status::Complete(127.into()).into())
     }
 
-    fn define_function(&self, name: &str, program: &Arc<Program>) -> anyhow::Result<()> {
-        self.funcs.define_function(name, program);
+    fn spawn_concurrent_command(
+        &self,
+        argv: &Vec<Value>,
+        environment: &Environment,
+        current_directory: &Path,
+        io_env: &IoEnvironment,
+    ) -> anyhow::Result<WaitableStatus> {
+        let runs_inline = match argv.first().and_then(Value::as_str) {
+            Some(name) => {
+                self.funcs.lookup_function(name).is_some() || lookup_builtin(&argv[0]).is_some()
+            }
+            None => false,
+        };
+        let mut environment = environment.clone();
+        let mut current_directory = current_directory.to_path_buf();
+        if !runs_inline {
+            return self.spawn_command(argv, &mut environment, &mut current_directory, io_env);
+        }
+
+        // Functions and builtins would otherwise run to completion
+        // before the next stage of the pipeline is spawned, blocking
+        // forever once the pipe between them is full
+        let host = self.worker_host();
+        let argv = argv.clone();
+        let name = argv[0].as_str().unwrap_or_default().to_owned();
+        Ok(ThreadStatus::spawn_stage(
+            Some(name),
+            io_env.clone(),
+            move |io_env| {
+                let status =
+                    host.spawn_command(&argv, &mut environment, &mut current_directory, io_env)?;
+                loop {
+                    match status.wait() {
+                        None | Some(Status::Running) => continue,
+                        Some(status) => return Ok(status),
+                    }
+                }
+            },
+        ))
+    }
+
+    fn spawn_concurrent_program(
+        &self,
+        program: &Arc<Program>,
+        environment: &Environment,
+        current_directory: &Path,
+        io_env: &IoEnvironment,
+    ) -> anyhow::Result<WaitableStatus> {
+        let host = self.worker_host();
+        let program = Arc::clone(program);
+        let environment = environment.clone();
+        let current_directory = current_directory.to_path_buf();
+        Ok(ThreadStatus::spawn_stage(
+            None,
+            io_env.clone(),
+            move |io_env| {
+                let mut machine = Machine::new(&program, Some(environment), &current_directory)?;
+                machine.set_host(Arc::new(host));
+                machine.set_io_env(io_env.clone());
+                machine.run()
+            },
+        ))
+    }
+
+    fn lookup_function(&self, name: &str) -> Option<Arc<Program>> {
+        self.funcs.lookup_function(name)
+    }
+
+    fn define_function(
+        &self,
+        name: &str,
+        program: &Arc<Program>,
+        location: &SourceLocation,
+    ) -> anyhow::Result<()> {
+        self.funcs.define_function(name, program, location);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn cpu_times(&self) -> anyhow::Result<CpuTimes> {
+        let mut times = CpuTimes::default();
+        for who in &[libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            if unsafe { libc::getrusage(*who, &mut usage) } != 0 {
+                return Err(std::io::Error::last_os_error()).context("getrusage");
+            }
+            times.user += timeval_to_duration(&usage.ru_utime);
+            times.system += timeval_to_duration(&usage.ru_stime);
+        }
+        Ok(times)
+    }
+}
+
+/// Resolve name to an executable, consulting the locations remembered
+/// by previous searches before searching PATH.  A remembered location
+/// that no longer exists is forgotten and searched for afresh.
+fn find_executable(name: &OsStr, environment: &mut Environment) -> Option<PathBuf> {
+    // Names that include a directory aren't searched for in PATH,
+    // and so are not remembered either
+    let key = name
+        .to_str()
+        .filter(|name| !name.contains(std::path::is_separator));
+
+    if let Some(key) = key {
+        let mut hash = environment.command_hash();
+        if let Some(path) = hash.lookup(key) {
+            if path.is_file() {
+                return Some(path);
+            }
+            hash.remove(key);
+        }
+    }
+
+    let exe =
+        PathSearcher::new(name, environment.get("PATH"), environment.get("PATHEXT")).next()?;
+    if let Some(key) = key {
+        let mut hash = environment.command_hash();
+        hash.insert(key, exe.clone());
+        // Count this as the first use of the command
+        hash.lookup(key);
+    }
+    Some(exe)
+}
+
+#[cfg(unix)]
+fn timeval_to_duration(tv: &libc::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
+
+/// Arrange for the descriptors in fds to be present in the child
+/// process with the descriptor numbers that the IO environment
+/// assigned to them.  This runs in the child between fork and exec.
+/// The sources are first duplicated above the highest target number,
+/// so that assigning one target cannot clobber the source of another.
+#[cfg(unix)]
+fn map_extra_fds(
+    fds: &[(usize, std::os::unix::io::RawFd)],
+    temp_fds: &mut Vec<std::os::unix::io::RawFd>,
+) -> std::io::Result<()> {
+    let base = match fds.iter().map(|(fd_number, _)| *fd_number).max() {
+        Some(highest) => highest as libc::c_int + 1,
+        None => return Ok(()),
+    };
+    for (_, fd) in fds {
+        let temp = unsafe { libc::fcntl(*fd, libc::F_DUPFD, base) };
+        if temp == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        temp_fds.push(temp);
+    }
+    for ((fd_number, _), temp) in fds.iter().zip(temp_fds.iter()) {
+        if unsafe { libc::dup2(*temp, *fd_number as libc::c_int) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        unsafe { libc::close(*temp) };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builtins::test::scratch_dir;
+    use crate::script::compile_and_run_script;
+
+    #[test]
+    fn autocd() -> anyhow::Result<()> {
+        let root = scratch_dir("autocd")?;
+        std::fs::create_dir_all(root.join("sub/inner"))?;
+        let funcs = Arc::new(FunctionRegistry::new());
+        let mut env = Environment::new_empty();
+        let mut cwd = root.clone();
+        let mut run = |script: &str, cwd: &mut PathBuf| {
+            compile_and_run_script(script.as_bytes(), "test", cwd, &mut env, &funcs)
+        };
+
+        // Without the option, a directory isn't a command
+        assert_eq!(run("sub", &mut cwd)?, Status::Complete(127.into()));
+        assert_eq!(cwd, root);
+
+        assert_eq!(
+            run("set -o autocd; sub; inner", &mut cwd)?,
+            Status::Complete(0.into())
+        );
+        assert_eq!(cwd, root.join("sub/inner"));
+        assert_eq!(run("..", &mut cwd)?, Status::Complete(0.into()));
+        assert_eq!(cwd, root.join("sub"));
+
+        assert_eq!(
+            run("set +o autocd; inner", &mut cwd)?,
+            Status::Complete(127.into())
+        );
+        assert_eq!(cwd, root.join("sub"));
+
+        std::fs::remove_dir_all(&root)?;
         Ok(())
     }
 }